@@ -1,18 +1,23 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::{SystemTime, UNIX_EPOCH}};
 
+use glam::{IVec3, UVec3, Vec2, Vec3};
 use winit::{
     event::*, event_loop::EventLoop, keyboard::{Key, KeyCode, NamedKey}, window::{Window, WindowBuilder}
 };
 
-use crate::{editor::Editor, egui_renderer::EguiRenderer, game::Game, renderer_context::{RendererContext, Resolution}, system::System, time_step::TimeStep};
+use crate::{editor::{Editor, HistoryAction, NewEntity, SceneAction}, egui_renderer::EguiRenderer, game::Game, profiler::Profiler, renderer_context::{RendererContext, RendererContextError, Resolution}, scene::{EntityId, EntityKind}, sdf::{Primitive, SdfScene, Shape}, sprite::{Sprite, SpriteParams, SPHERE_SHADER}, system::System, terrain::TerrainParams, transform::Transform, time_step::TimeStep, vox::{VoxError, VoxScene}, voxel_world::{Region, WorldRepresentation}, world_file::WorldFileError};
 
 const INITIAL_WIDTH: u32 = 1920;
 const INITIAL_HEIGHT: u32 = 1080;
+const SCREENSHOTS_DIR: &str = "screenshots";
+const EXPORTS_DIR: &str = "exports";
+const QUICK_SAVE_PATH: &str = "saves/quicksave.octw";
 
 pub struct App {
     pub window: Arc<Window>,
     pub game: Game,
     pub time_step: TimeStep,
+    pub profiler: Profiler,
     pub editor: Editor,
     pub show_editor: bool,
 }
@@ -26,6 +31,7 @@ impl App {
             window,
             game,
             time_step: TimeStep::new(),
+            profiler: Profiler::new(),
             editor: Editor::new(),
             show_editor: true,
         }
@@ -55,7 +61,7 @@ impl App {
                 event 
             } if window_id == window.id() => {
                 window.request_redraw();
-                egui_renderer.handle_input(&window, &event);
+                let consumed = egui_renderer.handle_input(&window, &event);
                 match event {
                     WindowEvent::Resized(physical_size) => {
                         renderer.resize(
@@ -65,15 +71,24 @@ impl App {
                             }
                         );
                     },
-                    WindowEvent::CloseRequested
-                    | WindowEvent::KeyboardInput {
+                    WindowEvent::CloseRequested => {
+                        app.editor.save_settings();
+                        ewlt.exit();
+                    }
+                    // keys typed in the editor text fields, Escape included, don't reach the game
+                    WindowEvent::KeyboardInput { event: KeyEvent { state: ElementState::Pressed, .. }, .. } if consumed => {}
+                    WindowEvent::KeyboardInput {
                         event:
                             KeyEvent {
                                 logical_key: Key::Named(NamedKey::Escape),
+                                state: ElementState::Pressed,
                                 ..
                             },
                         ..
-                    } => ewlt.exit(),
+                    } => {
+                        app.editor.save_settings();
+                        ewlt.exit();
+                    }
                     WindowEvent::KeyboardInput { device_id, event, is_synthetic } => match event.state {
                         ElementState::Pressed => {
                             match event.physical_key {
@@ -81,6 +96,32 @@ impl App {
                                     if key == KeyCode::F1 {
                                         app.show_editor = !app.show_editor;
                                     }
+                                    if key == KeyCode::F2 {
+                                        let representation = match app.game.world().representation() {
+                                            WorldRepresentation::Grid => WorldRepresentation::Octree,
+                                            WorldRepresentation::Octree => WorldRepresentation::Brickmap,
+                                            WorldRepresentation::Brickmap => WorldRepresentation::Grid,
+                                        };
+                                        app.game.set_world_representation(&mut renderer, representation);
+                                    }
+                                    if key == KeyCode::F3 {
+                                        app.export_vox();
+                                    }
+                                    if key == KeyCode::F5 {
+                                        app.quick_save();
+                                    }
+                                    if key == KeyCode::F9 {
+                                        app.quick_load();
+                                    }
+                                    if key == KeyCode::F6 {
+                                        app.generate_terrain(&mut renderer);
+                                    }
+                                    if key == KeyCode::F7 {
+                                        app.voxelize_shapes(&mut renderer);
+                                    }
+                                    if key == KeyCode::F12 {
+                                        app.take_screenshot(&renderer);
+                                    }
                                     app.game.on_key_down(key);
                                 },
                                 winit::keyboard::PhysicalKey::Unidentified(_) => todo!(),
@@ -104,12 +145,19 @@ impl App {
                             app.game.on_mouse_button_up(button);
                         },
                     },
+                    WindowEvent::DroppedFile(path) if path.extension().is_some_and(|extension| extension == "vox") => {
+                        app.import_vox(&path);
+                    },
                     WindowEvent::ScaleFactorChanged { scale_factor, inner_size_writer } => {
                         //game.resize(&mut renderer, new_inner_size.width, new_inner_size.height);
                     },
                     WindowEvent::RedrawRequested => {
+                        app.profiler.new_frame();
+                        app.profiler.begin_scope("update");
                         app.game.hot_reload(&mut renderer);
                         app.game.update(app.time_step.tick());
+                        app.apply_scene_actions(&mut renderer);
+                        app.profiler.end_scope();
 
                         app.game.resize(
                             &mut renderer, 
@@ -131,11 +179,17 @@ impl App {
                             }
                         );
 
+                        app.profiler.begin_scope("prepare_rendering");
                         app.game.prepare_rendering(&mut renderer);
+                        app.profiler.end_scope();
 
+                        renderer.set_gpu_timing(app.profiler.gpu_timing);
                         if let Some(mut frame) = renderer.begin_frame() {
+                            app.profiler.begin_scope("render");
                             app.game.render(&mut frame);
+                            app.profiler.end_scope();
                             if app.show_editor {
+                                app.profiler.begin_scope("egui");
                                 let game_texture = egui_renderer.register_native_texture(&renderer, app.game.game_texture());
                                 egui_renderer.render(
                                     &renderer,
@@ -143,8 +197,10 @@ impl App {
                                     &window,
                                     |ui| app.run_ui(ui, &renderer, game_texture)
                                 );
+                                app.profiler.end_scope();
                             }
                             renderer.commit_frame(frame);
+                            app.profiler.set_gpu_passes(renderer.gpu_timing_report().map(|report| report.passes).unwrap_or_default());
                         }
                     },
                     _ => {}
@@ -167,7 +223,222 @@ impl App {
         });
     }
 
+    /// Runs the game for a number of frames without window, rendering into an offscreen texture
+    pub async fn run_headless(resolution: Resolution, frame_count: u32) -> Result<(), RendererContextError> {
+        let mut renderer = RendererContext::new_headless(resolution).await?;
+
+        let mut game = Game::new(&mut renderer);
+        game.init(&mut renderer);
+        game.resize(&mut renderer, resolution);
+
+        let mut time_step = TimeStep::new();
+        for _ in 0..frame_count {
+            game.update(time_step.tick());
+            game.prepare_rendering(&mut renderer);
+
+            if let Some(mut frame) = renderer.begin_frame() {
+                game.render(&mut frame);
+                renderer.commit_frame(frame);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Saves the game view into the screenshots folder
+    pub fn take_screenshot(&self, renderer: &RendererContext) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_millis())
+            .unwrap_or_default();
+        let path = format!("{}/screenshot_{}.png", SCREENSHOTS_DIR, timestamp);
+
+        match renderer.read_texture(self.game.game_texture()) {
+            Ok(image) => match image.save_png(&path) {
+                Ok(()) => log::info!("Screenshot saved to {}", path),
+                Err(e) => log::error!("{}", e),
+            },
+            Err(e) => log::error!("{}", e),
+        }
+    }
+
+    /// Adds the models of a MagicaVoxel file to the world
+    pub fn import_vox(&mut self, path: &std::path::Path) {
+        match VoxScene::load(path) {
+            Ok(scene) => {
+                let bounds = Region::bounding(scene.voxels().map(|(coord, _)| coord));
+                // without voxels, only the palette is recorded
+                let region = bounds.unwrap_or(Region::new(IVec3::ZERO, IVec3::ZERO));
+                self.game.record_edit("Import", region, |world| scene.fill_world(world, IVec3::ZERO));
+                if let Some(region) = bounds {
+                    let name = path.file_stem().map_or("Model".into(), |stem| stem.to_string_lossy());
+                    self.game.scene_mut().add(name, EntityKind::Model { region }, Transform::from_position(region.min.as_vec3()), None);
+                }
+                log::info!("Imported {}", path.display());
+            }
+            Err(e) => log::error!("{}", e),
+        }
+    }
+
+    pub fn export_vox(&self) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_millis())
+            .unwrap_or_default();
+        let path = format!("{}/world_{}.vox", EXPORTS_DIR, timestamp);
+
+        let result = std::fs::create_dir_all(EXPORTS_DIR)
+            .map_err(VoxError::from)
+            .and_then(|_| VoxScene::from_world(self.game.world()))
+            .and_then(|scene| scene.save(&path));
+        match result {
+            Ok(()) => log::info!("World exported to {}", path),
+            Err(e) => log::error!("{}", e),
+        }
+    }
+
+    pub fn quick_save(&self) {
+        let path = std::path::Path::new(QUICK_SAVE_PATH);
+        let result = path
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .map_err(WorldFileError::from)
+            .and_then(|_| self.game.world().save(path));
+        match result {
+            Ok(()) => log::info!("World saved to {}", path.display()),
+            Err(e) => log::error!("{}", e),
+        }
+    }
+
+    pub fn quick_load(&mut self) {
+        match self.game.world_mut().load(QUICK_SAVE_PATH) {
+            Ok(()) => {
+                self.game.clear_history();
+                log::info!("World loaded from {}", QUICK_SAVE_PATH);
+            }
+            Err(e) => log::error!("{}", e),
+        }
+    }
+
+    /// Replaces the sprites by a new random terrain
+    pub fn generate_terrain(&mut self, renderer: &mut RendererContext) {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.subsec_nanos())
+            .unwrap_or_default();
+        let region = Region::new(IVec3::new(-64, 0, -64), IVec3::new(64, 32, 64));
+
+        self.game.clear_sprites(renderer);
+        // wgpu's GL backend can't write 3D storage textures
+        if renderer.adapter_info().backend == wgpu::Backend::Gl {
+            self.game.generate_terrain(TerrainParams::with_seed(seed), &region);
+        }
+        else {
+            self.game.generate_terrain_gpu(renderer, TerrainParams::with_seed(seed), region);
+        }
+        log::info!("Generated terrain with seed {}", seed);
+    }
+
+    /// Adds a few shapes combined with CSG operations above the origin
+    pub fn voxelize_shapes(&mut self, renderer: &mut RendererContext) {
+        let mut ring = Transform::from_position(Vec3::new(0.0, 40.0, 0.0));
+        ring.rotate_x(std::f32::consts::FRAC_PI_2);
+        let scene = SdfScene::new()
+            .union(Primitive::new(Shape::Box { half_extents: Vec3::splat(8.0) }, Transform::from_position(Vec3::new(0.0, 40.0, 0.0)), 1))
+            .subtract(Primitive::new(Shape::Sphere { radius: 10.0 }, Transform::from_position(Vec3::new(0.0, 40.0, 0.0)), 0))
+            .union(Primitive::new(Shape::Torus { major_radius: 14.0, minor_radius: 2.0 }, ring, 255))
+            .smooth_union(Primitive::new(Shape::Capsule { half_height: 8.0, radius: 3.0 }, Transform::from_position(Vec3::new(0.0, 20.0, 0.0)), 128), 4.0);
+
+        // wgpu's GL backend can't write 3D storage textures
+        if renderer.adapter_info().backend == wgpu::Backend::Gl {
+            self.game.voxelize(&scene);
+        }
+        else {
+            self.game.voxelize_gpu(renderer, &scene);
+        }
+    }
+
     pub fn run_ui(&mut self, ctx: &egui::Context, renderer: &RendererContext, game_texture: Option<egui::TextureId>) {
-        self.editor.run_ui(ctx, &self.game, renderer, game_texture);
+        self.editor.run_ui(ctx, &mut self.game, renderer, &mut self.profiler, game_texture);
+        if let Some(pixel) = self.editor.take_pick() {
+            self.apply_brush(renderer, pixel);
+        }
+        if let Some(action) = self.editor.take_history_action() {
+            // edits are restored in the CPU world, which must not miss voxels written on the GPU
+            self.game.sync_world(renderer);
+            let name = match action {
+                HistoryAction::Undo => self.game.undo(),
+                HistoryAction::Redo => self.game.redo(),
+            };
+            if let Some(name) = name {
+                log::info!("{:?} {}", action, name);
+            }
+        }
+    }
+
+    /// Applies the changes of the scene graph requested by the hierarchy since the last frame
+    pub fn apply_scene_actions(&mut self, renderer: &mut RendererContext) {
+        for action in self.editor.take_scene_actions() {
+            match action {
+                SceneAction::Create { template, parent } => {
+                    if let Some(id) = self.create_entity(renderer, template, parent) {
+                        self.editor.set_selection(Some(id));
+                    }
+                }
+                SceneAction::Delete(id) => {
+                    if !self.game.delete_entity(renderer, id) {
+                        log::error!("the camera can't be deleted");
+                    }
+                }
+                SceneAction::Rename(id, name) => {
+                    self.game.scene_mut().rename(id, name);
+                }
+                SceneAction::Reparent(id, parent) => {
+                    if !self.game.scene_mut().set_parent(id, parent) {
+                        log::error!("an entity can't be moved under itself");
+                    }
+                }
+            }
+        }
+    }
+
+    fn create_entity(&mut self, renderer: &mut RendererContext, template: NewEntity, parent: Option<EntityId>) -> Option<EntityId> {
+        let position = parent.map_or(Vec3::ZERO, |parent| self.game.scene().world_transform(parent).position);
+        match template {
+            NewEntity::Empty => Some(self.game.scene_mut().add("Entity", EntityKind::Empty, Transform::IDENTITY, parent)),
+            NewEntity::Light => {
+                let kind = EntityKind::Light {
+                    color: Vec3::ONE,
+                    intensity: 1.0,
+                };
+                Some(self.game.scene_mut().add("Light", kind, Transform::IDENTITY, parent))
+            }
+            NewEntity::Sprite => {
+                let params = SpriteParams::new(position.round().as_ivec3(), UVec3::splat(16), 255);
+                match Sprite::new(renderer, self.game.world(), SPHERE_SHADER, params) {
+                    Ok(sprite) => {
+                        let handle = self.game.add_sprite(sprite);
+                        let id = self.game.sprite_entity(handle)?;
+                        self.game.scene_mut().set_parent(id, parent);
+                        Some(id)
+                    }
+                    Err(e) => {
+                        log::error!("{}", e);
+                        None
+                    }
+                }
+            }
+        }
+    }
+
+    /// Edits the world with the editor brush where the pixel of the game view picks it
+    pub fn apply_brush(&mut self, renderer: &RendererContext, pixel: Vec2) {
+        self.game.sync_world(renderer);
+        if let Some(hit) = self.game.pick(pixel) {
+            let brush = self.editor.brush_mut();
+            let name = format!("{:?} {:?}", brush.mode, brush.shape);
+            let edits = brush.apply(self.game.world().chunks(), &hit);
+            self.game.apply_edits(&name, &edits);
+        }
     }
 }
\ No newline at end of file
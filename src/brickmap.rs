@@ -0,0 +1,273 @@
+use glam::{IVec3, UVec3};
+
+use crate::voxel_world::{ChunkMap, CHUNK_SIZE};
+
+/// Size of a brick side in voxels
+pub const BRICK_SIZE: i32 = 8;
+const BRICK_VOLUME: usize = (BRICK_SIZE * BRICK_SIZE * BRICK_SIZE) as usize;
+/// Marks a grid cell without brick
+const EMPTY_BRICK: u32 = u32::MAX;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+struct BrickmapHeader {
+    origin: [i32; 4],
+    grid_size: [u32; 3],
+    bricks_offset: u32,
+}
+
+/// Bytes of the GPU buffer modified by an update, see [`Brickmap::to_bytes`]
+pub struct BufferWrite {
+    pub offset: u64,
+    pub bytes: Vec<u8>,
+}
+
+/// Two level grid: a coarse grid whose non empty cells point to a brick of voxels in a pool
+pub struct Brickmap {
+    origin: IVec3,
+    grid_size: UVec3,
+    grid: Vec<u32>,
+    bricks: Vec<u32>,
+    /// Bricks of the pool no longer used by the grid
+    free_bricks: Vec<u32>,
+}
+
+impl Brickmap {
+    pub fn build(chunks: &ChunkMap) -> Self {
+        let (origin, grid_size) = Brickmap::layout(chunks);
+        let mut brickmap = Self {
+            origin,
+            grid_size,
+            grid: vec![EMPTY_BRICK; (grid_size.x * grid_size.y * grid_size.z) as usize],
+            bricks: vec![],
+            free_bricks: vec![],
+        };
+
+        for (chunk_coord, _) in chunks.iter() {
+            brickmap.update_chunk(chunks, *chunk_coord);
+        }
+
+        brickmap
+    }
+
+    /// Origin and grid size of the brickmap covering the chunks
+    fn layout(chunks: &ChunkMap) -> (IVec3, UVec3) {
+        match chunks.bounds() {
+            Some((min, max)) => (min, ((max - min) / BRICK_SIZE).as_uvec3()),
+            None => (IVec3::ZERO, UVec3::ZERO),
+        }
+    }
+
+    /// Updates the bricks of the given chunks, returning the writes to apply to the GPU buffer.
+    /// Returns `None`, leaving the brickmap untouched, when the chunks outgrew it and it must be built again.
+    pub fn update_chunks<I: IntoIterator<Item = IVec3>>(&mut self, chunks: &ChunkMap, chunk_coords: I) -> Option<Vec<BufferWrite>> {
+        if chunks.bounds().is_none() || Brickmap::layout(chunks) != (self.origin, self.grid_size) {
+            return None;
+        }
+
+        let mut cells = vec![];
+        for chunk_coord in chunk_coords {
+            cells.extend(self.update_chunk(chunks, chunk_coord));
+        }
+
+        let header_size = std::mem::size_of::<BrickmapHeader>();
+        let bricks_offset = header_size + self.grid.len() * 4;
+        let mut writes = vec![];
+        for cell_index in cells {
+            let brick = self.grid[cell_index];
+            writes.push(BufferWrite {
+                offset: (header_size + cell_index * 4) as u64,
+                bytes: bytemuck::bytes_of(&brick).to_vec(),
+            });
+            if brick != EMPTY_BRICK {
+                let brick_voxels = brick as usize * BRICK_VOLUME..(brick as usize + 1) * BRICK_VOLUME;
+                writes.push(BufferWrite {
+                    offset: (bricks_offset + brick_voxels.start * 4) as u64,
+                    bytes: bytemuck::cast_slice(&self.bricks[brick_voxels]).to_vec(),
+                });
+            }
+        }
+        Some(writes)
+    }
+
+    /// Copies the bricks of a chunk into the pool, returns the indices of the cells it covers
+    fn update_chunk(&mut self, chunks: &ChunkMap, chunk_coord: IVec3) -> Vec<usize> {
+        let bricks_per_chunk = CHUNK_SIZE / BRICK_SIZE;
+        let chunk = chunks.chunk(chunk_coord);
+        let mut cells = vec![];
+
+        for i in 0..bricks_per_chunk.pow(3) {
+            let brick_in_chunk = IVec3::new(
+                i % bricks_per_chunk,
+                i / bricks_per_chunk % bricks_per_chunk,
+                i / (bricks_per_chunk * bricks_per_chunk)
+            );
+            let cell = chunk_coord * bricks_per_chunk + brick_in_chunk - self.origin / BRICK_SIZE;
+            if cell.cmplt(IVec3::ZERO).any() || cell.as_uvec3().cmpge(self.grid_size).any() {
+                continue;
+            }
+            let cell_index = self.cell_index(cell.as_uvec3());
+            cells.push(cell_index);
+
+            let mut voxels = Vec::with_capacity(BRICK_VOLUME);
+            if let Some(chunk) = chunk {
+                for z in 0..BRICK_SIZE {
+                    for y in 0..BRICK_SIZE {
+                        for x in 0..BRICK_SIZE {
+                            voxels.push(chunk.get(brick_in_chunk * BRICK_SIZE + IVec3::new(x, y, z)));
+                        }
+                    }
+                }
+            }
+
+            let brick = self.grid[cell_index];
+            if voxels.iter().all(|voxel| *voxel == 0) {
+                if brick != EMPTY_BRICK {
+                    self.free_bricks.push(brick);
+                    self.grid[cell_index] = EMPTY_BRICK;
+                }
+                continue;
+            }
+
+            let brick = match brick {
+                EMPTY_BRICK => {
+                    let brick = self.free_bricks.pop().unwrap_or_else(|| {
+                        self.bricks.resize(self.bricks.len() + BRICK_VOLUME, 0);
+                        (self.bricks.len() / BRICK_VOLUME - 1) as u32
+                    });
+                    self.grid[cell_index] = brick;
+                    brick
+                }
+                brick => brick,
+            };
+            let start = brick as usize * BRICK_VOLUME;
+            self.bricks[start..start + BRICK_VOLUME].copy_from_slice(&voxels);
+        }
+
+        cells
+    }
+
+    fn cell_index(&self, cell: UVec3) -> usize {
+        (cell.x + cell.y * self.grid_size.x + cell.z * self.grid_size.x * self.grid_size.y) as usize
+    }
+
+    /// Bricks used by the grid
+    pub fn brick_count(&self) -> usize {
+        self.bricks.len() / BRICK_VOLUME - self.free_bricks.len()
+    }
+
+    /// Size of the buffer returned by [`Brickmap::to_bytes`]
+    pub fn byte_size(&self) -> usize {
+        std::mem::size_of::<BrickmapHeader>() + 4 * (self.grid.len().max(1) + self.bricks.len())
+    }
+
+    pub fn get_voxel_at(&self, coord: &IVec3) -> u32 {
+        let local = *coord - self.origin;
+        if local.cmplt(IVec3::ZERO).any() {
+            return 0;
+        }
+        let cell = (local / BRICK_SIZE).as_uvec3();
+        if cell.cmpge(self.grid_size).any() {
+            return 0;
+        }
+
+        match self.grid[self.cell_index(cell)] {
+            EMPTY_BRICK => 0,
+            brick => {
+                let in_brick = local % BRICK_SIZE;
+                let index = in_brick.x + in_brick.y * BRICK_SIZE + in_brick.z * BRICK_SIZE * BRICK_SIZE;
+                self.bricks[brick as usize * BRICK_VOLUME + index as usize]
+            }
+        }
+    }
+
+    /// Header followed by the grid then the bricks, as read by the brickmap raymarcher
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let header = BrickmapHeader {
+            origin: [self.origin.x, self.origin.y, self.origin.z, 0],
+            grid_size: self.grid_size.to_array(),
+            bricks_offset: self.grid.len() as u32,
+        };
+
+        let mut bytes = bytemuck::bytes_of(&header).to_vec();
+        bytes.extend_from_slice(bytemuck::cast_slice(&self.grid));
+        bytes.extend_from_slice(bytemuck::cast_slice(&self.bricks));
+        // storage buffers can't be empty
+        if self.grid.is_empty() {
+            bytes.extend_from_slice(bytemuck::bytes_of(&EMPTY_BRICK));
+        }
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::voxel_world::Region;
+
+    #[test]
+    fn empty_world() {
+        let brickmap = Brickmap::build(&ChunkMap::new());
+        assert_eq!(brickmap.brick_count(), 0);
+        assert_eq!(brickmap.get_voxel_at(&IVec3::ZERO), 0);
+    }
+
+    #[test]
+    fn matches_the_chunks() {
+        let mut chunks = ChunkMap::new();
+        let voxels = [
+            (IVec3::new(0, 0, 0), 1),
+            (IVec3::new(-1, 5, 3), 2),
+            (IVec3::new(17, 30, -20), 3),
+            (IVec3::new(40, 2, 9), 4),
+        ];
+        for (coord, value) in voxels.iter() {
+            chunks.set_voxel_at(*value, coord);
+        }
+
+        let brickmap = Brickmap::build(&chunks);
+        assert_eq!(brickmap.brick_count(), voxels.len());
+
+        let (min, max) = chunks.bounds().unwrap();
+        for z in min.z - 1..=max.z {
+            for y in min.y - 1..=max.y {
+                for x in min.x - 1..=max.x {
+                    let coord = IVec3::new(x, y, z);
+                    assert_eq!(brickmap.get_voxel_at(&coord), chunks.get_voxel_at(&coord), "at {}", coord);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn updated_chunks_match_a_new_build() {
+        let mut chunks = ChunkMap::new();
+        chunks.allocate_region(IVec3::new(-16, 0, 0), IVec3::new(32, 16, 16));
+        chunks.set_voxel_at(1, &IVec3::new(-3, 4, 5));
+        chunks.set_voxel_at(2, &IVec3::new(20, 2, 3));
+        let mut brickmap = Brickmap::build(&chunks);
+        assert_eq!(brickmap.brick_count(), 2);
+
+        chunks.set_voxel_at(0, &IVec3::new(-3, 4, 5));
+        chunks.set_voxel_at(3, &IVec3::new(-12, 12, 12));
+        chunks.set_voxel_at(4, &IVec3::new(21, 2, 3));
+        let writes = brickmap.update_chunks(&chunks, [IVec3::new(-1, 0, 0), IVec3::new(1, 0, 0)]).unwrap();
+        // the grid cells of both chunks, and the bricks still used
+        assert_eq!(writes.len(), 2 * 8 + 2);
+        assert_eq!(brickmap.brick_count(), 2);
+
+        let bytes = brickmap.to_bytes();
+        for write in writes {
+            let offset = write.offset as usize;
+            assert_eq!(&bytes[offset..offset + write.bytes.len()], write.bytes.as_slice());
+        }
+        for coord in Region::new(IVec3::new(-16, 0, 0), IVec3::new(32, 16, 16)).iter() {
+            assert_eq!(brickmap.get_voxel_at(&coord), chunks.get_voxel_at(&coord), "at {}", coord);
+        }
+        assert_eq!(brickmap.byte_size(), brickmap.to_bytes().len());
+
+        // chunks outside of the grid need a new build
+        chunks.set_voxel_at(1, &IVec3::new(100, 0, 0));
+        assert!(brickmap.update_chunks(&chunks, [IVec3::new(6, 0, 0)]).is_none());
+    }
+}
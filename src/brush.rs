@@ -0,0 +1,297 @@
+//! Voxel editing brushes, applied where a ray picked the world.
+
+use std::collections::{HashSet, VecDeque};
+
+use glam::IVec3;
+
+use crate::{ray::{Ray, RayHit}, voxel_world::{ChunkMap, Region}};
+
+/// Most voxels a fill can change, whatever the brush size
+const MAX_FILL_VOXELS: usize = 1 << 18;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BrushMode {
+    /// Adds voxels in front of the picked face, keeping the existing ones
+    Place,
+    Remove,
+    /// Changes the value of existing voxels
+    Paint,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BrushShape {
+    Sphere,
+    Cube,
+    /// Stroke between two picks, as thick as the brush
+    Line,
+    /// Voxels connected to the picked one with the same value, within the brush size
+    Fill,
+}
+
+/// Change of a single voxel, which can be reverted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VoxelEdit {
+    pub coord: IVec3,
+    pub old_value: u32,
+    pub new_value: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Brush {
+    pub mode: BrushMode,
+    pub shape: BrushShape,
+    /// Diameter of spheres and lines, side of cubes and of the box a fill stays in
+    pub size: u32,
+    /// Value written by the place and paint modes
+    pub value: u32,
+    /// First end of a line, set by the previous pick
+    line_start: Option<IVec3>,
+}
+
+impl Default for Brush {
+    fn default() -> Self {
+        Self {
+            mode: BrushMode::Place,
+            shape: BrushShape::Sphere,
+            size: 1,
+            value: 255,
+            line_start: None,
+        }
+    }
+}
+
+impl Brush {
+    pub fn line_start(&self) -> Option<IVec3> {
+        self.line_start
+    }
+
+    /// Forgets the first end of the current line
+    pub fn cancel_line(&mut self) {
+        self.line_start = None;
+    }
+
+    /// Voxel the brush is centered on: in front of the picked face when placing, the picked voxel otherwise
+    pub fn target(&self, hit: &RayHit) -> IVec3 {
+        match self.mode {
+            BrushMode::Place => hit.adjacent_voxel(),
+            BrushMode::Remove | BrushMode::Paint => hit.voxel,
+        }
+    }
+
+    /// Edits made by a pick of the world, without changing it.
+    /// The first pick of a line only records its start and edits nothing.
+    pub fn apply(&mut self, world: &ChunkMap, hit: &RayHit) -> Vec<VoxelEdit> {
+        let target = self.target(hit);
+        let coords = match self.shape {
+            BrushShape::Sphere => self.sphere(target),
+            BrushShape::Cube => self.cube(target),
+            BrushShape::Line => match self.line_start.take() {
+                Some(start) => self.line(start, target),
+                None => {
+                    self.line_start = Some(target);
+                    return vec![];
+                }
+            },
+            BrushShape::Fill => self.fill(world, target),
+        };
+
+        coords
+            .into_iter()
+            .filter_map(|coord| {
+                let old_value = world.get_voxel_at(&coord);
+                let new_value = match self.mode {
+                    BrushMode::Place if old_value == 0 => self.value,
+                    BrushMode::Remove => 0,
+                    BrushMode::Paint if old_value != 0 => self.value,
+                    _ => old_value,
+                };
+                (new_value != old_value).then_some(VoxelEdit {
+                    coord,
+                    old_value,
+                    new_value,
+                })
+            })
+            .collect()
+    }
+
+    fn radius(&self) -> f32 {
+        self.size.max(1) as f32 * 0.5
+    }
+
+    /// Box of `size` voxels per side around the center, shifted toward the positive axes for even sizes
+    fn bounds(&self, center: IVec3) -> Region {
+        let size = self.size.max(1) as i32;
+        let min = center - IVec3::splat((size - 1) / 2);
+        Region::new(min, min + size)
+    }
+
+    fn sphere(&self, center: IVec3) -> Vec<IVec3> {
+        let bounds = self.bounds(center);
+        let sphere_center = bounds.min.as_vec3() + self.radius();
+        bounds
+            .iter()
+            .filter(|coord| (coord.as_vec3() + 0.5).distance(sphere_center) <= self.radius())
+            .collect()
+    }
+
+    fn cube(&self, center: IVec3) -> Vec<IVec3> {
+        self.bounds(center).iter().collect()
+    }
+
+    /// Voxels crossed by the segment between the voxel centers, grown to the brush radius
+    fn line(&self, start: IVec3, end: IVec3) -> Vec<IVec3> {
+        let from = start.as_vec3() + 0.5;
+        let ray = Ray::new(from, end.as_vec3() + 0.5 - from);
+        let mut visited = HashSet::new();
+        let mut coords = vec![];
+        for cell in ray.traverse_grid(1.0, 1.0) {
+            for coord in self.sphere(cell.cell) {
+                if visited.insert(coord) {
+                    coords.push(coord);
+                }
+            }
+        }
+        coords
+    }
+
+    /// Flood fill of the voxels with the value of the start, through their faces
+    fn fill(&self, world: &ChunkMap, start: IVec3) -> Vec<IVec3> {
+        let value = world.get_voxel_at(&start);
+        let bounds = self.bounds(start);
+        let mut visited = HashSet::from([start]);
+        let mut queue = VecDeque::from([start]);
+        let mut coords = vec![];
+        while let Some(coord) = queue.pop_front() {
+            coords.push(coord);
+            if coords.len() >= MAX_FILL_VOXELS {
+                break;
+            }
+            for offset in [IVec3::X, IVec3::NEG_X, IVec3::Y, IVec3::NEG_Y, IVec3::Z, IVec3::NEG_Z] {
+                let neighbor = coord + offset;
+                if bounds.contains(&neighbor) && world.get_voxel_at(&neighbor) == value && visited.insert(neighbor) {
+                    queue.push_back(neighbor);
+                }
+            }
+        }
+        coords
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hit(voxel: IVec3, normal: IVec3) -> RayHit {
+        RayHit {
+            voxel,
+            normal,
+            distance: 1.0,
+            value: 1,
+        }
+    }
+
+    fn apply(chunks: &mut ChunkMap, edits: &[VoxelEdit]) {
+        for edit in edits {
+            chunks.set_voxel_at(edit.new_value, &edit.coord);
+        }
+    }
+
+    #[test]
+    fn shapes() {
+        let chunks = ChunkMap::new();
+        let mut brush = Brush {
+            size: 3,
+            value: 4,
+            ..Default::default()
+        };
+        let edits = brush.apply(&chunks, &hit(IVec3::ZERO, IVec3::Y));
+        // rounded 3x3x3 cube, centered in front of the face
+        assert_eq!(edits.len(), 27 - 8);
+        assert!(edits.iter().all(|edit| edit.old_value == 0 && edit.new_value == 4));
+        assert!(edits.iter().any(|edit| edit.coord == IVec3::new(1, 1, 0)));
+        assert!(!edits.iter().any(|edit| edit.coord == IVec3::new(1, 2, 1)));
+
+        brush.shape = BrushShape::Cube;
+        brush.size = 2;
+        assert_eq!(brush.apply(&chunks, &hit(IVec3::ZERO, IVec3::Y)).len(), 8);
+
+        brush.size = 1;
+        brush.shape = BrushShape::Sphere;
+        assert_eq!(brush.apply(&chunks, &hit(IVec3::ZERO, IVec3::Y)), vec![VoxelEdit {
+            coord: IVec3::Y,
+            old_value: 0,
+            new_value: 4,
+        }]);
+    }
+
+    #[test]
+    fn modes_only_change_what_they_target() {
+        let mut chunks = ChunkMap::new();
+        chunks.set_voxel_at(1, &IVec3::ZERO);
+        chunks.set_voxel_at(1, &IVec3::X);
+        let mut brush = Brush {
+            shape: BrushShape::Cube,
+            size: 3,
+            value: 9,
+            ..Default::default()
+        };
+
+        brush.mode = BrushMode::Paint;
+        let edits = brush.apply(&chunks, &hit(IVec3::ZERO, IVec3::Y));
+        assert_eq!(edits.len(), 2);
+        assert!(edits.iter().all(|edit| edit.old_value == 1 && edit.new_value == 9));
+
+        brush.mode = BrushMode::Remove;
+        let edits = brush.apply(&chunks, &hit(IVec3::ZERO, IVec3::Y));
+        assert_eq!(edits.len(), 2);
+        assert!(edits.iter().all(|edit| edit.new_value == 0));
+
+        brush.mode = BrushMode::Place;
+        let edits = brush.apply(&chunks, &hit(IVec3::ZERO, IVec3::NEG_Y));
+        apply(&mut chunks, &edits);
+        assert_eq!(chunks.get_voxel_at(&IVec3::ZERO), 1);
+        assert_eq!(chunks.get_voxel_at(&IVec3::new(0, -1, 0)), 9);
+    }
+
+    #[test]
+    fn lines_need_two_picks() {
+        let chunks = ChunkMap::new();
+        let mut brush = Brush {
+            shape: BrushShape::Line,
+            ..Default::default()
+        };
+        assert!(brush.apply(&chunks, &hit(IVec3::ZERO, IVec3::Y)).is_empty());
+        assert_eq!(brush.line_start(), Some(IVec3::Y));
+
+        let edits = brush.apply(&chunks, &hit(IVec3::new(10, 0, 0), IVec3::Y));
+        let mut coords: Vec<_> = edits.iter().map(|edit| edit.coord.x).collect();
+        coords.sort();
+        assert_eq!(coords, (0..=10).collect::<Vec<_>>());
+        assert!(edits.iter().all(|edit| edit.coord.y == 1 && edit.coord.z == 0));
+        assert_eq!(brush.line_start(), None);
+    }
+
+    #[test]
+    fn fill_stays_in_the_connected_region() {
+        let mut chunks = ChunkMap::new();
+        // two separate rows of 5 voxels
+        for x in 0..5 {
+            chunks.set_voxel_at(2, &IVec3::new(x, 0, 0));
+            chunks.set_voxel_at(2, &IVec3::new(x, 2, 0));
+        }
+        let mut brush = Brush {
+            mode: BrushMode::Paint,
+            shape: BrushShape::Fill,
+            size: 64,
+            value: 3,
+            ..Default::default()
+        };
+        let edits = brush.apply(&chunks, &hit(IVec3::ZERO, IVec3::Y));
+        assert_eq!(edits.len(), 5);
+        assert!(edits.iter().all(|edit| edit.coord.y == 0));
+
+        // the size bounds the fill
+        brush.size = 3;
+        assert_eq!(brush.apply(&chunks, &hit(IVec3::ZERO, IVec3::Y)).len(), 2);
+    }
+}
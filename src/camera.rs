@@ -1,13 +1,29 @@
 use glam::{Mat4, Vec2};
 
-use crate::{renderer_context::{BufferHandle, RendererContext}, transform::Transform};
+use crate::{ray::Ray, renderer_context::{BufferHandle, RendererContext}, transform::Transform};
+
+/// Horizontal field of view of new cameras, in radians
+pub const DEFAULT_FOV: f32 = std::f32::consts::FRAC_PI_2;
 
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 struct CameraData {
     model: [[f32; 4]; 4],
     size: [f32; 2],
-    _padding3: u64,
+    /// Tangent of half the horizontal field of view
+    fov_scale: f32,
+    _padding3: u32,
+}
+
+impl CameraData {
+    fn new(size: Vec2) -> Self {
+        Self {
+            model: Mat4::IDENTITY.to_cols_array_2d(),
+            size: size.to_array(),
+            fov_scale: (DEFAULT_FOV * 0.5).tan(),
+            _padding3: 0,
+        }
+    }
 }
 
 pub struct Camera {
@@ -18,11 +34,7 @@ pub struct Camera {
 
 impl Camera {
     pub fn new(renderer: &mut RendererContext, size: Vec2) -> Self {
-        let data = CameraData {
-            model: Mat4::IDENTITY.to_cols_array_2d(),
-            size: size.to_array(),
-            _padding3: 0,
-        };
+        let data = CameraData::new(size);
 
         let buffer = renderer.new_buffer(
             &wgpu::util::BufferInitDescriptor {
@@ -49,6 +61,29 @@ impl Camera {
         Vec2::from_array(self.data.size)
     }
 
+    /// Horizontal field of view, in radians
+    pub fn fov(&self) -> f32 {
+        self.data.fov_scale.atan() * 2.0
+    }
+
+    pub fn set_fov(&mut self, fov: f32) {
+        self.data.fov_scale = (fov * 0.5).tan();
+    }
+
+    /// Ray through a pixel of the output, with the origin at the top left corner.
+    /// Matches the rays cast by the raymarchers, the direction isn't normalized.
+    pub fn screen_ray(&self, pixel: Vec2) -> Ray {
+        let size = self.size();
+        let screen_pos = Vec2::new(pixel.x / size.x * 2.0 - 1.0, 1.0 - pixel.y / size.y * 2.0);
+        let model = self.transform.compute_matrix();
+        let plane_u = model.x_axis.truncate() * self.data.fov_scale;
+        let plane_v = model.y_axis.truncate() * self.data.fov_scale * size.y / size.x;
+        Ray::new(
+            model.w_axis.truncate(),
+            model.z_axis.truncate() + screen_pos.x * plane_u + screen_pos.y * plane_v
+        )
+    }
+
     pub fn update_buffer(&mut self, renderer: &mut RendererContext) {
         self.data.model = self.transform.compute_matrix().to_cols_array_2d();
 
@@ -67,3 +102,39 @@ impl Camera {
         self.buffer
     }
 } 
+
+#[cfg(test)]
+mod tests {
+    use glam::{vec2, Vec3};
+
+    use super::*;
+
+    #[test]
+    fn screen_rays() {
+        // the buffer is never uploaded
+        let mut camera = Camera {
+            transform: Transform::IDENTITY,
+            data: CameraData::new(vec2(200.0, 100.0)),
+            buffer: BufferHandle::default(),
+        };
+        camera.transform.position = Vec3::new(1.0, 2.0, 3.0);
+        camera.transform.rotate_y(0.7);
+
+        let center = camera.screen_ray(vec2(100.0, 50.0));
+        assert_eq!(center.origin, camera.transform.position);
+        assert!(center.direction.abs_diff_eq(camera.transform.forward(), 1e-5));
+
+        // top left corner, the vertical extent is scaled by the aspect ratio
+        let corner = camera.screen_ray(Vec2::ZERO);
+        let expected = camera.transform.forward() - camera.transform.right() + camera.transform.up() * 0.5;
+        assert!(corner.direction.abs_diff_eq(expected, 1e-5), "{}", corner.direction);
+
+        // a narrower field of view brings the corner closer to the center
+        camera.set_fov(std::f32::consts::FRAC_PI_3);
+        assert!((camera.fov() - std::f32::consts::FRAC_PI_3).abs() < 1e-5);
+        let scale = (std::f32::consts::FRAC_PI_6).tan();
+        let corner = camera.screen_ray(Vec2::ZERO);
+        let expected = camera.transform.forward() + (camera.transform.up() * 0.5 - camera.transform.right()) * scale;
+        assert!(corner.direction.abs_diff_eq(expected, 1e-5), "{}", corner.direction);
+    }
+}
@@ -1,8 +1,62 @@
-use egui::{ahash::{HashSet, HashSetExt}, CentralPanel, Frame, TopBottomPanel, Ui, WidgetText};
-use egui_dock::{AllowedSplits, DockArea, DockState, NodeIndex, Style, SurfaceIndex, TabViewer};
+use std::collections::BTreeSet;
 
-use crate::{game::Game, renderer_context::RendererContext};
+use egui::{collapsing_header::CollapsingState, Align2, Button, CentralPanel, Color32, ComboBox, DragValue, FontId, Frame, Grid, Id, Key, KeyboardShortcut, Label, Modifiers, Pos2, RichText, ScrollArea, Sense, Shape, Slider, Stroke, TextEdit, TextStyle, TopBottomPanel, Ui, Visuals, WidgetText};
+use egui_dock::{AllowedSplits, DockArea, DockState, Node, NodeIndex, Style, TabIndex, TabViewer, Tree};
 
+use glam::{EulerRot, Quat, Vec2, Vec3};
+use log::Level;
+
+use crate::{editor_settings::{EditorSettings, EditorSettingsError, LayoutNode}, brush::{Brush, BrushMode, BrushShape}, game::{Game, PlayState}, logger::{self, LogRecord}, profiler::{Profiler, Timing}, renderer_context::RendererContext, palette::Material, scene::{Entity, EntityId, EntityKind}, sprite::SpriteParams, transform::Transform};
+
+const MAX_BRUSH_SIZE: u32 = 32;
+
+const SETTINGS_PATH: &str = "settings/editor.txt";
+
+const UNDO_SHORTCUT: KeyboardShortcut = KeyboardShortcut::new(Modifiers::COMMAND, Key::Z);
+const REDO_SHORTCUTS: [KeyboardShortcut; 2] = [
+    KeyboardShortcut::new(Modifiers::COMMAND, Key::Y),
+    KeyboardShortcut::new(Modifiers::COMMAND.plus(Modifiers::SHIFT), Key::Z),
+];
+
+const RENAME_ID: &str = "rename_entity";
+
+/// Range of the camera field of view, in degrees
+const MIN_FOV: f32 = 10.0;
+const MAX_FOV: f32 = 150.0;
+/// Largest sprite side editable in the inspector
+const MAX_SPRITE_SIZE: u32 = 256;
+
+/// Starts playing, or stops when already playing
+const PLAY_SHORTCUT: KeyboardShortcut = KeyboardShortcut::new(Modifiers::COMMAND, Key::P);
+
+/// Change of the world history requested by the editor, applied by the app
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistoryAction {
+    Undo,
+    Redo,
+}
+
+/// Entity created from the hierarchy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NewEntity {
+    Empty,
+    Light,
+    /// A sphere sprite
+    Sprite,
+}
+
+/// Change of the scene graph requested by the hierarchy, applied by the app
+#[derive(Debug, Clone, PartialEq)]
+pub enum SceneAction {
+    Create {
+        template: NewEntity,
+        parent: Option<EntityId>,
+    },
+    Delete(EntityId),
+    Rename(EntityId, String),
+    /// Moves the entity under another one, or to the roots
+    Reparent(EntityId, Option<EntityId>),
+}
 
 #[derive(Debug, Eq, Hash, PartialEq, Copy, Clone)]
 enum GuiTab {
@@ -10,21 +64,94 @@ enum GuiTab {
     Hierarchy,
     Inspector,
     RendererContext,
+    History,
+    Console,
+    Profiler,
+}
+
+impl GuiTab {
+    const ALL: [GuiTab; 7] = [
+        GuiTab::GameView,
+        GuiTab::Hierarchy,
+        GuiTab::Inspector,
+        GuiTab::RendererContext,
+        GuiTab::History,
+        GuiTab::Console,
+        GuiTab::Profiler,
+    ];
+
+    /// Tab with the name saved in the settings, see [`GuiTab::name`]
+    fn from_name(name: &str) -> Option<GuiTab> {
+        GuiTab::ALL.into_iter().find(|tab| tab.name() == name)
+    }
+
+    fn name(&self) -> String {
+        format!("{self:?}")
+    }
+}
+
+/// Records shown in the console tab
+#[derive(Debug, Clone)]
+struct ConsoleFilter {
+    /// Shown levels, indexed by `level as usize - 1`
+    levels: [bool; 5],
+    /// Text the message or the module must contain, ignoring the case
+    search: String,
+    /// Module whose records are shown, all of them when `None`
+    target: Option<String>,
+}
+
+impl Default for ConsoleFilter {
+    fn default() -> Self {
+        Self {
+            levels: [true; 5],
+            search: String::new(),
+            target: None,
+        }
+    }
+}
+
+impl ConsoleFilter {
+    fn matches(&self, record: &LogRecord) -> bool {
+        let search = self.search.to_lowercase();
+        self.levels[record.level as usize - 1]
+            && self.target.as_ref().is_none_or(|target| *target == record.target)
+            && (record.message.to_lowercase().contains(&search) || record.target.to_lowercase().contains(&search))
+    }
 }
 
 struct GuiContext<'a> {
     viewport_rect: &'a mut (bool, egui::Rect), 
-    open_tabs: HashSet<GuiTab>,
-    game: &'a Game,
+    game: &'a mut Game,
     renderer: &'a RendererContext,
     game_texture: Option<egui::TextureId>,
+    brush: &'a mut Brush,
+    pick: &'a mut Option<Vec2>,
+    history_action: &'a mut Option<HistoryAction>,
+    selection: &'a mut Option<EntityId>,
+    renaming: &'a mut Option<(EntityId, String)>,
+    edited_material: &'a mut Option<u32>,
+    scene_actions: &'a mut Vec<SceneAction>,
+    console_filter: &'a mut ConsoleFilter,
+    profiler: &'a mut Profiler,
 }
 
 pub struct Editor {
     viewport_rect: (bool, egui::Rect),
     pub style: Option<Style>,
-    open_tabs: HashSet<GuiTab>,
     tree: DockState<GuiTab>,
+    dark_mode: bool,
+    brush: Brush,
+    /// Pixel of the game view clicked with the brush, applied by the app
+    pick: Option<Vec2>,
+    history_action: Option<HistoryAction>,
+    selection: Option<EntityId>,
+    /// Entity whose name is edited in the hierarchy, with the new name
+    renaming: Option<(EntityId, String)>,
+    /// Material changed since the pointer was pressed, its changes are merged in the history
+    edited_material: Option<u32>,
+    scene_actions: Vec<SceneAction>,
+    console_filter: ConsoleFilter,
 }
 
 impl TabViewer for GuiContext<'_> {
@@ -38,14 +165,13 @@ impl TabViewer for GuiContext<'_> {
         match tab {
             GuiTab::GameView => self.game_view(ui),
             GuiTab::RendererContext => self.renderer_context(ui),
-            _ => {}
+            GuiTab::History => self.history(ui),
+            GuiTab::Hierarchy => self.hierarchy(ui),
+            GuiTab::Inspector => self.inspector(ui),
+            GuiTab::Console => self.console(ui),
+            GuiTab::Profiler => self.profiler(ui),
         }
     }
-
-    fn on_close(&mut self, tab: &mut Self::Tab) -> bool {
-        self.open_tabs.remove(tab);
-        true
-    }
 }
 
 impl GuiContext<'_> {
@@ -56,15 +182,90 @@ impl GuiContext<'_> {
         });
     }
 
+    fn play_controls(&mut self, ui: &mut Ui) {
+        let state = self.game.play_state();
+        let label = match state {
+            PlayState::Paused => "Resume",
+            _ => "Play",
+        };
+        if ui.add_enabled(state != PlayState::Playing, Button::new(label)).clicked() {
+            self.play();
+        }
+        if ui.add_enabled(state == PlayState::Playing, Button::new("Pause")).clicked() {
+            self.game.pause();
+        }
+        if ui.add_enabled(state != PlayState::Editing, Button::new("Step")).clicked() {
+            self.game.step();
+        }
+        if ui.add_enabled(state != PlayState::Editing, Button::new("Stop")).clicked() {
+            self.game.stop();
+        }
+        ui.label(RichText::new(format!("{state:?}")).weak());
+    }
+
+    fn play(&mut self) {
+        // the voxels generated on the GPU must be captured with the world
+        if self.game.play_state() == PlayState::Editing {
+            self.game.sync_world(self.renderer);
+        }
+        self.game.play();
+    }
+
+    fn toggle_play(&mut self) {
+        match self.game.play_state() {
+            PlayState::Editing => self.play(),
+            PlayState::Playing | PlayState::Paused => self.game.stop(),
+        }
+    }
+
     fn game_view(&mut self, ui: &mut Ui) {
-        self.viewport_rect.1 = ui.clip_rect();
+        self.brush_toolbar(ui);
+        self.viewport_rect.1 = ui.available_rect_before_wrap();
         if let Some(game_texture) = self.game_texture {
-            ui.image(
-                (game_texture, ui.available_size())
-            );  
+            let response = ui.add(
+                egui::Image::new((game_texture, ui.available_size())).sense(Sense::click())
+            );
+            if response.clicked() {
+                if let Some(position) = response.interact_pointer_pos() {
+                    let uv = (position - response.rect.min) / response.rect.size();
+                    let pixel = Vec2::new(uv.x, uv.y) * self.game.camera().size();
+                    // the brush is kept for plain clicks, selecting needs the command modifier
+                    if ui.input(|input| input.modifiers.command) {
+                        *self.selection = self.game.pick_entity(pixel);
+                    }
+                    else {
+                        *self.pick = Some(pixel);
+                    }
+                }
+            }
+            if response.secondary_clicked() {
+                self.brush.cancel_line();
+            }
         }
     }
 
+    fn brush_toolbar(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            for mode in [BrushMode::Place, BrushMode::Remove, BrushMode::Paint] {
+                ui.selectable_value(&mut self.brush.mode, mode, format!("{mode:?}"));
+            }
+            ui.separator();
+            ComboBox::from_id_source("brush_shape")
+                .selected_text(format!("{:?}", self.brush.shape))
+                .show_ui(ui, |ui| {
+                    for shape in [BrushShape::Sphere, BrushShape::Cube, BrushShape::Line, BrushShape::Fill] {
+                        ui.selectable_value(&mut self.brush.shape, shape, format!("{shape:?}"));
+                    }
+                });
+            ui.add(Slider::new(&mut self.brush.size, 1..=MAX_BRUSH_SIZE).text("Size"));
+            ui.add(DragValue::new(&mut self.brush.value).clamp_range(1..=255).prefix("Value: "));
+            if let Some(start) = self.brush.line_start() {
+                ui.label(format!("Line from {} (right click to cancel)", start));
+            }
+            ui.label(RichText::new("Ctrl+click to select an entity").weak());
+        });
+    }
+
     fn renderer_context(&mut self, ui: &mut Ui) {
         ui.label(format!("Render pipelines: {}", self.renderer.render_pipeline_count()));
         ui.label(format!("Compute pipelines: {}", self.renderer.compute_pipeline_count()));
@@ -73,20 +274,351 @@ impl GuiContext<'_> {
         ui.label(format!("Textures: {}", self.renderer.texture_count()));
         ui.label(format!("Buffers: {}", self.renderer.buffer_count()));
     }
+
+    fn console(&mut self, ui: &mut Ui) {
+        let records = logger::records(|_| true);
+        let targets: BTreeSet<&str> = records.iter().map(|record| record.target.as_str()).collect();
+        let filter = &mut *self.console_filter;
+        ui.horizontal(|ui| {
+            for level in Level::iter() {
+                ui.checkbox(&mut filter.levels[level as usize - 1], level.as_str());
+            }
+            ComboBox::from_id_source("console_target")
+                .selected_text(filter.target.as_deref().unwrap_or("All modules"))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut filter.target, None, "All modules");
+                    for target in targets {
+                        ui.selectable_value(&mut filter.target, Some(target.to_string()), target);
+                    }
+                });
+            ui.add(TextEdit::singleline(&mut filter.search).hint_text("Search"));
+            if ui.button("Clear").clicked() {
+                logger::clear();
+            }
+        });
+        ui.separator();
+
+        let shown: Vec<_> = records.iter().filter(|record| filter.matches(record)).collect();
+        let row_height = ui.text_style_height(&TextStyle::Monospace);
+        ScrollArea::vertical()
+            .auto_shrink([false; 2])
+            .stick_to_bottom(true)
+            .show_rows(ui, row_height, shown.len(), |ui, rows| {
+                for record in &shown[rows] {
+                    let text = format!("{:>8.2} {:<5} {}: {}", record.time, record.level, record.target, record.message);
+                    let color = level_color(ui, record.level);
+                    let response = ui
+                        .add(Label::new(RichText::new(&text).monospace().color(color)).sense(Sense::click()))
+                        .on_hover_text("Click to copy");
+                    if response.clicked() {
+                        ui.ctx().copy_text(text);
+                    }
+                }
+            });
+    }
+
+    fn profiler(&mut self, ui: &mut Ui) {
+        let profiler = &mut *self.profiler;
+        ui.horizontal(|ui| {
+            if let Some(mean) = profiler.mean_frame_time() {
+                ui.label(format!("{:.0} FPS", 1000.0 / mean));
+                ui.separator();
+            }
+            for (name, fraction) in [("p50", 0.5), ("p95", 0.95), ("p99", 0.99)] {
+                if let Some(duration) = profiler.percentile(fraction) {
+                    ui.label(format!("{}: {:.2} ms", name, duration));
+                }
+            }
+            ui.separator();
+            ui.add_enabled(self.renderer.supports_gpu_timing(), |ui: &mut Ui| ui.checkbox(&mut profiler.gpu_timing, "GPU timings"))
+                .on_hover_text("The GPU timings are read a few frames late")
+                .on_disabled_hover_text("The adapter can't write timestamps");
+            if ui.button("Clear").clicked() {
+                profiler.clear();
+            }
+        });
+        frame_time_graph(ui, profiler);
+
+        let Some(last_frame) = profiler.last_frame() else {
+            return;
+        };
+        ScrollArea::vertical().show(ui, |ui| {
+            ui.label(RichText::new("CPU").strong());
+            timing_grid(ui, "cpu_scopes", &last_frame.scopes, last_frame.duration);
+            if !last_frame.gpu_passes.is_empty() {
+                ui.label(RichText::new("GPU").strong());
+                let total = last_frame.gpu_passes.iter().map(|pass| pass.ms).sum();
+                timing_grid(ui, "gpu_passes", &last_frame.gpu_passes, total);
+            }
+        });
+    }
+
+    fn hierarchy(&mut self, ui: &mut Ui) {
+        let scene = self.game.scene();
+        let selection = self.selection.filter(|id| scene.contains(*id));
+        let deletable = selection.is_some_and(|id| !scene.is_ancestor(id, self.game.camera_entity()));
+        let roots = scene.roots().to_vec();
+        ui.horizontal(|ui| {
+            ui.menu_button("Create", |ui| self.create_menu(ui, None));
+            if ui.add_enabled(deletable, Button::new("Delete")).clicked() {
+                self.scene_actions.extend(selection.map(SceneAction::Delete));
+            }
+        });
+        ui.separator();
+        ScrollArea::vertical().show(ui, |ui| {
+            for id in roots {
+                self.entity_tree(ui, id);
+            }
+            // dropping an entity below the tree moves it to the roots
+            let response = ui.allocate_response(ui.available_size().max(egui::vec2(0.0, 32.0)), Sense::hover());
+            if let Some(dragged) = response.dnd_release_payload::<EntityId>() {
+                self.scene_actions.push(SceneAction::Reparent(*dragged, None));
+            }
+        });
+    }
+
+    fn entity_tree(&mut self, ui: &mut Ui, id: EntityId) {
+        // the game can't stay borrowed while the rows are drawn
+        let Some(entity) = self.game.scene().get(id).cloned() else {
+            return;
+        };
+        if entity.children().is_empty() {
+            self.entity_row(ui, id, &entity);
+        }
+        else {
+            CollapsingState::load_with_default_open(ui.ctx(), ui.make_persistent_id(id), true)
+                .show_header(ui, |ui| self.entity_row(ui, id, &entity))
+                .body(|ui| {
+                    for child in entity.children() {
+                        self.entity_tree(ui, *child);
+                    }
+                });
+        }
+    }
+
+    fn entity_row(&mut self, ui: &mut Ui, id: EntityId, entity: &Entity) {
+        if let Some((_, name)) = self.renaming.as_mut().filter(|(renamed, _)| *renamed == id) {
+            let response = ui.add(TextEdit::singleline(name).id(Id::new(RENAME_ID)));
+            if response.lost_focus() {
+                let cancelled = ui.input(|input| input.key_pressed(Key::Escape));
+                if let Some((_, name)) = self.renaming.take().filter(|_| !cancelled) {
+                    self.scene_actions.push(SceneAction::Rename(id, name));
+                }
+            }
+            return;
+        }
+
+        let selected = *self.selection == Some(id);
+        let response = ui
+            .selectable_label(selected, &entity.name)
+            .interact(Sense::click_and_drag())
+            .on_hover_text(entity_kind_name(&entity.kind));
+        response.dnd_set_drag_payload(id);
+        if response.clicked() {
+            *self.selection = Some(id);
+        }
+        if response.double_clicked() {
+            self.start_rename(ui, id, entity);
+        }
+        if response.dnd_hover_payload::<EntityId>().is_some_and(|dragged| *dragged != id) {
+            ui.painter().rect_stroke(response.rect, 2.0, ui.visuals().selection.stroke);
+        }
+        if let Some(dragged) = response.dnd_release_payload::<EntityId>() {
+            if *dragged != id {
+                self.scene_actions.push(SceneAction::Reparent(*dragged, Some(id)));
+            }
+        }
+
+        let deletable = !self.game.scene().is_ancestor(id, self.game.camera_entity());
+        response.context_menu(|ui| {
+            if ui.button("Rename").clicked() {
+                self.start_rename(ui, id, entity);
+                ui.close_menu();
+            }
+            ui.menu_button("Create child", |ui| self.create_menu(ui, Some(id)));
+            if ui.add_enabled(deletable, Button::new("Delete")).clicked() {
+                self.scene_actions.push(SceneAction::Delete(id));
+                ui.close_menu();
+            }
+        });
+    }
+
+    fn start_rename(&mut self, ui: &mut Ui, id: EntityId, entity: &Entity) {
+        *self.renaming = Some((id, entity.name.clone()));
+        ui.memory_mut(|memory| memory.request_focus(Id::new(RENAME_ID)));
+    }
+
+    fn create_menu(&mut self, ui: &mut Ui, parent: Option<EntityId>) {
+        for template in [NewEntity::Empty, NewEntity::Light, NewEntity::Sprite] {
+            if ui.button(format!("{template:?}")).clicked() {
+                self.scene_actions.push(SceneAction::Create {
+                    template,
+                    parent,
+                });
+                ui.close_menu();
+            }
+        }
+    }
+
+    fn inspector(&mut self, ui: &mut Ui) {
+        let Some(id) = self.selection.filter(|id| self.game.scene().contains(*id)) else {
+            ui.label("Select an entity in the hierarchy");
+            return;
+        };
+        let mut entity = self.game.scene().get(id).cloned().unwrap();
+
+        ui.horizontal(|ui| {
+            ui.label("Name");
+            if ui.text_edit_singleline(&mut entity.name).changed() {
+                self.game.scene_mut().rename(id, entity.name.clone());
+            }
+        });
+        ui.label(RichText::new(entity_kind_name(&entity.kind)).weak());
+        ui.separator();
+
+        // entities mirroring an object of the game are edited through it
+        if !entity.kind.is_linked() && transform_editor(ui, &mut entity.transform) {
+            if let Some(edited) = self.game.scene_mut().get_mut(id) {
+                edited.transform = entity.transform;
+            }
+        }
+
+        match entity.kind {
+            EntityKind::Empty => {}
+            EntityKind::Camera => self.camera_inspector(ui),
+            EntityKind::Sprite(handle) => {
+                let Some(sprite) = self.game.sprite_mut(handle) else {
+                    return;
+                };
+                ui.label(format!("Shader: {}", sprite.shader_path().display()));
+                let mut params = *sprite.params();
+                if sprite_params_editor(ui, &mut params) {
+                    sprite.set_params(params);
+                }
+                ui.separator();
+                self.material_inspector(ui, params.material);
+            }
+            EntityKind::Light { mut color, mut intensity } => {
+                let mut changed = false;
+                ui.horizontal(|ui| {
+                    ui.label("Color");
+                    let mut rgb = color.to_array();
+                    changed |= ui.color_edit_button_rgb(&mut rgb).changed();
+                    color = Vec3::from_array(rgb);
+                });
+                changed |= ui.add(DragValue::new(&mut intensity).speed(0.05).clamp_range(0.0..=f32::MAX).prefix("Intensity: ")).changed();
+                if changed {
+                    if let Some(edited) = self.game.scene_mut().get_mut(id) {
+                        edited.kind = EntityKind::Light { color, intensity };
+                    }
+                }
+            }
+            EntityKind::Model { region } => {
+                ui.label(format!("Voxels from {} to {}", region.min, region.max));
+            }
+        }
+    }
+
+    fn camera_inspector(&mut self, ui: &mut Ui) {
+        let camera = self.game.camera_mut();
+        transform_editor(ui, &mut camera.transform);
+        let mut fov = camera.fov().to_degrees();
+        if ui.add(Slider::new(&mut fov, MIN_FOV..=MAX_FOV).text("FOV")).changed() {
+            camera.set_fov(fov.to_radians());
+        }
+        // the size is the resolution of the game texture, resized to the game view so the rays match its pixels:
+        // an edited size would be overwritten by the next resize of the view
+        let size = camera.size();
+        ui.label(format!("Size: {} x {}", size.x, size.y))
+            .on_hover_text("Resolution of the game texture, which follows the size of the game view");
+    }
+
+    fn material_inspector(&mut self, ui: &mut Ui, value: u32) {
+        ui.label(format!("Material {}", value));
+        let mut material = *self.game.world().palette().get(value);
+        if material_editor(ui, &mut material) {
+            // the changes of a drag are a single command
+            let merge = *self.edited_material == Some(value);
+            self.game.set_material(value, material, merge);
+            *self.edited_material = Some(value);
+        }
+        if !ui.input(|input| input.pointer.any_down()) {
+            *self.edited_material = None;
+        }
+    }
+
+    fn history(&mut self, ui: &mut Ui) {
+        let history = self.game.history();
+        ui.horizontal(|ui| {
+            if ui.add_enabled(history.can_undo(), egui::Button::new("Undo")).clicked() {
+                *self.history_action = Some(HistoryAction::Undo);
+            }
+            if ui.add_enabled(history.can_redo(), egui::Button::new("Redo")).clicked() {
+                *self.history_action = Some(HistoryAction::Redo);
+            }
+            ui.label(format!("{} KiB", history.size() / 1024));
+        });
+        ui.separator();
+        ScrollArea::vertical().show(ui, |ui| {
+            // the undone commands are listed above the current state, greyed out
+            let undone: Vec<_> = history.redo_commands().collect();
+            for command in undone.into_iter().rev() {
+                ui.label(RichText::new(command.name()).weak())
+                    .on_hover_text(format!("{} to {}", command.region().min, command.region().max));
+            }
+            for command in history.undo_commands() {
+                ui.label(command.name())
+                    .on_hover_text(format!("{} to {}", command.region().min, command.region().max));
+            }
+        });
+    }
 }
 
 impl Editor {
+    /// Editor with the layout of the last session, or the default one
     pub fn new() -> Self {
+        let settings = match EditorSettings::load(SETTINGS_PATH) {
+            Ok(settings) => settings,
+            Err(EditorSettingsError::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => EditorSettings::default(),
+            Err(e) => {
+                log::error!("{}", e);
+                EditorSettings::default()
+            }
+        };
+        let dock_state = Editor::dock_state(&settings.layout).unwrap_or_else(Editor::default_dock_state);
+
+        Self {
+            viewport_rect: (true, egui::Rect::NOTHING),
+            style: None,
+            tree: dock_state,
+            dark_mode: settings.dark_mode,
+            brush: Brush::default(),
+            pick: None,
+            history_action: None,
+            selection: None,
+            renaming: None,
+            edited_material: None,
+            scene_actions: vec![],
+            console_filter: ConsoleFilter::default(),
+        }
+    }
+
+    fn default_dock_state() -> DockState<GuiTab> {
         let mut dock_state = DockState::new(
             vec![GuiTab::GameView]
         );
         dock_state.translations.tab_context_menu.eject_button = "Undock".to_owned();
         let tree = dock_state.main_surface_mut();
-        let [game, _inspector] = tree.split_right(
+        let [game, inspector] = tree.split_right(
             NodeIndex::root(),
             0.75,
             vec![GuiTab::Inspector],
         );
+        let [_, _] = tree.split_below(
+            inspector,
+            0.5,
+            vec![GuiTab::History],
+        );
         let [game, _hierarchy] = tree.split_left(
             game,
             0.2,
@@ -95,41 +627,137 @@ impl Editor {
         let [_, _] = tree.split_below(
             game, 
             0.8, 
-            vec![GuiTab::RendererContext]
+            vec![GuiTab::RendererContext, GuiTab::Console, GuiTab::Profiler]
         );
+        dock_state
+    }
 
-        let mut open_tabs = HashSet::new();
-
-        for node in dock_state[SurfaceIndex::main()].iter() {
-            if let Some(tabs) = node.tabs() {
-                for tab in tabs {
-                    open_tabs.insert(*tab);
+    /// Rebuilds the main surface from saved nodes, `None` when no known tab is left
+    fn dock_state(layout: &[LayoutNode]) -> Option<DockState<GuiTab>> {
+        // unknown tabs are dropped, as the tabs already restored by a previous leaf
+        let mut restored = vec![];
+        let leaves: Vec<Vec<GuiTab>> = layout
+            .iter()
+            .map(|node| match node {
+                LayoutNode::Leaf { tabs, .. } => {
+                    let tabs: Vec<GuiTab> = tabs
+                        .iter()
+                        .filter_map(|name| GuiTab::from_name(name))
+                        .filter(|tab| !restored.contains(tab))
+                        .collect();
+                    restored.extend(&tabs);
+                    tabs
                 }
-            }
+                _ => vec![],
+            })
+            .collect();
+
+        let mut dock_state = DockState::new(first_tabs(layout, &leaves, NodeIndex::root())?);
+        dock_state.translations.tab_context_menu.eject_button = "Undock".to_owned();
+        restore_node(dock_state.main_surface_mut(), layout, &leaves, NodeIndex::root(), NodeIndex::root());
+        Some(dock_state)
+    }
+
+    /// Nodes of the main surface, the tabs of floating windows aren't kept
+    fn layout(dock_state: &DockState<GuiTab>) -> Vec<LayoutNode> {
+        let mut layout: Vec<_> = dock_state
+            .main_surface()
+            .iter()
+            .map(|node| match node {
+                Node::Empty => LayoutNode::Empty,
+                Node::Leaf { tabs, active, .. } => LayoutNode::Leaf {
+                    tabs: tabs.iter().map(GuiTab::name).collect(),
+                    active: active.0,
+                },
+                Node::Horizontal { fraction, .. } => LayoutNode::Horizontal(*fraction),
+                Node::Vertical { fraction, .. } => LayoutNode::Vertical(*fraction),
+            })
+            .collect();
+        // the tree pads its last level with empty nodes, which depends on how it was built
+        while layout.last() == Some(&LayoutNode::Empty) {
+            layout.pop();
         }
+        layout
+    }
 
-        Self {
-            viewport_rect: (true, egui::Rect::NOTHING),
-            style: None,
-            open_tabs,
-            tree: dock_state,
-        }   
+    /// Saves the layout for the next session
+    pub fn save_settings(&self) {
+        let settings = EditorSettings {
+            layout: Editor::layout(&self.tree),
+            dark_mode: self.dark_mode,
+        };
+        if let Err(e) = settings.save(SETTINGS_PATH) {
+            log::error!("{}", e);
+        }
     }
 
-    pub fn run_ui(&mut self, ctx: &egui::Context, game: &Game, renderer: &RendererContext, game_texture: Option<egui::TextureId>) {
+    pub fn run_ui(
+        &mut self,
+        ctx: &egui::Context,
+        game: &mut Game,
+        renderer: &RendererContext,
+        profiler: &mut Profiler,
+        game_texture: Option<egui::TextureId>,
+    ) {
+        if ctx.style().visuals.dark_mode != self.dark_mode {
+            ctx.set_visuals(if self.dark_mode { Visuals::dark() } else { Visuals::light() });
+            // the dock style is made from the egui one
+            self.style = None;
+        }
+
         let mut gui_context = GuiContext {
             viewport_rect: &mut self.viewport_rect,
-            open_tabs: self.open_tabs.clone(),
             game,
             renderer,
             game_texture,
+            brush: &mut self.brush,
+            pick: &mut self.pick,
+            history_action: &mut self.history_action,
+            selection: &mut self.selection,
+            renaming: &mut self.renaming,
+            edited_material: &mut self.edited_material,
+            scene_actions: &mut self.scene_actions,
+            console_filter: &mut self.console_filter,
+            profiler,
         };
 
+        if ctx.input_mut(|input| input.consume_shortcut(&PLAY_SHORTCUT)) {
+            gui_context.toggle_play();
+        }
+        ctx.input_mut(|input| {
+            // Ctrl+Shift+Z also matches Ctrl+Z, so the redo shortcuts are checked first
+            if REDO_SHORTCUTS.iter().any(|shortcut| input.consume_shortcut(shortcut)) {
+                *gui_context.history_action = Some(HistoryAction::Redo);
+            }
+            else if input.consume_shortcut(&UNDO_SHORTCUT) {
+                *gui_context.history_action = Some(HistoryAction::Undo);
+            }
+        });
+
         TopBottomPanel::top("egui_dock::MenuBar").show(ctx, |ui| {
             egui::menu::bar(ui, |ui| {
                 ui.menu_button("View", |ui| {
-
+                    for tab in GuiTab::ALL {
+                        let mut open = self.tree.find_tab(&tab).is_some();
+                        if ui.checkbox(&mut open, tab.name()).changed() {
+                            match self.tree.find_tab(&tab) {
+                                Some(index) if !open => {
+                                    self.tree.remove_tab(index);
+                                }
+                                None if open => self.tree.push_to_first_leaf(tab),
+                                _ => {}
+                            }
+                        }
+                    }
+                    ui.separator();
+                    ui.checkbox(&mut self.dark_mode, "Dark mode");
+                    if ui.button("Reset layout").clicked() {
+                        self.tree = Editor::default_dock_state();
+                        ui.close_menu();
+                    }
                 });
+                ui.separator();
+                gui_context.play_controls(ui);
             })
         });
         CentralPanel::default()
@@ -155,6 +783,29 @@ impl Editor {
             });
     }
 
+    pub fn brush_mut(&mut self) -> &mut Brush {
+        &mut self.brush
+    }
+
+    /// Pixel of the game view clicked since the last call
+    pub fn take_pick(&mut self) -> Option<Vec2> {
+        self.pick.take()
+    }
+
+    /// Undo or redo requested since the last call
+    pub fn take_history_action(&mut self) -> Option<HistoryAction> {
+        self.history_action.take()
+    }
+
+    pub fn set_selection(&mut self, selection: Option<EntityId>) {
+        self.selection = selection;
+    }
+
+    /// Changes of the scene graph requested since the last call
+    pub fn take_scene_actions(&mut self) -> Vec<SceneAction> {
+        std::mem::take(&mut self.scene_actions)
+    }
+
     pub fn viewport_changed(&self) -> bool {
         self.viewport_rect.0
     }
@@ -162,4 +813,222 @@ impl Editor {
     pub fn viewport_rect(&self) -> egui::Rect {
         self.viewport_rect.1
     }
-}
\ No newline at end of file
+}
+
+/// Tabs of the first leaf holding tabs under a saved node, `None` when there is none
+fn first_tabs(layout: &[LayoutNode], leaves: &[Vec<GuiTab>], index: NodeIndex) -> Option<Vec<GuiTab>> {
+    match layout.get(index.0)? {
+        LayoutNode::Empty => None,
+        LayoutNode::Leaf { .. } => Some(leaves[index.0].clone()).filter(|tabs| !tabs.is_empty()),
+        LayoutNode::Horizontal(_) | LayoutNode::Vertical(_) => {
+            first_tabs(layout, leaves, index.left()).or_else(|| first_tabs(layout, leaves, index.right()))
+        }
+    }
+}
+
+/// Splits the leaf at `target`, which holds the first tabs of the saved node at `index`, as the saved node was.
+/// A side of a split without tabs left is dropped, the other side takes its place.
+fn restore_node(tree: &mut Tree<GuiTab>, layout: &[LayoutNode], leaves: &[Vec<GuiTab>], index: NodeIndex, target: NodeIndex) {
+    match layout[index.0] {
+        LayoutNode::Empty => {}
+        LayoutNode::Leaf { active, .. } => {
+            if let Node::Leaf { tabs, active: leaf_active, .. } = &mut tree[target] {
+                *leaf_active = TabIndex(active.min(tabs.len().saturating_sub(1)));
+            }
+        }
+        LayoutNode::Horizontal(fraction) | LayoutNode::Vertical(fraction) => {
+            let (left, right) = (index.left(), index.right());
+            match (first_tabs(layout, leaves, left), first_tabs(layout, leaves, right)) {
+                (Some(_), Some(right_tabs)) => {
+                    let [left_target, right_target] = match layout[index.0] {
+                        LayoutNode::Horizontal(_) => tree.split_right(target, fraction, right_tabs),
+                        _ => tree.split_below(target, fraction, right_tabs),
+                    };
+                    restore_node(tree, layout, leaves, left, left_target);
+                    restore_node(tree, layout, leaves, right, right_target);
+                }
+                (Some(_), None) => restore_node(tree, layout, leaves, left, target),
+                (None, Some(_)) => restore_node(tree, layout, leaves, right, target),
+                (None, None) => {}
+            }
+        }
+    }
+}
+
+/// Durations of the frames of the history, with lines at 60 and 30 FPS
+fn frame_time_graph(ui: &mut Ui, profiler: &Profiler) {
+    const TARGETS: [f32; 2] = [1000.0 / 60.0, 1000.0 / 30.0];
+
+    let (response, painter) = ui.allocate_painter(egui::vec2(ui.available_width(), 100.0), Sense::hover());
+    let rect = response.rect;
+    painter.rect_filled(rect, 0.0, ui.visuals().extreme_bg_color);
+
+    let durations: Vec<f32> = profiler.history().map(|frame| frame.duration).collect();
+    let max = durations.iter().copied().fold(TARGETS[1] * 1.2, f32::max);
+    let y = |duration: f32| rect.bottom() - duration / max * rect.height();
+    for target in TARGETS {
+        painter.hline(rect.x_range(), y(target), Stroke::new(1.0, ui.visuals().weak_text_color()));
+        painter.text(
+            Pos2::new(rect.left() + 2.0, y(target)),
+            Align2::LEFT_BOTTOM,
+            format!("{:.1} ms", target),
+            FontId::monospace(10.0),
+            ui.visuals().weak_text_color(),
+        );
+    }
+
+    // the newest frame is on the right
+    let step = rect.width() / (crate::profiler::PROFILER_HISTORY - 1) as f32;
+    let offset = crate::profiler::PROFILER_HISTORY - durations.len();
+    let points: Vec<Pos2> = durations
+        .iter()
+        .enumerate()
+        .map(|(i, duration)| Pos2::new(rect.left() + (offset + i) as f32 * step, y(*duration)))
+        .collect();
+    painter.add(Shape::line(points, Stroke::new(1.0, ui.visuals().text_color())));
+
+    if let Some(pointer) = response.hover_pos() {
+        let i = ((pointer.x - rect.left()) / step).round() as usize;
+        if let Some(duration) = i.checked_sub(offset).and_then(|i| durations.get(i)) {
+            response.on_hover_text_at_pointer(format!("{:.2} ms", duration));
+        }
+    }
+}
+
+/// Durations with their share of the total
+fn timing_grid(ui: &mut Ui, id: &str, timings: &[Timing], total: f32) {
+    Grid::new(id).num_columns(3).striped(true).show(ui, |ui| {
+        for timing in timings {
+            ui.label(&timing.name);
+            ui.label(format!("{:.2} ms", timing.ms));
+            ui.label(format!("{:.0} %", timing.ms / total.max(f32::EPSILON) * 100.0));
+            ui.end_row();
+        }
+    });
+}
+
+fn level_color(ui: &Ui, level: Level) -> Color32 {
+    match level {
+        Level::Error => ui.visuals().error_fg_color,
+        Level::Warn => ui.visuals().warn_fg_color,
+        Level::Info => ui.visuals().text_color(),
+        Level::Debug | Level::Trace => ui.visuals().weak_text_color(),
+    }
+}
+
+fn entity_kind_name(kind: &EntityKind) -> &'static str {
+    match kind {
+        EntityKind::Empty => "Empty",
+        EntityKind::Camera => "Camera",
+        EntityKind::Sprite(_) => "Sprite",
+        EntityKind::Light { .. } => "Light",
+        EntityKind::Model { .. } => "Model",
+    }
+}
+
+/// Edits a transform with the rotation as Euler angles in degrees, returns true when it changed
+fn transform_editor(ui: &mut Ui, transform: &mut Transform) -> bool {
+    let mut changed = false;
+    let (yaw, pitch, roll) = transform.rotation.to_euler(EulerRot::YXZ);
+    let mut euler = Vec3::new(pitch, yaw, roll) * (180.0 / std::f32::consts::PI);
+    egui::Grid::new("transform").num_columns(4).show(ui, |ui| {
+        ui.label("Position");
+        changed |= vec3_editor(ui, &mut transform.position, 0.1);
+        ui.end_row();
+        ui.label("Rotation");
+        if vec3_editor(ui, &mut euler, 1.0) {
+            let radians = euler * (std::f32::consts::PI / 180.0);
+            transform.rotation = Quat::from_euler(EulerRot::YXZ, radians.y, radians.x, radians.z);
+            changed = true;
+        }
+        ui.end_row();
+        ui.label("Scale");
+        changed |= vec3_editor(ui, &mut transform.scale, 0.01);
+        ui.end_row();
+    });
+    changed
+}
+
+fn vec3_editor(ui: &mut Ui, value: &mut Vec3, speed: f32) -> bool {
+    let mut changed = false;
+    for (axis, component) in ["x: ", "y: ", "z: "].into_iter().zip(value.as_mut()) {
+        changed |= ui.add(DragValue::new(component).speed(speed).prefix(axis)).changed();
+    }
+    changed
+}
+
+fn sprite_params_editor(ui: &mut Ui, params: &mut SpriteParams) -> bool {
+    let mut changed = false;
+    egui::Grid::new("sprite_params").num_columns(4).show(ui, |ui| {
+        ui.label("Position");
+        for (axis, component) in ["x: ", "y: ", "z: "].into_iter().zip(params.position.iter_mut()) {
+            changed |= ui.add(DragValue::new(component).prefix(axis)).changed();
+        }
+        ui.end_row();
+        ui.label("Size");
+        for (axis, component) in ["x: ", "y: ", "z: "].into_iter().zip(params.size.iter_mut()) {
+            changed |= ui.add(DragValue::new(component).clamp_range(1..=MAX_SPRITE_SIZE).prefix(axis)).changed();
+        }
+        ui.end_row();
+        ui.label("Material");
+        changed |= ui.add(DragValue::new(&mut params.material).clamp_range(1..=255)).changed();
+        ui.end_row();
+    });
+    changed
+}
+
+fn material_editor(ui: &mut Ui, material: &mut Material) -> bool {
+    let mut changed = false;
+    egui::Grid::new("material").num_columns(2).show(ui, |ui| {
+        ui.label("Albedo");
+        changed |= ui.color_edit_button_rgb(&mut material.albedo).changed();
+        ui.end_row();
+        ui.label("Emission");
+        changed |= ui.color_edit_button_rgb(&mut material.emission).changed();
+        ui.end_row();
+        ui.label("Roughness");
+        changed |= ui.add(Slider::new(&mut material.roughness, 0.0..=1.0)).changed();
+        ui.end_row();
+        ui.label("Metallic");
+        changed |= ui.add(Slider::new(&mut material.metallic, 0.0..=1.0)).changed();
+        ui.end_row();
+        ui.label("Transparency");
+        changed |= ui.add(Slider::new(&mut material.transparency, 0.0..=1.0)).changed();
+        ui.end_row();
+    });
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layout_round_trip() {
+        let layout = Editor::layout(&Editor::default_dock_state());
+        let dock_state = Editor::dock_state(&layout).expect("the default layout has tabs");
+        assert_eq!(Editor::layout(&dock_state), layout);
+        assert_eq!(dock_state.iter_all_tabs().count(), GuiTab::ALL.len());
+    }
+
+    #[test]
+    fn unknown_tabs_are_dropped() {
+        let leaf = |tabs: &[&str]| LayoutNode::Leaf {
+            tabs: tabs.iter().map(|tab| tab.to_string()).collect(),
+            active: 1,
+        };
+        // the right side only has an unknown tab, the left side takes the whole surface
+        let layout = vec![LayoutNode::Horizontal(0.5), LayoutNode::Vertical(0.3), leaf(&["Removed"]), leaf(&["Console", "Profiler"]), leaf(&["GameView"])];
+        let dock_state = Editor::dock_state(&layout).unwrap();
+        assert_eq!(Editor::layout(&dock_state), vec![
+            LayoutNode::Vertical(0.3),
+            leaf(&["Console", "Profiler"]),
+            LayoutNode::Leaf {
+                tabs: vec!["GameView".to_string()],
+                active: 0,
+            },
+        ]);
+
+        assert!(Editor::dock_state(&[leaf(&["Removed"])]).is_none());
+    }
+}
@@ -0,0 +1,215 @@
+//! Editor settings kept between sessions, stored as text with one setting per line:
+//!
+//! ```text
+//! octo-editor 1
+//! dark_mode true
+//! node horizontal 0.75
+//! node leaf 0 GameView Hierarchy
+//! node leaf 0 Inspector
+//! ```
+//!
+//! The `node` lines are the nodes of the dock tree in index order, the children of node `i` being `2i + 1` and `2i + 2`.
+
+use std::path::Path;
+
+const MAGIC: &str = "octo-editor";
+const VERSION: u32 = 1;
+
+#[derive(Debug, thiserror::Error)]
+pub enum EditorSettingsError {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("not an editor settings file")]
+    InvalidMagic,
+    #[error("unsupported editor settings version {0}")]
+    UnsupportedVersion(u32),
+    #[error("line {0}: {1}")]
+    InvalidLine(usize, String),
+    #[error("the layout isn't a complete tree")]
+    InvalidLayout,
+}
+
+/// Node of the dock tree, with the tabs named by their title
+#[derive(Debug, Clone, PartialEq)]
+pub enum LayoutNode {
+    Empty,
+    Leaf {
+        tabs: Vec<String>,
+        /// Index of the tab shown
+        active: usize,
+    },
+    /// Split in a left and a right child, the fraction is the width of the left one
+    Horizontal(f32),
+    /// Split in a top and a bottom child, the fraction is the height of the top one
+    Vertical(f32),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EditorSettings {
+    pub layout: Vec<LayoutNode>,
+    pub dark_mode: bool,
+}
+
+impl Default for EditorSettings {
+    fn default() -> Self {
+        Self {
+            layout: vec![],
+            dark_mode: true,
+        }
+    }
+}
+
+impl EditorSettings {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, EditorSettingsError> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), EditorSettingsError> {
+        if let Some(parent) = path.as_ref().parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, self.to_string())?;
+        Ok(())
+    }
+
+    pub fn parse(text: &str) -> Result<Self, EditorSettingsError> {
+        let mut lines = text.lines().enumerate().map(|(i, line)| (i + 1, line.trim()));
+        let header = lines.next().map(|(_, line)| line).unwrap_or_default();
+        let version = header
+            .strip_prefix(MAGIC)
+            .ok_or(EditorSettingsError::InvalidMagic)?
+            .trim()
+            .parse()
+            .map_err(|_| EditorSettingsError::InvalidMagic)?;
+        if version != VERSION {
+            return Err(EditorSettingsError::UnsupportedVersion(version));
+        }
+
+        let mut settings = EditorSettings::default();
+        for (number, line) in lines.filter(|(_, line)| !line.is_empty()) {
+            let invalid = |message: &str| EditorSettingsError::InvalidLine(number, message.to_string());
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some("dark_mode") => {
+                    settings.dark_mode = words.next().and_then(|word| word.parse().ok()).ok_or_else(|| invalid("expected true or false"))?;
+                }
+                Some("node") => {
+                    let node = match words.next() {
+                        Some("empty") => LayoutNode::Empty,
+                        Some("leaf") => {
+                            let active = words.next().and_then(|word| word.parse().ok()).ok_or_else(|| invalid("expected the active tab"))?;
+                            LayoutNode::Leaf {
+                                tabs: words.by_ref().map(str::to_string).collect(),
+                                active,
+                            }
+                        }
+                        Some(split @ ("horizontal" | "vertical")) => {
+                            let fraction = words
+                                .next()
+                                .and_then(|word| word.parse::<f32>().ok())
+                                .filter(|fraction| (0.0..=1.0).contains(fraction))
+                                .ok_or_else(|| invalid("expected a fraction between 0 and 1"))?;
+                            match split {
+                                "horizontal" => LayoutNode::Horizontal(fraction),
+                                _ => LayoutNode::Vertical(fraction),
+                            }
+                        }
+                        _ => return Err(invalid("unknown node")),
+                    };
+                    settings.layout.push(node);
+                }
+                // settings of newer versions are ignored
+                _ => {}
+            }
+        }
+
+        if !settings.is_layout_valid() {
+            return Err(EditorSettingsError::InvalidLayout);
+        }
+        Ok(settings)
+    }
+
+    /// True when every split has two children which aren't empty, and every other node is the child of a split
+    fn is_layout_valid(&self) -> bool {
+        self.layout.iter().enumerate().all(|(i, node)| {
+            let under_split = i == 0 || matches!(self.layout[(i - 1) / 2], LayoutNode::Horizontal(_) | LayoutNode::Vertical(_));
+            match node {
+                LayoutNode::Empty => true,
+                LayoutNode::Leaf { .. } => under_split,
+                LayoutNode::Horizontal(_) | LayoutNode::Vertical(_) => under_split && [2 * i + 1, 2 * i + 2]
+                    .iter()
+                    .all(|child| !matches!(self.layout.get(*child), None | Some(LayoutNode::Empty))),
+            }
+        })
+    }
+}
+
+impl std::fmt::Display for EditorSettings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{} {}", MAGIC, VERSION)?;
+        writeln!(f, "dark_mode {}", self.dark_mode)?;
+        for node in &self.layout {
+            match node {
+                LayoutNode::Empty => writeln!(f, "node empty")?,
+                LayoutNode::Leaf { tabs, active } => writeln!(f, "node leaf {} {}", active, tabs.join(" "))?,
+                LayoutNode::Horizontal(fraction) => writeln!(f, "node horizontal {}", fraction)?,
+                LayoutNode::Vertical(fraction) => writeln!(f, "node vertical {}", fraction)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let settings = EditorSettings {
+            layout: vec![
+                LayoutNode::Horizontal(0.75),
+                LayoutNode::Vertical(0.8),
+                LayoutNode::Leaf {
+                    tabs: vec!["Inspector".to_string()],
+                    active: 0,
+                },
+                LayoutNode::Leaf {
+                    tabs: vec!["GameView".to_string(), "Hierarchy".to_string()],
+                    active: 1,
+                },
+                LayoutNode::Leaf {
+                    tabs: vec![],
+                    active: 0,
+                },
+                LayoutNode::Empty,
+            ],
+            dark_mode: false,
+        };
+        assert_eq!(EditorSettings::parse(&settings.to_string()).unwrap(), settings);
+    }
+
+    #[test]
+    fn invalid_settings() {
+        assert!(matches!(EditorSettings::parse(""), Err(EditorSettingsError::InvalidMagic)));
+        assert!(matches!(EditorSettings::parse("octo-editor 2"), Err(EditorSettingsError::UnsupportedVersion(2))));
+        assert!(matches!(
+            EditorSettings::parse("octo-editor 1\nnode vertical 1.5"),
+            Err(EditorSettingsError::InvalidLine(2, _))
+        ));
+        // a split without children
+        assert!(matches!(
+            EditorSettings::parse("octo-editor 1\nnode horizontal 0.5\nnode leaf 0 GameView"),
+            Err(EditorSettingsError::InvalidLayout)
+        ));
+        // a leaf under another leaf
+        assert!(matches!(
+            EditorSettings::parse("octo-editor 1\nnode leaf 0 GameView\nnode leaf 0 Console"),
+            Err(EditorSettingsError::InvalidLayout)
+        ));
+        // unknown settings are skipped
+        let settings = EditorSettings::parse("octo-editor 1\n\nfont_size 12\ndark_mode false").unwrap();
+        assert!(!settings.dark_mode);
+        assert!(settings.layout.is_empty());
+    }
+}
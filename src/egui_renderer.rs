@@ -16,7 +16,7 @@ impl EguiRenderer {
     pub fn new(renderer: &RendererContext, window: Arc<Window>) -> Self {
         let egui_renderer = egui_wgpu::Renderer::new(
             renderer.device(), 
-            wgpu::TextureFormat::Bgra8UnormSrgb,
+            renderer.target_format(),
             None,
             1
         );
@@ -44,8 +44,9 @@ impl EguiRenderer {
         }
     }
 
-    pub fn handle_input(&mut self, window: &Window, event: &winit::event::WindowEvent) {
-        let _ = self.egui_state.on_window_event(window, event);
+    /// Returns true when egui consumed the event, e.g. keys typed in a text field
+    pub fn handle_input(&mut self, window: &Window, event: &winit::event::WindowEvent) -> bool {
+        self.egui_state.on_window_event(window, event).consumed
     }
 
     pub fn render(
@@ -80,7 +81,7 @@ impl EguiRenderer {
             &screen_desc,
         );
 
-        let mut rpass = frame.new_render_pass(wgpu::LoadOp::Load);
+        let mut rpass = frame.new_render_pass("Egui", wgpu::LoadOp::Load);
         self.egui_renderer.render(
             &mut rpass,
             &clipped_primitives[..],
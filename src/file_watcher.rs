@@ -22,8 +22,8 @@ impl FileWatcher {
         // Automatically select the best implementation for the platform.
         let mut watcher = notify::recommended_watcher(|res| {
             match res {
-                Ok(event) => println!("event: {:?}", event),
-                Err(e) => println!("watch error: {:?}", e),
+                Ok(event) => log::debug!("event: {:?}", event),
+                Err(e) => log::error!("watch error: {:?}", e),
             }
         })?;
         
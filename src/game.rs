@@ -1,15 +1,49 @@
 use std::{time::Duration, path::Path};
 
-use glam::{vec2, vec3, Vec2, Vec3};
+use glam::{vec2, vec3, IVec3, UVec3, Vec2, Vec3};
+use slotmap::{SecondaryMap, SlotMap};
 use thiserror::Error;
 use winit::{event::MouseButton, keyboard::KeyCode};
 
 use crate::{
-    camera::Camera, file_watcher::FileWatcher, globals::Globals, inputs::Inputs, renderer_context::{
+    brush::VoxelEdit, camera::Camera, file_watcher::FileWatcher, globals::Globals, history::{Command, History}, inputs::Inputs, renderer_context::{
         BindGroupHandle, Binding, BindingResource, ComputePassDesc, ComputePipelineHandle, Frame, PipelineDesc, RenderPassDesc, RenderPipelineHandle, RendererContext, RendererContextError, Resolution, ShaderHandle, TextureHandle
-    }, sprite::Sprite, system::System, utils::make_relative_path, voxel_world::VoxelWorld 
+    }, palette::{Material, Palette}, ray::{Intersection, Ray, RayHit}, scene::{Entity, EntityId, EntityKind, SceneGraph}, sdf::{SdfScene, MAX_PRIMITIVES, SDF_SHADER}, shader, sprite::{Sprite, SpriteHandle, SpriteParams, SpriteUniform, SPHERE_SHADER}, system::System, terrain::{self, TerrainGenerator, TerrainParams, TerrainRegion}, transform::Transform, utils::make_relative_path, voxel_world::{ChunkMap, Region, VoxelWorld, WorldRepresentation} 
 };
 
+/// Farthest voxel that can be picked, in voxels
+const MAX_PICK_DISTANCE: f32 = 512.0;
+/// Radius of the sphere standing for a light when picking entities
+const LIGHT_PICK_RADIUS: f32 = 1.0;
+/// Half size of the square facing the camera standing for an empty entity when picking entities
+const EMPTY_PICK_HALF_SIZE: f32 = 0.5;
+
+/// Time simulated by a step while paused, in seconds
+const STEP_DELTA_TIME: f32 = 1.0 / 60.0;
+
+/// Whether the game is tested from the editor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlayState {
+    /// Edited, the camera can still be moved
+    Editing,
+    Playing,
+    /// Frozen until resumed or stepped
+    Paused,
+}
+
+/// State restored when the game stops playing
+struct PlaySnapshot {
+    chunks: ChunkMap,
+    materials: Vec<Material>,
+    camera: Transform,
+    fov: f32,
+    history: History,
+    scene: SceneGraph,
+    /// State of the sprites and their entity
+    sprites: SecondaryMap<SpriteHandle, ((SpriteParams, bool), EntityId)>,
+    /// Sprites of the snapshot removed during the play, kept to be restored
+    removed_sprites: Vec<(SpriteHandle, Sprite)>,
+}
 
 #[derive(Error, Debug)]
 pub enum GameError {
@@ -30,7 +64,26 @@ pub struct Game {
     render_shader: Option<ShaderHandle>,
     render_pipeline: Option<RenderPipelineHandle>,
     render_bind_group: Option<BindGroupHandle>,
-    sprites: Vec<Sprite>,
+    /// With the entity mirroring each sprite
+    sprites: SlotMap<SpriteHandle, (Sprite, EntityId)>,
+    /// Terrain generated on the GPU at the next frame
+    terrain_passes: Vec<Sprite<TerrainRegion>>,
+    dispatched_terrain_passes: Vec<Sprite<TerrainRegion>>,
+    /// Shapes voxelized on the GPU at the next frame
+    sdf_passes: Vec<Sprite<SdfScene>>,
+    dispatched_sdf_passes: Vec<Sprite<SdfScene>>,
+    /// Sprites added during a play, destroyed before the next frame
+    released_sprites: Vec<Sprite>,
+    /// Set when passes or sprites wrote into the world atlas since the last read back
+    gpu_writes: bool,
+    history: History,
+    scene: SceneGraph,
+    camera_entity: EntityId,
+    play_state: PlayState,
+    /// Captured when the game started playing
+    play_snapshot: Option<PlaySnapshot>,
+    /// Set when a single update was requested while paused
+    step_requested: bool,
     file_watcher: FileWatcher,
 }
 
@@ -56,6 +109,9 @@ impl Game {
 
         let globals = Globals::new(renderer);
 
+        let mut scene = SceneGraph::new();
+        let camera_entity = scene.add("Camera", EntityKind::Camera, camera.transform, None);
+
         // render texture
         let output_texture = renderer.new_texture(
             &wgpu::TextureDescriptor {
@@ -86,19 +142,30 @@ impl Game {
             render_shader: None,
             render_pipeline : None,
             render_bind_group: None,
-            sprites: vec![],
+            sprites: SlotMap::default(),
+            terrain_passes: vec![],
+            dispatched_terrain_passes: vec![],
+            sdf_passes: vec![],
+            dispatched_sdf_passes: vec![],
+            released_sprites: vec![],
+            gpu_writes: false,
+            history: History::new(),
+            scene,
+            camera_entity,
+            play_state: PlayState::Editing,
+            play_snapshot: None,
+            step_requested: false,
             file_watcher,
         }
     }
 
     fn create_shader<P: AsRef<Path>>(renderer: &mut RendererContext, path: P) -> Option<ShaderHandle> {
-        let shader_src = std::fs::read_to_string(path).unwrap();
-        match renderer.new_shader(shader_src.as_str()) {
+        match shader::load(renderer, path) {
             Ok(shader) => {
                 return Some(shader);
             }
             Err(e) => {
-                println!("error: {}", e);
+                log::error!("{}", e);
             }
         }
 
@@ -131,41 +198,175 @@ impl Game {
         )
     }
 
+    fn raymarcher_shader_path(representation: WorldRepresentation) -> &'static str {
+        match representation {
+            WorldRepresentation::Grid => "src/shaders/voxel_renderer.wgsl",
+            WorldRepresentation::Octree => "src/shaders/voxel_renderer_octree.wgsl",
+            WorldRepresentation::Brickmap => "src/shaders/voxel_renderer_brickmap.wgsl",
+        }
+    }
+
     fn create_compute_pipeline(
         renderer: &mut RendererContext, 
         shader: ShaderHandle, 
         globals: &Globals, 
-        camera: &Camera
+        camera: &Camera,
+        representation: WorldRepresentation,
     ) -> ComputePipelineHandle{
-        renderer.new_compute_pipeline(
-            &PipelineDesc {
-                shader,
-                bindings_layout: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: VoxelWorld::binding_type(),
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::StorageTexture {
-                            access: wgpu::StorageTextureAccess::WriteOnly,
-                            format: wgpu::TextureFormat::Rgba8Unorm,
-                            view_dimension: wgpu::TextureViewDimension::D2,
+        let output_binding = wgpu::BindGroupLayoutEntry {
+            binding: 1,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::StorageTexture {
+                access: wgpu::StorageTextureAccess::WriteOnly,
+                format: wgpu::TextureFormat::Rgba8Unorm,
+                view_dimension: wgpu::TextureViewDimension::D2,
+            },
+            count: None,
+        };
+        let camera_binding = wgpu::BindGroupLayoutEntry {
+            binding: 2,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: camera.binding_type(),
+            count: None,
+        };
+        let palette_binding = wgpu::BindGroupLayoutEntry {
+            binding: 4,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: Palette::binding_type(),
+            count: None,
+        };
+
+        match representation {
+            WorldRepresentation::Grid => renderer.new_compute_pipeline(
+                &PipelineDesc {
+                    shader,
+                    bindings_layout: &[
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 0,
+                            visibility: wgpu::ShaderStages::COMPUTE,
+                            ty: VoxelWorld::binding_type(),
+                            count: None,
                         },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 2,
-                        visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: camera.binding_type(),
-                        count: None,
-                    },
-                ]
-            }
-        )
+                        output_binding,
+                        camera_binding,
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 3,
+                            visibility: wgpu::ShaderStages::COMPUTE,
+                            ty: VoxelWorld::chunk_table_binding_type(),
+                            count: None,
+                        },
+                        palette_binding,
+                    ]
+                }
+            ),
+            WorldRepresentation::Octree | WorldRepresentation::Brickmap => renderer.new_compute_pipeline(
+                &PipelineDesc {
+                    shader,
+                    bindings_layout: &[
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 0,
+                            visibility: wgpu::ShaderStages::COMPUTE,
+                            ty: VoxelWorld::structure_binding_type(),
+                            count: None,
+                        },
+                        output_binding,
+                        camera_binding,
+                        palette_binding,
+                    ]
+                }
+            ),
+        }
+    }
+
+    /// Switches the world representation and the raymarcher rendering it
+    pub fn set_world_representation(&mut self, renderer: &mut RendererContext, representation: WorldRepresentation) {
+        // the other representations are built from the CPU chunks, which miss what the sprites generated
+        self.sync_world(renderer);
+        self.world.set_representation(representation);
+
+        if let Some(compute_shader) = self.compute_shader {
+            renderer.destroy_shader(compute_shader);
+        }
+        if let Some(compute_pipeline) = self.compute_pipeline {
+            renderer.destroy_compute_pipeline(compute_pipeline);
+        }
+        self.compute_shader = Game::create_shader(renderer, Game::raymarcher_shader_path(representation));
+        self.compute_pipeline = self.compute_shader
+            .map(|shader| {
+                Game::create_compute_pipeline(
+                    renderer,
+                    shader,
+                    &self.globals,
+                    &self.camera,
+                    representation
+                )
+            });
+    }
+
+    /// Copies the voxels generated on the GPU into the CPU world
+    pub fn read_back_world(&mut self, renderer: &RendererContext) {
+        if let Err(e) = self.world.read_back(renderer) {
+            log::error!("{}", e);
+        }
+        self.gpu_writes = false;
+    }
+
+    /// Reads back the world if the GPU generated voxels the CPU copy misses, before picking or editing it
+    pub fn sync_world(&mut self, renderer: &RendererContext) {
+        if self.gpu_writes {
+            self.read_back_world(renderer);
+        }
+    }
+
+    /// Applies the edits of a brush as a single command of the history
+    pub fn apply_edits(&mut self, name: &str, edits: &[VoxelEdit]) {
+        if let Some(region) = Region::bounding(edits.iter().map(|edit| edit.coord)) {
+            self.record_edit(name, region, |world| {
+                for edit in edits {
+                    world.set_voxel_at(edit.new_value, &edit.coord);
+                }
+            });
+        }
+    }
+
+    /// Runs an edit of the CPU world which only changes voxels of the region, so that it can be undone.
+    /// Voxels written by GPU passes aren't recorded.
+    pub fn record_edit<F: FnOnce(&mut VoxelWorld)>(&mut self, name: &str, region: Region, edit: F) {
+        let command = Command::record(&mut self.world, name, region, edit);
+        self.history.push(command);
+    }
+
+    /// Changes a material of the palette so that it can be undone.
+    /// With `merge`, the change is folded into the previous change of the material, to record a drag as a single command.
+    pub fn set_material(&mut self, value: u32, material: Material, merge: bool) {
+        let command = Command::record(&mut self.world, format!("Material {}", value), Region::new(IVec3::ZERO, IVec3::ZERO), |world| {
+            world.palette_mut().set(value, material);
+        });
+        if merge {
+            self.history.push_merged(command);
+        }
+        else {
+            self.history.push(command);
+        }
+    }
+
+    /// Reverts the last recorded edit, returns its name
+    pub fn undo(&mut self) -> Option<&str> {
+        self.history.undo(&mut self.world)
+    }
+
+    /// Applies the last undone edit again, returns its name
+    pub fn redo(&mut self) -> Option<&str> {
+        self.history.redo(&mut self.world)
+    }
+
+    pub fn history(&self) -> &History {
+        &self.history
+    }
+
+    /// Forgets the recorded edits, when the world is replaced
+    pub fn clear_history(&mut self) {
+        self.history.clear();
     }
 
     pub fn hot_reload(&mut self, renderer: &mut RendererContext) {
@@ -195,7 +396,7 @@ impl Game {
                                 }
                                 self.compute_shader = Game::create_shader(renderer, "src/shaders/compute.wgsl");
                                 if let Some(compute_shader) = self.compute_shader {
-                                    self.compute_pipeline = Some(Game::create_compute_pipeline(renderer, compute_shader, &self.globals, &self.camera))
+                                    self.compute_pipeline = Some(Game::create_compute_pipeline(renderer, compute_shader, &self.globals, &self.camera, self.world.representation()))
                                 }
                             }
                         }
@@ -208,6 +409,289 @@ impl Game {
     pub fn game_texture(&self) -> TextureHandle {
         self.output_texture
     }
+
+    pub fn camera_mut(&mut self) -> &mut Camera {
+        &mut self.camera
+    }
+
+    pub fn camera(&self) -> &Camera {
+        &self.camera
+    }
+
+    /// Voxel under a pixel of the game texture, with the origin at the top left corner.
+    /// Where no voxel is met, the ground plane at y = 0 is hit instead so an empty world can be built on.
+    pub fn pick(&self, pixel: Vec2) -> Option<RayHit> {
+        let ray = self.camera.screen_ray(pixel);
+        let ray = Ray::new(ray.origin, ray.direction.normalize());
+        self.world.raycast(&ray, MAX_PICK_DISTANCE).or_else(|| {
+            let hit = ray.intersect_plane(Vec3::ZERO, Vec3::Y).filter(|hit| hit.distance <= MAX_PICK_DISTANCE)?;
+            Some(RayHit {
+                voxel: (ray.at(hit.distance) - hit.normal * 0.5).floor().as_ivec3(),
+                normal: hit.normal.round().as_ivec3(),
+                distance: hit.distance,
+                value: 0,
+            })
+        })
+    }
+
+    /// Nearest entity under a pixel of the game texture. Sprites and models are hit by their bounds,
+    /// lights by a small sphere and empty entities by a square facing the camera.
+    pub fn pick_entity(&self, pixel: Vec2) -> Option<EntityId> {
+        let ray = self.camera.screen_ray(pixel);
+        self.scene
+            .iter()
+            .filter_map(|(id, entity)| {
+                let transform = self.scene.world_transform(id);
+                let hit = match entity.kind {
+                    EntityKind::Camera => None,
+                    EntityKind::Sprite(handle) => {
+                        let size = self.sprites.get(handle)?.0.params().region().size();
+                        intersect_bounds(&ray, &transform, size.as_vec3())
+                    }
+                    EntityKind::Model { region } => intersect_bounds(&ray, &transform, region.size().as_vec3()),
+                    EntityKind::Light { .. } => ray.intersect_sphere(transform.position, LIGHT_PICK_RADIUS),
+                    EntityKind::Empty => {
+                        let right = self.camera.transform.right() * EMPTY_PICK_HALF_SIZE;
+                        let up = self.camera.transform.up() * EMPTY_PICK_HALF_SIZE;
+                        let p = transform.position;
+                        ray.intersect_triangle(p - right - up, p + right - up, p + right + up)
+                            .or_else(|| ray.intersect_triangle(p - right - up, p + right + up, p - right + up))
+                    }
+                };
+                Some((hit?.distance, id))
+            })
+            .min_by(|(a, _), (b, _)| a.total_cmp(b))
+            .map(|(_, id)| id)
+    }
+
+    pub fn world(&self) -> &VoxelWorld {
+        &self.world
+    }
+
+    pub fn world_mut(&mut self) -> &mut VoxelWorld {
+        &mut self.world
+    }
+
+    /// Adds a generator and a root entity for it, its bounds are allocated in the world
+    pub fn add_sprite(&mut self, sprite: Sprite) -> SpriteHandle {
+        let region = sprite.params().region();
+        self.world.allocate_region(region.min, region.max);
+        let name = sprite
+            .shader_path()
+            .file_stem()
+            .map_or("Sprite".to_string(), |stem| stem.to_string_lossy().into_owned());
+        let scene = &mut self.scene;
+        self.sprites.insert_with_key(|handle| {
+            let id = scene.add(name, EntityKind::Sprite(handle), Transform::from_position(region.min.as_vec3()), None);
+            (sprite, id)
+        })
+    }
+
+    /// The voxels already generated by the sprite are kept, the children of its entity move to its parent
+    pub fn remove_sprite(&mut self, renderer: &mut RendererContext, handle: SpriteHandle) -> bool {
+        match self.sprites.remove(handle) {
+            Some((sprite, id)) => {
+                self.release_sprite(renderer, handle, sprite);
+                self.remove_sprite_entity(id);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The sprites captured by [`Game::play`] are kept until the play stops, to be restored
+    fn release_sprite(&mut self, renderer: &mut RendererContext, handle: SpriteHandle, sprite: Sprite) {
+        match &mut self.play_snapshot {
+            Some(snapshot) if snapshot.sprites.contains_key(handle) => snapshot.removed_sprites.push((handle, sprite)),
+            _ => sprite.destroy(renderer),
+        }
+    }
+
+    fn remove_sprite_entity(&mut self, id: EntityId) {
+        let parent = self.scene.get(id).and_then(Entity::parent);
+        let children = self.scene.get(id).map(|entity| entity.children().to_vec()).unwrap_or_default();
+        for child in children {
+            self.scene.set_parent(child, parent);
+        }
+        self.scene.remove(id);
+    }
+
+    /// Entity mirroring the sprite
+    pub fn sprite_entity(&self, handle: SpriteHandle) -> Option<EntityId> {
+        self.sprites.get(handle).map(|(_, id)| *id)
+    }
+
+    /// Changes to the params are uploaded before the next frame
+    pub fn sprite_mut(&mut self, handle: SpriteHandle) -> Option<&mut Sprite> {
+        self.sprites.get_mut(handle).map(|(sprite, _)| sprite)
+    }
+
+    pub fn clear_sprites(&mut self, renderer: &mut RendererContext) {
+        let handles: Vec<_> = self.sprites.keys().collect();
+        for handle in handles {
+            self.remove_sprite(renderer, handle);
+        }
+    }
+
+    pub fn play_state(&self) -> PlayState {
+        self.play_state
+    }
+
+    /// Starts playing, or resumes when paused. The world and camera are captured to be restored by [`Game::stop`],
+    /// call [`Game::sync_world`] before so that the voxels generated on the GPU are captured too.
+    pub fn play(&mut self) {
+        if self.play_state == PlayState::Editing {
+            self.play_snapshot = Some(PlaySnapshot {
+                chunks: self.world.chunks().clone(),
+                materials: self.world.palette().materials().to_vec(),
+                camera: self.camera.transform,
+                fov: self.camera.fov(),
+                history: self.history.clone(),
+                scene: self.scene.clone(),
+                sprites: self.sprites.iter().map(|(handle, (sprite, id))| (handle, (sprite.state(), *id))).collect(),
+                removed_sprites: vec![],
+            });
+        }
+        self.play_state = PlayState::Playing;
+    }
+
+    pub fn pause(&mut self) {
+        if self.play_state == PlayState::Playing {
+            self.play_state = PlayState::Paused;
+        }
+    }
+
+    /// Runs a single update while paused, pausing the game if it was playing
+    pub fn step(&mut self) {
+        match self.play_state {
+            PlayState::Editing => return,
+            PlayState::Playing => self.pause(),
+            PlayState::Paused => {}
+        }
+        self.step_requested = true;
+    }
+
+    /// Goes back to editing, restoring the world, the camera, the history, the scene and the sprites captured by [`Game::play`]
+    pub fn stop(&mut self) {
+        self.play_state = PlayState::Editing;
+        self.step_requested = false;
+        if let Some(snapshot) = self.play_snapshot.take() {
+            self.world.set_chunks(snapshot.chunks);
+            self.world.palette_mut().set_materials(&snapshot.materials);
+            self.camera.transform = snapshot.camera;
+            self.camera.set_fov(snapshot.fov);
+            self.history = snapshot.history;
+            self.scene = snapshot.scene;
+            // the voxels generated on the GPU during the play are overwritten
+            self.gpu_writes = false;
+
+            let added: Vec<_> = self.sprites.keys().filter(|handle| !snapshot.sprites.contains_key(*handle)).collect();
+            for handle in added {
+                let (sprite, _) = self.sprites.remove(handle).unwrap();
+                self.released_sprites.push(sprite);
+            }
+            for (handle, sprite) in snapshot.removed_sprites {
+                let (_, id) = snapshot.sprites[handle];
+                // removed sprites get a new handle, their restored entity must point to it
+                let handle = self.sprites.insert((sprite, id));
+                if let Some(entity) = self.scene.get_mut(id) {
+                    entity.kind = EntityKind::Sprite(handle);
+                }
+            }
+            for (state, id) in snapshot.sprites.values() {
+                let Some(EntityKind::Sprite(handle)) = self.scene.get(*id).map(|entity| entity.kind) else {
+                    continue;
+                };
+                if let Some((sprite, _)) = self.sprites.get_mut(handle) {
+                    sprite.restore(*state);
+                }
+            }
+        }
+    }
+
+    pub fn scene(&self) -> &SceneGraph {
+        &self.scene
+    }
+
+    /// Entities mirroring the camera and the sprites are moved back to them at every update
+    pub fn scene_mut(&mut self) -> &mut SceneGraph {
+        &mut self.scene
+    }
+
+    pub fn camera_entity(&self) -> EntityId {
+        self.camera_entity
+    }
+
+    /// Removes the entity and its descendants, with their sprites.
+    /// The camera can't be deleted, nor the entities above it.
+    pub fn delete_entity(&mut self, renderer: &mut RendererContext, id: EntityId) -> bool {
+        if !self.scene.contains(id) || self.scene.is_ancestor(id, self.camera_entity) {
+            return false;
+        }
+        for entity in self.scene.remove(id) {
+            if let EntityKind::Sprite(handle) = entity.kind {
+                if let Some((sprite, _)) = self.sprites.remove(handle) {
+                    self.release_sprite(renderer, handle, sprite);
+                }
+            }
+        }
+        true
+    }
+
+    /// Moves the entities mirroring objects of the game to them
+    fn sync_scene(&mut self) {
+        self.scene.set_world_transform(self.camera_entity, &self.camera.transform);
+        for (sprite, id) in self.sprites.values() {
+            let mut transform = self.scene.world_transform(*id);
+            transform.position = IVec3::from_array(sprite.params().position).as_vec3();
+            self.scene.set_world_transform(*id, &transform);
+        }
+    }
+
+    /// Fills a region of the world with terrain generated on the CPU
+    pub fn generate_terrain(&mut self, params: TerrainParams, region: &Region) {
+        self.record_edit("Terrain", *region, |world| TerrainGenerator::new(params).generate(world, region));
+    }
+
+    /// Fills a region of the world with terrain generated on the GPU during the next frame
+    pub fn generate_terrain_gpu(&mut self, renderer: &mut RendererContext, params: TerrainParams, region: Region) {
+        // the pass can only write in allocated chunks
+        self.world.allocate_region(region.min, region.max);
+        terrain::set_materials(self.world.palette_mut());
+        match Sprite::new(renderer, &self.world, terrain::TERRAIN_SHADER, TerrainRegion { params, region }) {
+            Ok(pass) => self.terrain_passes.push(pass),
+            Err(e) => log::error!("{}", e),
+        }
+    }
+
+    /// Voxelizes the shapes of the scene on the CPU
+    pub fn voxelize(&mut self, scene: &SdfScene) {
+        self.record_edit("Voxelize", scene.bounds(), |world| scene.voxelize(world));
+    }
+
+    /// Voxelizes the shapes of the scene on the GPU during the next frame
+    pub fn voxelize_gpu(&mut self, renderer: &mut RendererContext, scene: &SdfScene) {
+        if scene.operations().len() > MAX_PRIMITIVES {
+            log::error!("{} primitives, at most {} can be voxelized on the GPU", scene.operations().len(), MAX_PRIMITIVES);
+            return;
+        }
+        // the pass can only write in allocated chunks
+        let region = scene.bounds();
+        self.world.allocate_region(region.min, region.max);
+        match Sprite::new(renderer, &self.world, SDF_SHADER, scene.clone()) {
+            Ok(pass) => self.sdf_passes.push(pass),
+            Err(e) => log::error!("{}", e),
+        }
+    }
+}
+
+/// Box of the given size with a corner at the origin of the transform, as the voxels of sprites and models
+fn intersect_bounds(ray: &Ray, transform: &Transform, size: Vec3) -> Option<Intersection> {
+    let center = Transform {
+        position: transform.compute_matrix().transform_point3(size * 0.5),
+        ..*transform
+    };
+    ray.intersect_obb(&center, size * 0.5)
 }
 
 impl System for Game {
@@ -221,24 +705,42 @@ impl System for Game {
                     &self.globals
                 ))
             });
-        self.compute_shader = Game::create_shader(renderer,"src/shaders/voxel_renderer.wgsl");
+        self.compute_shader = Game::create_shader(renderer, Game::raymarcher_shader_path(self.world.representation()));
         self.compute_pipeline = self.compute_shader
             .and_then(|shader| {
                 Some(Game::create_compute_pipeline(
                     renderer, 
                     shader, 
                     &self.globals, 
-                    &self.camera
+                    &self.camera,
+                    self.world.representation()
                 ))
             });
 
         self.camera.transform.position = Vec3::new(32.0, 16.0, 32.0);
         self.camera.transform.look_at(vec3(16.0, 16.0, 16.0), Vec3::Y);
 
-        self.sprites.push(Sprite::new(renderer, &self.world));
+        match Sprite::new(renderer, &self.world, SPHERE_SHADER, SpriteParams::new(IVec3::ZERO, UVec3::splat(32), 255)) {
+            Ok(sprite) => {
+                self.add_sprite(sprite);
+            }
+            Err(e) => log::error!("{}", e),
+        }
     }
 
     fn update(&mut self, delta_time: f32) {
+        let delta_time = match self.play_state {
+            PlayState::Editing | PlayState::Playing => delta_time,
+            PlayState::Paused if self.step_requested => {
+                self.step_requested = false;
+                STEP_DELTA_TIME
+            }
+            PlayState::Paused => {
+                self.inputs.reset();
+                self.sync_scene();
+                return;
+            }
+        };
         let speed = 25.0;
         let rot_speed = 10.0;
 
@@ -268,33 +770,93 @@ impl System for Game {
         }
 
         self.inputs.reset();
+        self.sync_scene();
     }
 
     /// Prepare resources for rendering
     fn prepare_rendering(&mut self, renderer: &mut RendererContext) {
+        for pass in self.dispatched_terrain_passes.drain(..) {
+            pass.destroy(renderer);
+        }
+        for pass in self.dispatched_sdf_passes.drain(..) {
+            pass.destroy(renderer);
+        }
+        for sprite in self.released_sprites.drain(..) {
+            sprite.destroy(renderer);
+        }
+        for (sprite, _) in self.sprites.values_mut() {
+            // cleared on the CPU, the read back below keeps these pending voxels over what the GPU wrote
+            if let Some(region) = sprite.take_stale_region() {
+                for coord in region.iter() {
+                    self.world.set_voxel_at(0, &coord);
+                }
+            }
+            if sprite.is_dirty() {
+                // sprites can only write in allocated chunks
+                let region = sprite.params().region();
+                self.world.allocate_region(region.min, region.max);
+                sprite.update_buffer(renderer);
+            }
+        }
         self.camera.update_buffer(renderer);
+        // the uploaded boxes around the edited voxels would overwrite what the GPU wrote next to them,
+        // and the acceleration structures are built from the CPU chunks
+        if self.gpu_writes && (self.world.has_pending_edits() || self.world.representation() != WorldRepresentation::Grid) {
+            self.read_back_world(renderer);
+        }
         self.world.update_texture(renderer);
+        let palette_buffer = self.world.palette().get_buffer().unwrap();
         
         if let Some(compute_bind_group) = self.compute_bind_group {
             renderer.destroy_bind_group(compute_bind_group);
         }
         
-        self.compute_bind_group = Some(renderer.new_compute_bind_group(
-            self.compute_pipeline.unwrap(), 
-            &[
-            Binding {
-                binding: 0,
-                resource: BindingResource::Texture(self.world.get_texture()),
-            },
-            Binding {
-                binding: 1,
-                resource: BindingResource::Texture(self.output_texture),
-            },
-            Binding {
-                binding: 2,
-                resource: BindingResource::Buffer(self.camera.get_buffer()),
-            }]
-        ));
+        self.compute_bind_group = Some(match self.world.representation() {
+            WorldRepresentation::Grid => renderer.new_compute_bind_group(
+                self.compute_pipeline.unwrap(), 
+                &[
+                Binding {
+                    binding: 0,
+                    resource: BindingResource::Texture(self.world.get_texture()),
+                },
+                Binding {
+                    binding: 1,
+                    resource: BindingResource::Texture(self.output_texture),
+                },
+                Binding {
+                    binding: 2,
+                    resource: BindingResource::Buffer(self.camera.get_buffer()),
+                },
+                Binding {
+                    binding: 3,
+                    resource: BindingResource::Buffer(self.world.get_chunk_table()),
+                },
+                Binding {
+                    binding: 4,
+                    resource: BindingResource::Buffer(palette_buffer),
+                }]
+            ),
+            WorldRepresentation::Octree | WorldRepresentation::Brickmap => renderer.new_compute_bind_group(
+                self.compute_pipeline.unwrap(), 
+                &[
+                Binding {
+                    binding: 0,
+                    resource: BindingResource::Buffer(self.world.get_structure_buffer().unwrap()),
+                },
+                Binding {
+                    binding: 1,
+                    resource: BindingResource::Texture(self.output_texture),
+                },
+                Binding {
+                    binding: 2,
+                    resource: BindingResource::Buffer(self.camera.get_buffer()),
+                },
+                Binding {
+                    binding: 4,
+                    resource: BindingResource::Buffer(palette_buffer),
+                }]
+            ),
+        });
 
         if let Some(render_bind_group) = self.render_bind_group {
             renderer.destroy_bind_group(render_bind_group);
@@ -315,19 +877,24 @@ impl System for Game {
     }
 
     fn render(&mut self, frame: &mut Frame) {
-        for sprite in &self.sprites {
-            let mut cpass = frame.begin_compute_pass(
-                &ComputePassDesc {
-                pipeline: sprite.compute_pipeline,
-                bind_group: sprite.compute_bind_group,
-            });
-            cpass.dispatch(32, 32, 32);
+        for mut pass in self.terrain_passes.drain(..) {
+            self.gpu_writes |= pass.dispatch(frame);
+            self.dispatched_terrain_passes.push(pass);
+        }
+        for mut pass in self.sdf_passes.drain(..) {
+            self.gpu_writes |= pass.dispatch(frame);
+            self.dispatched_sdf_passes.push(pass);
+        }
+
+        for (sprite, _) in self.sprites.values_mut() {
+            self.gpu_writes |= sprite.dispatch(frame);
         }
 
         // Compute pass
         {
             let mut cpass = frame.begin_compute_pass(
                 &ComputePassDesc {
+                label: "Raymarch",
                 pipeline: self.compute_pipeline.unwrap(),
                 bind_group: self.compute_bind_group.unwrap(),
             });
@@ -339,6 +906,7 @@ impl System for Game {
         {
             let mut rpass = frame.begin_render_pass(
                 &RenderPassDesc {
+                label: "Quad",
                 pipeline: self.render_pipeline.unwrap(),
                 bind_group: self.render_bind_group.unwrap(),
                 load_op: wgpu::LoadOp::Clear(wgpu::Color::GREEN),
@@ -396,4 +964,152 @@ impl System for Game {
         self.camera.set_size(Vec2::new(resolution.width as f32, resolution.height as f32));
         self.camera.update_buffer(renderer);
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sprite::CUBE_SHADER, test_utils::{headless_renderer, storage_renderer}};
+
+    #[test]
+    fn stop_restores_the_state_captured_at_play() {
+        let Some(mut renderer) = headless_renderer("stop_restores_the_state_captured_at_play") else {
+            return;
+        };
+
+        let mut game = Game::new(&mut renderer);
+        game.apply_edits("Place", &[VoxelEdit {
+            coord: IVec3::ZERO,
+            old_value: 0,
+            new_value: 1,
+        }]);
+        game.camera_mut().transform.position = Vec3::new(1.0, 2.0, 3.0);
+
+        game.play();
+        assert_eq!(game.play_state(), PlayState::Playing);
+        game.world_mut().set_voxel_at(0, &IVec3::ZERO);
+        game.world_mut().set_voxel_at(5, &IVec3::ONE);
+        game.camera_mut().transform.position = Vec3::ZERO;
+        game.apply_edits("Place", &[VoxelEdit {
+            coord: IVec3::X,
+            old_value: 0,
+            new_value: 2,
+        }]);
+
+        // paused games only update when stepped
+        game.pause();
+        game.on_key_down(KeyCode::KeyW);
+        game.update(1.0);
+        assert_eq!(game.camera().transform.position, Vec3::ZERO);
+        game.step();
+        game.update(1.0);
+        assert_ne!(game.camera().transform.position, Vec3::ZERO);
+        assert_eq!(game.play_state(), PlayState::Paused);
+
+        game.stop();
+        assert_eq!(game.play_state(), PlayState::Editing);
+        assert_eq!(game.world().get_voxel_at(&IVec3::ZERO), 1);
+        assert_eq!(game.world().get_voxel_at(&IVec3::ONE), 0);
+        assert_eq!(game.world().get_voxel_at(&IVec3::X), 0);
+        assert_eq!(game.camera().transform.position, Vec3::new(1.0, 2.0, 3.0));
+        assert_eq!(game.history().undo_commands().count(), 1);
+    }
+
+    #[test]
+    fn picking() {
+        let Some(mut renderer) = headless_renderer("picking") else {
+            return;
+        };
+
+        let mut game = Game::new(&mut renderer);
+        game.world_mut().set_voxel_at(3, &IVec3::ZERO);
+        let light = game.scene_mut().add("Light", EntityKind::Light { color: Vec3::ONE, intensity: 1.0 }, Transform::from_position(Vec3::new(0.5, 0.5, -5.0)), None);
+        let center = game.camera().size() * 0.5;
+
+        // looking toward +Z at the voxel, through the light
+        game.camera_mut().transform = Transform::from_position(Vec3::new(0.5, 0.5, -10.0));
+        let hit = game.pick(center).unwrap();
+        assert_eq!((hit.voxel, hit.normal, hit.value), (IVec3::ZERO, IVec3::NEG_Z, 3));
+        assert!((hit.distance - 10.0).abs() < 1e-4);
+        assert_eq!(game.pick_entity(center), Some(light));
+
+        game.scene_mut().remove(light);
+        assert_eq!(game.pick_entity(center), None);
+        game.world_mut().set_voxel_at(0, &IVec3::ZERO);
+        // parallel to the ground
+        assert_eq!(game.pick(center), None);
+
+        // looking down at the ground
+        game.camera_mut().transform.position = Vec3::new(0.5, 10.0, 0.5);
+        game.camera_mut().transform.rotation = glam::Quat::from_rotation_x(std::f32::consts::FRAC_PI_2);
+        let hit = game.pick(center).unwrap();
+        assert_eq!((hit.voxel, hit.normal, hit.value), (IVec3::NEG_Y, IVec3::Y, 0));
+        assert_eq!(hit.adjacent_voxel(), IVec3::ZERO);
+    }
+
+    #[test]
+    fn stop_restores_the_scene_and_the_sprites() {
+        let Some(mut renderer) = headless_renderer("stop_restores_the_scene_and_the_sprites") else {
+            return;
+        };
+
+        let mut game = Game::new(&mut renderer);
+        game.init(&mut renderer);
+        let params = SpriteParams::new(IVec3::ZERO, UVec3::splat(4), 1);
+        let moved = game.add_sprite(Sprite::new(&mut renderer, game.world(), SPHERE_SHADER, params).unwrap());
+        let removed = game.add_sprite(Sprite::new(&mut renderer, game.world(), SPHERE_SHADER, params).unwrap());
+        let moved_entity = game.sprite_entity(moved).unwrap();
+        let removed_entity = game.sprite_entity(removed).unwrap();
+        let entity_count = game.scene().iter().count();
+
+        game.play();
+        game.sprite_mut(moved).unwrap().set_params(SpriteParams::new(IVec3::splat(8), UVec3::splat(4), 2));
+        assert!(game.delete_entity(&mut renderer, removed_entity));
+        let added = game.add_sprite(Sprite::new(&mut renderer, game.world(), SPHERE_SHADER, params).unwrap());
+        game.scene_mut().rename(moved_entity, "Moved");
+
+        game.stop();
+        assert_eq!(game.sprite_mut(moved).unwrap().params(), &params);
+        assert_eq!(game.sprite_entity(moved), Some(moved_entity));
+        assert_ne!(game.scene().get(moved_entity).unwrap().name, "Moved");
+        assert!(game.sprite_mut(added).is_none());
+        assert_eq!(game.scene().iter().count(), entity_count);
+        // the removed sprite is back, mirrored by its restored entity
+        let EntityKind::Sprite(restored) = game.scene().get(removed_entity).unwrap().kind else {
+            panic!("the entity of the removed sprite isn't restored");
+        };
+        assert_eq!(game.sprite_entity(restored), Some(removed_entity));
+        assert_eq!(game.sprite_mut(restored).unwrap().params(), &params);
+
+        let shaders = renderer.shader_count();
+        game.prepare_rendering(&mut renderer);
+        // the sprite added during the play is destroyed
+        assert_eq!(renderer.shader_count(), shaders - 1);
+    }
+
+    #[test]
+    fn moved_sprites_leave_no_voxels() {
+        let Some(mut renderer) = storage_renderer("moved_sprites_leave_no_voxels") else {
+            return;
+        };
+
+        let mut game = Game::new(&mut renderer);
+        game.init(&mut renderer);
+        let params = SpriteParams::new(IVec3::ZERO, UVec3::splat(4), 1);
+        let handle = game.add_sprite(Sprite::new(&mut renderer, game.world(), CUBE_SHADER, params).unwrap());
+        let mut generate = |game: &mut Game| {
+            game.prepare_rendering(&mut renderer);
+            let mut frame = renderer.begin_frame().expect("Headless frames are always available");
+            game.render(&mut frame);
+            renderer.commit_frame(frame);
+            game.sync_world(&renderer);
+        };
+        generate(&mut game);
+        assert_eq!(game.world().get_voxel_at(&IVec3::ONE), 1);
+
+        game.sprite_mut(handle).unwrap().set_params(SpriteParams::new(IVec3::splat(8), UVec3::splat(4), 1));
+        generate(&mut game);
+        assert_eq!(game.world().get_voxel_at(&IVec3::ONE), 0);
+        assert_eq!(game.world().get_voxel_at(&IVec3::splat(9)), 1);
+    }
+}
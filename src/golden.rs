@@ -0,0 +1,314 @@
+//! Golden image regression tests for the voxel raymarcher.
+//!
+//! Scenes are rendered headless and compared against the references stored in `tests/golden`.
+//! Set `OCTO_UPDATE_GOLDEN=1` to (re)write the references instead of comparing,
+//! a missing reference fails the test otherwise.
+//! On mismatch, the actual and diff images are written to `target/golden`.
+//! The tests fail without an adapter, see [`crate::test_utils`] to skip them.
+
+use std::path::PathBuf;
+
+use glam::{IVec3, UVec3, Vec3};
+
+use crate::{
+    game::Game, image::Image, palette::Material, renderer_context::{RendererContext, Resolution}, sdf::{Primitive, SdfScene, Shape}, sprite::{Sprite, SpriteParams, CUBE_SHADER, SPHERE_SHADER}, system::System, terrain::TerrainParams, test_utils::{headless_renderer, storage_renderer}, transform::Transform, voxel_world::{Region, WorldRepresentation}
+};
+
+const RESOLUTION: Resolution = Resolution {
+    width: 160,
+    height: 120,
+};
+
+/// Maximum difference allowed on a channel before a pixel is considered different
+const CHANNEL_TOLERANCE: u8 = 8;
+/// Fraction of the pixels allowed to be different, to absorb rasterization differences between adapters
+const MAX_MISMATCH_RATIO: f32 = 0.002;
+
+pub struct ImageComparison {
+    pub mismatched_pixels: u32,
+    pub max_channel_difference: u8,
+    pub diff: Image,
+}
+
+impl ImageComparison {
+    pub fn mismatch_ratio(&self) -> f32 {
+        self.mismatched_pixels as f32 / (self.diff.width * self.diff.height) as f32
+    }
+}
+
+/// Compares two images of the same size.
+/// Mismatched pixels are drawn red in the diff image, others are a dimmed copy of the reference.
+pub fn compare_images(reference: &Image, actual: &Image, tolerance: u8) -> ImageComparison {
+    assert_eq!(
+        (reference.width, reference.height),
+        (actual.width, actual.height),
+        "Images have different sizes"
+    );
+
+    let mut diff = Image::new(reference.width, reference.height);
+    let mut mismatched_pixels = 0;
+    let mut max_channel_difference = 0;
+
+    for y in 0..reference.height {
+        for x in 0..reference.width {
+            let expected = reference.pixel(x, y);
+            let found = actual.pixel(x, y);
+            let difference = expected
+                .iter()
+                .zip(found.iter())
+                .map(|(a, b)| a.abs_diff(*b))
+                .max()
+                .unwrap_or(0);
+            max_channel_difference = max_channel_difference.max(difference);
+
+            if difference > tolerance {
+                mismatched_pixels += 1;
+                diff.set_pixel(x, y, [255, 0, 0, 255]);
+            }
+            else {
+                diff.set_pixel(x, y, [expected[0] / 4, expected[1] / 4, expected[2] / 4, 255]);
+            }
+        }
+    }
+
+    ImageComparison {
+        mismatched_pixels,
+        max_channel_difference,
+        diff,
+    }
+}
+
+fn camera_transform() -> Transform {
+    // off the voxel grid, the DDA never advances when side distances start equal
+    let position = Vec3::new(28.3, 20.7, 28.6);
+    let target = Vec3::new(16.0, 10.0, 16.0);
+    let mut transform = Transform::from_position(position);
+    // the raymarcher casts rays along the camera forward axis (+Z) while look_to aligns -Z
+    transform.look_to(position - target, Vec3::Y);
+    transform
+}
+
+/// Renders a single frame of the world filled by `populate`
+fn render_scene<F>(renderer: &mut RendererContext, populate: F) -> Image
+where
+    F: FnOnce(&mut Game, &mut RendererContext)
+{
+    let mut game = Game::new(renderer);
+    game.init(renderer);
+    game.clear_sprites(renderer);
+    populate(&mut game, renderer);
+    game.camera_mut().transform = camera_transform();
+    game.resize(renderer, RESOLUTION);
+    game.prepare_rendering(renderer);
+
+    let mut frame = renderer.begin_frame().expect("Headless frames are always available");
+    game.render(&mut frame);
+    renderer.commit_frame(frame);
+
+    renderer.read_texture(game.game_texture()).unwrap()
+}
+
+fn golden_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden")
+}
+
+fn output_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("target/golden")
+}
+
+fn check_image(name: &str, actual: &Image) {
+    let reference_path = golden_dir().join(format!("{}.png", name));
+
+    if std::env::var_os("OCTO_UPDATE_GOLDEN").is_some() {
+        actual.save_png(&reference_path).unwrap();
+        eprintln!("golden reference written to {}, review and commit it", reference_path.display());
+        return;
+    }
+
+    assert!(
+        reference_path.exists(),
+        "Golden reference {} is missing, run with OCTO_UPDATE_GOLDEN=1 to write it",
+        reference_path.display()
+    );
+
+    let reference = Image::load_png(&reference_path).unwrap();
+    let comparison = compare_images(&reference, actual, CHANNEL_TOLERANCE);
+
+    if comparison.mismatch_ratio() > MAX_MISMATCH_RATIO {
+        let actual_path = output_dir().join(format!("{}_actual.png", name));
+        let diff_path = output_dir().join(format!("{}_diff.png", name));
+        actual.save_png(&actual_path).unwrap();
+        comparison.diff.save_png(&diff_path).unwrap();
+
+        panic!(
+            "Golden image {} differs: {} pixels mismatched (max channel difference {}), see {} and {}",
+            name,
+            comparison.mismatched_pixels,
+            comparison.max_channel_difference,
+            actual_path.display(),
+            diff_path.display()
+        );
+    }
+}
+
+/// Checks a scene generated on the GPU by a sprite shader
+fn check_sprite_scene(name: &str, sprite_shader: &str, params: SpriteParams) {
+    let Some(mut renderer) = storage_renderer(name) else {
+        return;
+    };
+
+    let actual = render_scene(&mut renderer, |game, renderer| {
+        let sprite = Sprite::new(renderer, game.world(), sprite_shader, params).unwrap();
+        game.add_sprite(sprite);
+    });
+    check_image(name, &actual);
+}
+
+/// Checks a scene whose voxels are set from the CPU, rendered with the given world representation
+fn check_voxels_scene(name: &str, voxels: &[IVec3], representation: WorldRepresentation) {
+    let Some(mut renderer) = headless_renderer(name) else {
+        return;
+    };
+
+    let actual = render_scene(&mut renderer, |game, renderer| {
+        game.set_world_representation(renderer, representation);
+        for voxel in voxels {
+            game.world_mut().set_voxel_at(255, voxel);
+        }
+    });
+    check_image(name, &actual);
+}
+
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identical_images_match() {
+        let mut image = Image::new(4, 4);
+        image.set_pixel(1, 2, [10, 20, 30, 255]);
+        let comparison = compare_images(&image, &image.clone(), 0);
+        assert_eq!(comparison.mismatched_pixels, 0);
+        assert_eq!(comparison.max_channel_difference, 0);
+    }
+
+    #[test]
+    fn differences_within_tolerance_match() {
+        let reference = Image::new(2, 2);
+        let mut actual = Image::new(2, 2);
+        actual.set_pixel(0, 0, [4, 0, 0, 0]);
+        actual.set_pixel(1, 1, [0, 0, 20, 0]);
+
+        let comparison = compare_images(&reference, &actual, 4);
+        assert_eq!(comparison.mismatched_pixels, 1);
+        assert_eq!(comparison.max_channel_difference, 20);
+        assert_eq!(comparison.diff.pixel(1, 1), [255, 0, 0, 255]);
+        assert_eq!(comparison.diff.pixel(0, 0), [0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn golden_sphere() {
+        check_sprite_scene("sphere", SPHERE_SHADER, SpriteParams::new(IVec3::ZERO, UVec3::splat(32), 255));
+    }
+
+    #[test]
+    fn golden_cube() {
+        check_sprite_scene("cube", CUBE_SHADER, SpriteParams::new(IVec3::splat(6), UVec3::new(32, 2, 32), 255));
+    }
+
+    fn staircase() -> Vec<IVec3> {
+        // steps crossing the world on both horizontal axes, with a floor
+        let mut voxels = vec![];
+        for i in 0..16 {
+            for j in 0..4 {
+                voxels.push(IVec3::new(8 + i, i / 2, 8 + j));
+                voxels.push(IVec3::new(8 + j, i / 2, 8 + i));
+            }
+        }
+        for x in 0..32 {
+            for z in 0..32 {
+                voxels.push(IVec3::new(x, 0, z));
+            }
+        }
+        voxels
+    }
+
+    #[test]
+    fn golden_staircase() {
+        check_voxels_scene("staircase", &staircase(), WorldRepresentation::Grid);
+    }
+
+    #[test]
+    fn golden_staircase_octree() {
+        check_voxels_scene("staircase_octree", &staircase(), WorldRepresentation::Octree);
+    }
+
+    #[test]
+    fn golden_staircase_brickmap() {
+        check_voxels_scene("staircase_brickmap", &staircase(), WorldRepresentation::Brickmap);
+    }
+
+    #[test]
+    fn golden_terrain() {
+        let Some(mut renderer) = headless_renderer("terrain") else {
+            return;
+        };
+
+        let actual = render_scene(&mut renderer, |game, _| {
+            game.generate_terrain(TerrainParams::with_seed(1), &Region::new(IVec3::ZERO, IVec3::splat(32)));
+        });
+        check_image("terrain", &actual);
+    }
+
+    #[test]
+    fn golden_csg() {
+        let Some(mut renderer) = headless_renderer("csg") else {
+            return;
+        };
+
+        let actual = render_scene(&mut renderer, |game, _| {
+            let center = Vec3::new(16.0, 12.0, 16.0);
+            let mut ring = Transform::from_position(center);
+            ring.rotate_x(std::f32::consts::FRAC_PI_4);
+            let scene = SdfScene::new()
+                .union(Primitive::new(Shape::Box { half_extents: Vec3::splat(7.0) }, Transform::from_position(center), 200))
+                .subtract(Primitive::new(Shape::Sphere { radius: 8.5 }, Transform::from_position(center), 0))
+                .union(Primitive::new(Shape::Torus { major_radius: 10.0, minor_radius: 1.5 }, ring, 255))
+                .smooth_union(Primitive::new(Shape::Cylinder { half_height: 2.0, radius: 6.0 }, Transform::from_position(Vec3::new(16.0, 2.0, 16.0)), 120), 3.0);
+            game.voxelize(&scene);
+        });
+        check_image("csg", &actual);
+    }
+
+    #[test]
+    fn golden_materials() {
+        let Some(mut renderer) = headless_renderer("materials") else {
+            return;
+        };
+
+        let actual = render_scene(&mut renderer, |game, _| {
+            let palette = game.world_mut().palette_mut();
+            palette.set(1, Material::new([0.8, 0.1, 0.1]));
+            let mut metal = Material::new([0.2, 0.8, 0.2]);
+            metal.metallic = 1.0;
+            metal.roughness = 0.2;
+            palette.set(2, metal);
+            let mut light = Material::new([0.1, 0.1, 0.8]);
+            light.emission = [0.5, 0.5, 1.0];
+            palette.set(3, light);
+            let mut glass = Material::new([1.0, 1.0, 1.0]);
+            glass.transparency = 0.6;
+            palette.set(4, glass);
+
+            for (i, voxel) in staircase().iter().enumerate() {
+                game.world_mut().set_voxel_at(1 + (i % 3) as u32, voxel);
+            }
+            // glass wall in front of the stairs
+            for y in 0..12 {
+                for x in 14..30 {
+                    game.world_mut().set_voxel_at(4, &IVec3::new(x, y, 24));
+                }
+            }
+        });
+        check_image("materials", &actual);
+    }
+}
@@ -0,0 +1,407 @@
+//! Undo and redo of the edits of the world, as commands restoring the voxels of a region.
+
+use std::{collections::VecDeque, io::{Read, Write}};
+
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+use glam::IVec3;
+
+use crate::{palette::Material, voxel_world::{ChunkMap, Region, VoxelWorld}};
+
+/// Most commands that can be undone, the oldest ones are forgotten first
+pub const MAX_HISTORY: usize = 100;
+
+/// Snapshots of more voxels than this are compressed
+const COMPRESSION_THRESHOLD: usize = 4096;
+
+/// Voxels a command can read and write
+pub trait VoxelStorage {
+    fn get_voxel_at(&self, coord: &IVec3) -> u32;
+    fn set_voxel_at(&mut self, value: u32, coord: &IVec3);
+
+    /// Materials of the palette the voxels index, `None` without palette
+    fn materials(&self) -> Option<&[Material]> {
+        None
+    }
+
+    fn set_materials(&mut self, _materials: &[Material]) {}
+}
+
+impl VoxelStorage for ChunkMap {
+    fn get_voxel_at(&self, coord: &IVec3) -> u32 {
+        ChunkMap::get_voxel_at(self, coord)
+    }
+
+    fn set_voxel_at(&mut self, value: u32, coord: &IVec3) {
+        ChunkMap::set_voxel_at(self, value, coord)
+    }
+}
+
+impl VoxelStorage for VoxelWorld {
+    fn get_voxel_at(&self, coord: &IVec3) -> u32 {
+        VoxelWorld::get_voxel_at(self, coord)
+    }
+
+    fn set_voxel_at(&mut self, value: u32, coord: &IVec3) {
+        VoxelWorld::set_voxel_at(self, value, coord)
+    }
+
+    fn materials(&self) -> Option<&[Material]> {
+        Some(self.palette().materials())
+    }
+
+    fn set_materials(&mut self, materials: &[Material]) {
+        self.palette_mut().set_materials(materials);
+    }
+}
+
+/// Values of the voxels of a region, in [`Region::iter`] order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Snapshot {
+    Raw(Vec<u32>),
+    /// Zlib stream of the little endian values
+    Compressed(Vec<u8>),
+}
+
+impl Snapshot {
+    pub fn capture<W: VoxelStorage>(world: &W, region: &Region) -> Self {
+        let values: Vec<u32> = region.iter().map(|coord| world.get_voxel_at(&coord)).collect();
+        if values.len() <= COMPRESSION_THRESHOLD {
+            return Snapshot::Raw(values);
+        }
+        let mut encoder = ZlibEncoder::new(vec![], Compression::fast());
+        match encoder.write_all(bytemuck::cast_slice(&values)).and_then(|_| encoder.finish()) {
+            Ok(compressed) => Snapshot::Compressed(compressed),
+            Err(_) => Snapshot::Raw(values),
+        }
+    }
+
+    pub fn values(&self) -> Vec<u32> {
+        match self {
+            Snapshot::Raw(values) => values.clone(),
+            Snapshot::Compressed(compressed) => {
+                let mut bytes = vec![];
+                ZlibDecoder::new(compressed.as_slice())
+                    .read_to_end(&mut bytes)
+                    .expect("snapshots are compressed in memory");
+                bytes
+                    .chunks_exact(4)
+                    .map(|value| u32::from_le_bytes(value.try_into().unwrap()))
+                    .collect()
+            }
+        }
+    }
+
+    /// Memory used by the snapshot, in bytes
+    pub fn size(&self) -> usize {
+        match self {
+            Snapshot::Raw(values) => values.len() * std::mem::size_of::<u32>(),
+            Snapshot::Compressed(compressed) => compressed.len(),
+        }
+    }
+
+    /// Writes the values in the region, skipping the voxels that already have them
+    fn restore<W: VoxelStorage>(&self, world: &mut W, region: &Region) {
+        for (coord, value) in region.iter().zip(self.values()) {
+            if world.get_voxel_at(&coord) != value {
+                world.set_voxel_at(value, &coord);
+            }
+        }
+    }
+}
+
+/// Reversible edit of the world, keeping the voxels of the region it touched before and after it
+#[derive(Debug, Clone)]
+pub struct Command {
+    name: String,
+    region: Region,
+    before: Snapshot,
+    after: Snapshot,
+    /// Materials before and after the edit, when it changed the palette
+    materials: Option<(Vec<Material>, Vec<Material>)>,
+}
+
+impl Command {
+    /// Runs an edit which only changes voxels of the region, and the palette, and records it
+    pub fn record<W: VoxelStorage, F: FnOnce(&mut W)>(world: &mut W, name: impl Into<String>, region: Region, edit: F) -> Self {
+        let before = Snapshot::capture(world, &region);
+        let materials_before = world.materials().map(<[Material]>::to_vec);
+        edit(world);
+        let after = Snapshot::capture(world, &region);
+        let materials = materials_before
+            .zip(world.materials())
+            .filter(|(before, after)| before.as_slice() != *after)
+            .map(|(before, after)| (before, after.to_vec()));
+        Self {
+            name: name.into(),
+            region,
+            before,
+            after,
+            materials,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn region(&self) -> &Region {
+        &self.region
+    }
+
+    /// True when the edit left the region as it was
+    pub fn is_noop(&self) -> bool {
+        self.before == self.after && self.materials.is_none()
+    }
+
+    /// Memory used by the snapshots, in bytes
+    pub fn size(&self) -> usize {
+        let materials = self.materials.as_ref().map_or(0, |(before, after)| before.len() + after.len());
+        self.before.size() + self.after.size() + materials * std::mem::size_of::<Material>()
+    }
+
+    /// Folds the next command over the same region into this one, e.g. the next step of a drag
+    fn merge(&mut self, next: Command) {
+        self.after = next.after;
+        self.materials = match (self.materials.take(), next.materials) {
+            (Some((before, _)), Some((_, after))) => Some((before, after)),
+            (materials, None) | (None, materials) => materials,
+        };
+    }
+
+    pub fn undo<W: VoxelStorage>(&self, world: &mut W) {
+        self.before.restore(world, &self.region);
+        if let Some((before, _)) = &self.materials {
+            world.set_materials(before);
+        }
+    }
+
+    pub fn redo<W: VoxelStorage>(&self, world: &mut W) {
+        self.after.restore(world, &self.region);
+        if let Some((_, after)) = &self.materials {
+            world.set_materials(after);
+        }
+    }
+}
+
+/// Commands that can be undone, and the undone ones that can be redone until a new command is pushed
+#[derive(Debug, Default, Clone)]
+pub struct History {
+    undo: VecDeque<Command>,
+    redo: Vec<Command>,
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a command which was already applied, commands changing nothing are dropped
+    pub fn push(&mut self, command: Command) {
+        if command.is_noop() {
+            return;
+        }
+        self.redo.clear();
+        if self.undo.len() == MAX_HISTORY {
+            self.undo.pop_front();
+        }
+        self.undo.push_back(command);
+    }
+
+    /// Adds a command which was already applied, merged into the last one when it continues it:
+    /// same name and region, and nothing undone since
+    pub fn push_merged(&mut self, command: Command) {
+        match self.undo.back_mut() {
+            Some(last) if self.redo.is_empty() && last.name == command.name && last.region == command.region => last.merge(command),
+            _ => self.push(command),
+        }
+    }
+
+    /// Reverts the last command, returns its name
+    pub fn undo<W: VoxelStorage>(&mut self, world: &mut W) -> Option<&str> {
+        let command = self.undo.pop_back()?;
+        command.undo(world);
+        self.redo.push(command);
+        self.redo.last().map(Command::name)
+    }
+
+    /// Applies the last undone command again, returns its name
+    pub fn redo<W: VoxelStorage>(&mut self, world: &mut W) -> Option<&str> {
+        let command = self.redo.pop()?;
+        command.redo(world);
+        self.undo.push_back(command);
+        self.undo.back().map(Command::name)
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    /// Commands that can be undone, the most recent first
+    pub fn undo_commands(&self) -> impl Iterator<Item = &Command> {
+        self.undo.iter().rev()
+    }
+
+    /// Commands that can be redone, the next one first
+    pub fn redo_commands(&self) -> impl Iterator<Item = &Command> {
+        self.redo.iter().rev()
+    }
+
+    /// Memory used by all the commands, in bytes
+    pub fn size(&self) -> usize {
+        self.undo.iter().chain(&self.redo).map(Command::size).sum()
+    }
+
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fill(name: &str, chunks: &mut ChunkMap, value: u32, region: Region) -> Command {
+        Command::record(chunks, name, region, |chunks| {
+            for coord in region.iter() {
+                chunks.set_voxel_at(value, &coord);
+            }
+        })
+    }
+
+    #[test]
+    fn undo_and_redo_restore_the_region() {
+        let mut chunks = ChunkMap::new();
+        chunks.set_voxel_at(7, &IVec3::new(1, 1, 1));
+        let mut history = History::new();
+
+        let region = Region::new(IVec3::ZERO, IVec3::splat(4));
+        history.push(fill("first", &mut chunks, 1, region));
+        history.push(fill("second", &mut chunks, 2, Region::new(IVec3::ZERO, IVec3::splat(2))));
+        assert_eq!(chunks.get_voxel_at(&IVec3::ZERO), 2);
+        assert_eq!(chunks.get_voxel_at(&IVec3::splat(3)), 1);
+
+        assert_eq!(history.undo(&mut chunks), Some("second"));
+        assert_eq!(chunks.get_voxel_at(&IVec3::ZERO), 1);
+        assert_eq!(history.undo(&mut chunks), Some("first"));
+        assert_eq!(chunks.get_voxel_at(&IVec3::ZERO), 0);
+        assert_eq!(chunks.get_voxel_at(&IVec3::new(1, 1, 1)), 7);
+        assert_eq!(history.undo(&mut chunks), None);
+
+        assert_eq!(history.redo(&mut chunks), Some("first"));
+        assert_eq!(chunks.get_voxel_at(&IVec3::new(1, 1, 1)), 1);
+        assert_eq!(history.redo_commands().map(Command::name).collect::<Vec<_>>(), vec!["second"]);
+
+        // a new command forgets the undone ones
+        history.push(fill("third", &mut chunks, 3, Region::from_voxel(IVec3::ZERO)));
+        assert!(!history.can_redo());
+        assert_eq!(history.undo_commands().map(Command::name).collect::<Vec<_>>(), vec!["third", "first"]);
+    }
+
+    #[test]
+    fn large_snapshots_are_compressed() {
+        let mut chunks = ChunkMap::new();
+        let region = Region::new(IVec3::ZERO, IVec3::splat(32));
+        let command = fill("fill", &mut chunks, 5, region);
+        assert!(matches!(command.before, Snapshot::Compressed(_)));
+        assert!(command.size() < region.volume());
+        assert_eq!(command.after.values(), vec![5; region.volume()]);
+
+        command.undo(&mut chunks);
+        assert_eq!(chunks.get_voxel_at(&IVec3::splat(31)), 0);
+        command.redo(&mut chunks);
+        assert_eq!(chunks.get_voxel_at(&IVec3::splat(31)), 5);
+    }
+
+    #[test]
+    fn history_is_bounded() {
+        let mut chunks = ChunkMap::new();
+        let mut history = History::new();
+        history.push(fill("noop", &mut chunks, 0, Region::from_voxel(IVec3::ZERO)));
+        assert!(!history.can_undo());
+
+        for i in 0..MAX_HISTORY as u32 + 10 {
+            history.push(fill("set", &mut chunks, i + 1, Region::from_voxel(IVec3::ZERO)));
+        }
+        assert_eq!(history.undo_commands().count(), MAX_HISTORY);
+        while history.undo(&mut chunks).is_some() {}
+        // the first commands were forgotten
+        assert_eq!(chunks.get_voxel_at(&IVec3::ZERO), 10);
+    }
+
+    /// Chunks indexing a palette
+    #[derive(Default)]
+    struct PaletteWorld {
+        chunks: ChunkMap,
+        materials: Vec<Material>,
+    }
+
+    impl VoxelStorage for PaletteWorld {
+        fn get_voxel_at(&self, coord: &IVec3) -> u32 {
+            self.chunks.get_voxel_at(coord)
+        }
+
+        fn set_voxel_at(&mut self, value: u32, coord: &IVec3) {
+            self.chunks.set_voxel_at(value, coord)
+        }
+
+        fn materials(&self) -> Option<&[Material]> {
+            Some(&self.materials)
+        }
+
+        fn set_materials(&mut self, materials: &[Material]) {
+            self.materials = materials.to_vec();
+        }
+    }
+
+    #[test]
+    fn palette_changes_are_recorded() {
+        let mut world = PaletteWorld {
+            materials: vec![Material::default(); 2],
+            ..Default::default()
+        };
+        let red = Material::new([1.0, 0.0, 0.0]);
+        let command = Command::record(&mut world, "import", Region::from_voxel(IVec3::ZERO), |world| {
+            world.set_materials(&[Material::default(), red]);
+        });
+        assert!(!command.is_noop());
+
+        command.undo(&mut world);
+        assert_eq!(world.materials[1], Material::default());
+        command.redo(&mut world);
+        assert_eq!(world.materials[1], red);
+    }
+
+    #[test]
+    fn continued_commands_are_merged() {
+        let mut world = PaletteWorld {
+            materials: vec![Material::default(); 2],
+            ..Default::default()
+        };
+        let mut history = History::new();
+        let region = Region::new(IVec3::ZERO, IVec3::ZERO);
+        for red in [0.2, 0.4, 0.6] {
+            history.push_merged(Command::record(&mut world, "drag", region, |world| {
+                world.set_materials(&[Material::default(), Material::new([red, 0.0, 0.0])]);
+            }));
+        }
+        assert_eq!(history.undo_commands().count(), 1);
+
+        history.undo(&mut world);
+        assert_eq!(world.materials[1], Material::default());
+        // an undone command isn't continued
+        history.redo(&mut world);
+        history.undo(&mut world);
+        history.push_merged(Command::record(&mut world, "drag", region, |world| {
+            world.set_materials(&[Material::default(), Material::new([1.0, 0.0, 0.0])]);
+        }));
+        assert_eq!(history.undo_commands().count(), 1);
+        assert!(!history.can_redo());
+        history.undo(&mut world);
+        assert_eq!(world.materials[1], Material::default());
+    }
+}
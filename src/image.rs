@@ -0,0 +1,149 @@
+use std::{fs::File, io::BufWriter, path::Path};
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ImageError {
+    #[error("Io error")]
+    Io(#[from] std::io::Error),
+    #[error("Png encoding error")]
+    PngEncoding(#[from] png::EncodingError),
+    #[error("Png decoding error")]
+    PngDecoding(#[from] png::DecodingError),
+    #[error("Unsupported png layout: {0:?} {1:?}")]
+    UnsupportedLayout(png::ColorType, png::BitDepth),
+}
+
+/// 8 bits RGBA image living on the CPU
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+impl Image {
+    pub const BYTES_PER_PIXEL: u32 = 4;
+
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            data: vec![0; (width * height * Self::BYTES_PER_PIXEL) as usize],
+        }
+    }
+
+    /// Builds an image from rows whose stride may be larger than the row size,
+    /// as returned by a texture to buffer copy
+    pub fn from_padded_rows(width: u32, height: u32, padded_bytes_per_row: u32, padded_data: &[u8]) -> Self {
+        let bytes_per_row = (width * Self::BYTES_PER_PIXEL) as usize;
+        let mut data = Vec::with_capacity(bytes_per_row * height as usize);
+        for row in padded_data
+            .chunks(padded_bytes_per_row as usize)
+            .take(height as usize) {
+            data.extend_from_slice(&row[..bytes_per_row]);
+        }
+
+        Self {
+            width,
+            height,
+            data,
+        }
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> [u8; 4] {
+        let offset = ((y * self.width + x) * Self::BYTES_PER_PIXEL) as usize;
+        [
+            self.data[offset],
+            self.data[offset + 1],
+            self.data[offset + 2],
+            self.data[offset + 3],
+        ]
+    }
+
+    pub fn set_pixel(&mut self, x: u32, y: u32, pixel: [u8; 4]) {
+        let offset = ((y * self.width + x) * Self::BYTES_PER_PIXEL) as usize;
+        self.data[offset..offset + 4].copy_from_slice(&pixel);
+    }
+
+    /// Swaps red and blue channels, converting between BGRA and RGBA
+    pub fn swap_red_blue(&mut self) {
+        for pixel in self.data.chunks_exact_mut(Self::BYTES_PER_PIXEL as usize) {
+            pixel.swap(0, 2);
+        }
+    }
+
+    pub fn save_png<P: AsRef<Path>>(&self, path: P) -> Result<(), ImageError> {
+        if let Some(parent) = path.as_ref().parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let file = File::create(path)?;
+        let mut encoder = png::Encoder::new(BufWriter::new(file), self.width, self.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.data)?;
+
+        Ok(())
+    }
+
+    pub fn load_png<P: AsRef<Path>>(path: P) -> Result<Self, ImageError> {
+        let decoder = png::Decoder::new(File::open(path)?);
+        let mut reader = decoder.read_info()?;
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buffer)?;
+
+        if info.color_type != png::ColorType::Rgba || info.bit_depth != png::BitDepth::Eight {
+            return Err(ImageError::UnsupportedLayout(info.color_type, info.bit_depth));
+        }
+        buffer.truncate(info.buffer_size());
+
+        Ok(
+            Self {
+                width: info.width,
+                height: info.height,
+                data: buffer,
+            }
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strip_row_padding() {
+        let padded = [
+            1, 2, 3, 4, 5, 6, 7, 8, 0, 0, 0, 0,
+            9, 10, 11, 12, 13, 14, 15, 16, 0, 0, 0, 0,
+        ];
+        let image = Image::from_padded_rows(2, 2, 12, &padded);
+        assert_eq!(image.data, (1..=16).collect::<Vec<u8>>());
+        assert_eq!(image.pixel(1, 1), [13, 14, 15, 16]);
+    }
+
+    #[test]
+    fn swapping_red_and_blue() {
+        let mut image = Image::new(1, 1);
+        image.set_pixel(0, 0, [1, 2, 3, 4]);
+        image.swap_red_blue();
+        assert_eq!(image.pixel(0, 0), [3, 2, 1, 4]);
+    }
+
+    #[test]
+    fn png_round_trip() {
+        let mut image = Image::new(3, 2);
+        image.set_pixel(0, 0, [255, 0, 0, 255]);
+        image.set_pixel(2, 1, [0, 128, 255, 64]);
+
+        let path = std::env::temp_dir().join("octo_engine_png_round_trip.png");
+        image.save_png(&path).unwrap();
+        let loaded = Image::load_png(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(image, loaded);
+    }
+}
@@ -0,0 +1,157 @@
+//! Logger printing to the terminal like `env_logger` and keeping the last records for the editor console.
+
+use std::{collections::VecDeque, sync::{Mutex, OnceLock}, time::Instant};
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+/// Records kept for the console, the oldest ones are dropped first
+pub const MAX_LOG_RECORDS: usize = 1000;
+
+/// Printed when `RUST_LOG` isn't set
+const DEFAULT_FILTER: &str = "warn,octo_engine=info";
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogRecord {
+    pub level: Level,
+    /// Module which logged the record
+    pub target: String,
+    pub message: String,
+    /// Seconds since the logger was installed
+    pub time: f32,
+}
+
+/// Ring buffer of the last records
+#[derive(Debug, Default)]
+pub struct LogBuffer {
+    records: VecDeque<LogRecord>,
+    capacity: usize,
+}
+
+impl LogBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            records: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, record: LogRecord) {
+        if self.records.len() == self.capacity {
+            self.records.pop_front();
+        }
+        self.records.push_back(record);
+    }
+
+    /// Records from the oldest to the newest
+    pub fn records(&self) -> impl Iterator<Item = &LogRecord> {
+        self.records.iter()
+    }
+
+    pub fn clear(&mut self) {
+        self.records.clear();
+    }
+}
+
+struct Logger {
+    terminal: env_logger::Logger,
+    buffer: Mutex<LogBuffer>,
+    start: Instant,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= Level::Info || self.terminal.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        self.terminal.log(record);
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let record = LogRecord {
+            level: record.level(),
+            target: record.target().to_string(),
+            message: record.args().to_string(),
+            time: self.start.elapsed().as_secs_f32(),
+        };
+        if let Ok(mut buffer) = self.buffer.lock() {
+            buffer.push(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.terminal.flush();
+    }
+}
+
+/// Installs the logger, does nothing when it already is.
+/// The terminal shows the records allowed by `RUST_LOG`, the console the info ones too.
+pub fn init() {
+    let mut installed = false;
+    let logger = LOGGER.get_or_init(|| {
+        installed = true;
+        Logger {
+            terminal: env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(DEFAULT_FILTER)).build(),
+            buffer: Mutex::new(LogBuffer::new(MAX_LOG_RECORDS)),
+            start: Instant::now(),
+        }
+    });
+    if installed && log::set_logger(logger).is_ok() {
+        log::set_max_level(logger.terminal.filter().max(LevelFilter::Info));
+    }
+}
+
+/// Copies the records kept for the console which pass the filter, from the oldest to the newest
+pub fn records<F: Fn(&LogRecord) -> bool>(filter: F) -> Vec<LogRecord> {
+    // records logged while drawing them must not wait for the lock, so they are copied
+    LOGGER
+        .get()
+        .and_then(|logger| logger.buffer.lock().ok())
+        .map(|buffer| buffer.records().filter(|record| filter(record)).cloned().collect())
+        .unwrap_or_default()
+}
+
+pub fn clear() {
+    if let Some(mut buffer) = LOGGER.get().and_then(|logger| logger.buffer.lock().ok()) {
+        buffer.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(message: &str) -> LogRecord {
+        LogRecord {
+            level: Level::Info,
+            target: "octo_engine::logger".to_string(),
+            message: message.to_string(),
+            time: 0.0,
+        }
+    }
+
+    #[test]
+    fn ring_buffer() {
+        let mut buffer = LogBuffer::new(3);
+        for message in ["a", "b", "c", "d"] {
+            buffer.push(record(message));
+        }
+        let messages: Vec<_> = buffer.records().map(|record| record.message.as_str()).collect();
+        assert_eq!(messages, vec!["b", "c", "d"]);
+        buffer.clear();
+        assert_eq!(buffer.records().count(), 0);
+    }
+
+    #[test]
+    fn records_are_kept_for_the_console() {
+        init();
+        init();
+        log::info!("console test record");
+        let records = records(|record| record.message == "console test record");
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].level, Level::Info);
+        assert_eq!(records[0].target, module_path!());
+    }
+}
@@ -6,18 +6,53 @@ mod game;
 mod system;
 mod inputs;
 mod file_watcher;
+mod logger;
+mod profiler;
 mod utils;
 mod voxel_world;
+mod octree;
+mod brickmap;
 mod egui_renderer;
 mod ray;
 mod color;
 mod transform;
 mod app;
 mod editor;
+mod editor_settings;
 mod sprite;
+mod image;
+mod noise;
+mod terrain;
+mod sdf;
+mod brush;
+mod history;
+mod scene;
+mod palette;
+mod vox;
+mod world_file;
+mod shader;
+#[cfg(test)]
+mod golden;
+#[cfg(test)]
+mod test_utils;
 
 use app::App;
+use renderer_context::Resolution;
+
+const HEADLESS_FRAME_COUNT: u32 = 10;
 
 fn main() {
+    if std::env::args().any(|arg| arg == "--headless") {
+        let resolution = Resolution {
+            width: 800,
+            height: 600,
+        };
+        if let Err(e) = pollster::block_on(App::run_headless(resolution, HEADLESS_FRAME_COUNT)) {
+            log::error!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     pollster::block_on(App::run());
 }
\ No newline at end of file
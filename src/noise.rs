@@ -0,0 +1,116 @@
+//! Seeded gradient noise.
+//!
+//! Only integer hashing and f32 arithmetic are used, `src/shaders/compute_terrain.wgsl`
+//! implements the same functions so the CPU and GPU agree up to float rounding.
+
+use glam::Vec3;
+
+/// Hash of a lattice point, well distributed over all the bits
+pub fn hash(x: i32, y: i32, z: i32, seed: u32) -> u32 {
+    let mut h = seed
+        ^ (x as u32).wrapping_mul(0x8da6b343)
+        ^ (y as u32).wrapping_mul(0xd8163841)
+        ^ (z as u32).wrapping_mul(0xcb1ab31f);
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x7feb352d);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x846ca68b);
+    h ^= h >> 16;
+    h
+}
+
+/// Dot product of the offset with one of the 12 cube edge gradients picked by the hash
+fn gradient(hash: u32, x: f32, y: f32, z: f32) -> f32 {
+    let h = hash & 15;
+    let u = if h < 8 { x } else { y };
+    let v = if h < 4 { y } else if h == 12 || h == 14 { x } else { z };
+    (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
+
+fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+/// Improved Perlin noise, roughly in [-1, 1] and 0 on the integer lattice
+pub fn perlin(p: Vec3, seed: u32) -> f32 {
+    let cell = p.floor();
+    let (x, y, z) = (cell.x as i32, cell.y as i32, cell.z as i32);
+    let f = p - cell;
+    let (u, v, w) = (fade(f.x), fade(f.y), fade(f.z));
+
+    let corner = |dx: i32, dy: i32, dz: i32| {
+        gradient(
+            hash(x + dx, y + dy, z + dz, seed),
+            f.x - dx as f32,
+            f.y - dy as f32,
+            f.z - dz as f32
+        )
+    };
+
+    lerp(
+        lerp(
+            lerp(corner(0, 0, 0), corner(1, 0, 0), u),
+            lerp(corner(0, 1, 0), corner(1, 1, 0), u),
+            v
+        ),
+        lerp(
+            lerp(corner(0, 0, 1), corner(1, 0, 1), u),
+            lerp(corner(0, 1, 1), corner(1, 1, 1), u),
+            v
+        ),
+        w
+    )
+}
+
+/// Fractal Brownian motion: octaves of Perlin noise of increasing frequency and decreasing amplitude,
+/// normalized to stay roughly in [-1, 1]
+pub fn fbm(p: Vec3, seed: u32, octaves: u32, lacunarity: f32, gain: f32) -> f32 {
+    let mut sum = 0.0;
+    let mut amplitude = 1.0;
+    let mut total_amplitude = 0.0;
+    let mut frequency = 1.0;
+    for octave in 0..octaves {
+        sum += amplitude * perlin(p * frequency, seed.wrapping_add(octave));
+        total_amplitude += amplitude;
+        amplitude *= gain;
+        frequency *= lacunarity;
+    }
+    if total_amplitude > 0.0 { sum / total_amplitude } else { 0.0 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zero_on_the_lattice() {
+        assert_eq!(perlin(Vec3::new(3.0, -7.0, 12.0), 1), 0.0);
+    }
+
+    #[test]
+    fn bounded_and_seeded() {
+        let mut differs = false;
+        for i in 0..1000 {
+            let p = Vec3::new(i as f32 * 0.37, i as f32 * 0.11 - 20.0, i as f32 * -0.53);
+            let value = perlin(p, 7);
+            assert!((-1.1..=1.1).contains(&value), "{} at {}", value, p);
+            assert_eq!(value, perlin(p, 7));
+            differs |= value != perlin(p, 8);
+
+            let value = fbm(p, 7, 5, 2.0, 0.5);
+            assert!((-1.1..=1.1).contains(&value), "{} at {}", value, p);
+        }
+        assert!(differs);
+    }
+
+    #[test]
+    fn continuous() {
+        let p = Vec3::new(1.3, 2.9, -0.4);
+        let step = Vec3::splat(1e-3);
+        assert!((perlin(p, 3) - perlin(p + step, 3)).abs() < 0.01);
+    }
+}
@@ -0,0 +1,330 @@
+use glam::IVec3;
+
+use crate::voxel_world::{ChunkMap, CHUNK_SIZE};
+
+/// Marks a node without children
+const NO_CHILDREN: u32 = 0;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+struct OctreeHeader {
+    origin: [i32; 4],
+    size: u32,
+    depth: u32,
+    node_count: u32,
+    _padding: u32,
+}
+
+/// Node as stored on the GPU.
+/// Branches point to their 8 contiguous children, indexed by `x | y << 1 | z << 2`.
+/// The root is the first node, so a child index of 0 means the node is a leaf holding `value`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct OctreeNode {
+    pub first_child: u32,
+    pub value: u32,
+}
+
+impl OctreeNode {
+    pub fn is_leaf(&self) -> bool {
+        self.first_child == NO_CHILDREN
+    }
+}
+
+/// Node of the CPU tree, flattened into [`OctreeNode`]s for the GPU
+#[derive(Clone, Debug, PartialEq)]
+enum Node {
+    Leaf(u32),
+    Branch(Box<[Node; 8]>),
+}
+
+impl Node {
+    /// Collapses the children into a single leaf when they are leaves of the same value
+    fn from_children(children: [Node; 8]) -> Node {
+        if let Node::Leaf(value) = children[0] {
+            if children.iter().all(|child| matches!(child, Node::Leaf(v) if *v == value)) {
+                return Node::Leaf(value);
+            }
+        }
+
+        Node::Branch(Box::new(children))
+    }
+}
+
+/// Sparse voxel octree built from the chunks of a world.
+/// Regions filled with a single value, empty ones included, are collapsed into a single leaf.
+/// The tree is aligned on the chunks, so that it can be updated one chunk at a time.
+pub struct SparseVoxelOctree {
+    origin: IVec3,
+    depth: u32,
+    root: Node,
+    nodes: Vec<OctreeNode>,
+}
+
+impl SparseVoxelOctree {
+    pub fn build(chunks: &ChunkMap) -> Self {
+        let (origin, depth) = SparseVoxelOctree::layout(chunks);
+        let chunk_coords: Vec<IVec3> = chunks.iter().map(|(coord, _)| *coord).collect();
+        let root = SparseVoxelOctree::build_node(chunks, &chunk_coords, origin, 1 << depth);
+
+        Self {
+            origin,
+            depth,
+            nodes: SparseVoxelOctree::flatten(&root),
+            root,
+        }
+    }
+
+    /// Origin and depth of the tree covering the chunks
+    fn layout(chunks: &ChunkMap) -> (IVec3, u32) {
+        match chunks.bounds() {
+            Some((min, max)) => {
+                let extent = (max - min).max_element() as u32;
+                (min, extent.next_power_of_two().trailing_zeros())
+            }
+            None => (IVec3::ZERO, 0),
+        }
+    }
+
+    /// Builds again the subtrees of the given chunks only.
+    /// Returns false, leaving the tree untouched, when the chunks outgrew it and it must be built again.
+    pub fn update_chunks<I: IntoIterator<Item = IVec3>>(&mut self, chunks: &ChunkMap, chunk_coords: I) -> bool {
+        if chunks.bounds().is_none() || SparseVoxelOctree::layout(chunks) != (self.origin, self.depth) {
+            return false;
+        }
+
+        let size = self.size();
+        let mut updated = false;
+        for chunk_coord in chunk_coords {
+            let chunk_min = chunk_coord * CHUNK_SIZE;
+            let local = chunk_min - self.origin;
+            if local.cmplt(IVec3::ZERO).any() || local.cmpge(IVec3::splat(size)).any() {
+                continue;
+            }
+            SparseVoxelOctree::update_node(&mut self.root, chunks, self.origin, size, chunk_min);
+            updated = true;
+        }
+
+        if updated {
+            self.nodes = SparseVoxelOctree::flatten(&self.root);
+        }
+        true
+    }
+
+    /// Builds the node from the chunks overlapping it, given by `chunk_coords`, the space without chunks is empty
+    fn build_node(chunks: &ChunkMap, chunk_coords: &[IVec3], min: IVec3, size: i32) -> Node {
+        if chunk_coords.is_empty() {
+            return Node::Leaf(0);
+        }
+        if size == 1 {
+            return Node::Leaf(chunks.get_voxel_at(&min));
+        }
+
+        let half = size / 2;
+        // the tree is aligned on the chunks, nodes at least as large as a chunk contain whole chunks
+        let mut child_coords: [Vec<IVec3>; 8] = Default::default();
+        if half >= CHUNK_SIZE {
+            for chunk_coord in chunk_coords {
+                let child = (*chunk_coord * CHUNK_SIZE - min) / half;
+                child_coords[(child.x | child.y << 1 | child.z << 2) as usize].push(*chunk_coord);
+            }
+        }
+        Node::from_children(std::array::from_fn(|i| {
+            // nodes smaller than a chunk are inside the single chunk of their parent
+            let coords = if half >= CHUNK_SIZE { &child_coords[i] } else { chunk_coords };
+            SparseVoxelOctree::build_node(chunks, coords, min + SparseVoxelOctree::child_offset(i) * half, half)
+        }))
+    }
+
+    /// Builds again the chunk sized node at `chunk_min` below `node`, collapsing the nodes on the way back up
+    fn update_node(node: &mut Node, chunks: &ChunkMap, min: IVec3, size: i32, chunk_min: IVec3) {
+        if size == CHUNK_SIZE {
+            let chunk_coord = ChunkMap::chunk_coord(&min);
+            let chunk_coords: &[IVec3] = if chunks.contains(&chunk_coord) { &[chunk_coord] } else { &[] };
+            *node = SparseVoxelOctree::build_node(chunks, chunk_coords, min, size);
+            return;
+        }
+
+        let half = size / 2;
+        let child = (chunk_min - min) / half;
+        let mut children = match std::mem::replace(node, Node::Leaf(0)) {
+            Node::Leaf(value) => std::array::from_fn(|_| Node::Leaf(value)),
+            Node::Branch(children) => *children,
+        };
+        let index = (child.x | child.y << 1 | child.z << 2) as usize;
+        SparseVoxelOctree::update_node(&mut children[index], chunks, min + child * half, half, chunk_min);
+        *node = Node::from_children(children);
+    }
+
+    /// Offset of a child in its parent, in units of the child size
+    fn child_offset(index: usize) -> IVec3 {
+        let index = index as i32;
+        IVec3::new(index & 1, (index >> 1) & 1, (index >> 2) & 1)
+    }
+
+    fn flatten(root: &Node) -> Vec<OctreeNode> {
+        let mut nodes = vec![OctreeNode { first_child: NO_CHILDREN, value: 0 }];
+        let mut stack = vec![(0, root)];
+
+        while let Some((index, node)) = stack.pop() {
+            match node {
+                Node::Leaf(value) => nodes[index].value = *value,
+                Node::Branch(children) => {
+                    let first_child = nodes.len();
+                    nodes[index].first_child = first_child as u32;
+                    nodes.resize(first_child + 8, OctreeNode { first_child: NO_CHILDREN, value: 0 });
+                    for (i, child) in children.iter().enumerate() {
+                        stack.push((first_child + i, child));
+                    }
+                }
+            }
+        }
+
+        nodes
+    }
+
+    /// Size of the root node side in voxels
+    pub fn size(&self) -> i32 {
+        1 << self.depth
+    }
+
+    pub fn nodes(&self) -> &[OctreeNode] {
+        &self.nodes
+    }
+
+    pub fn get_voxel_at(&self, coord: &IVec3) -> u32 {
+        let mut local = *coord - self.origin;
+        let mut size = self.size();
+        if local.cmplt(IVec3::ZERO).any() || local.cmpge(IVec3::splat(size)).any() {
+            return 0;
+        }
+
+        let mut node = self.nodes[0];
+        while !node.is_leaf() {
+            size /= 2;
+            let child = local / size;
+            local -= child * size;
+            node = self.nodes[(node.first_child + (child.x | child.y << 1 | child.z << 2) as u32) as usize];
+        }
+
+        node.value
+    }
+
+    /// Header followed by the nodes, as read by the octree raymarcher
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let header = OctreeHeader {
+            origin: [self.origin.x, self.origin.y, self.origin.z, 0],
+            size: self.size() as u32,
+            depth: self.depth,
+            node_count: self.nodes.len() as u32,
+            _padding: 0,
+        };
+
+        let mut bytes = bytemuck::bytes_of(&header).to_vec();
+        bytes.extend_from_slice(bytemuck::cast_slice(&self.nodes));
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::voxel_world::Region;
+
+    #[test]
+    fn empty_world() {
+        let octree = SparseVoxelOctree::build(&ChunkMap::new());
+        assert_eq!(octree.nodes().len(), 1);
+        assert_eq!(octree.get_voxel_at(&IVec3::ZERO), 0);
+    }
+
+    #[test]
+    fn matches_the_chunks() {
+        let mut chunks = ChunkMap::new();
+        let voxels = [
+            (IVec3::new(0, 0, 0), 1),
+            (IVec3::new(-1, 5, 3), 2),
+            (IVec3::new(17, 30, -20), 3),
+            (IVec3::new(40, 2, 9), 4),
+        ];
+        for (coord, value) in voxels.iter() {
+            chunks.set_voxel_at(*value, coord);
+        }
+
+        let octree = SparseVoxelOctree::build(&chunks);
+        let (min, max) = chunks.bounds().unwrap();
+        for z in min.z..max.z {
+            for y in min.y..max.y {
+                for x in min.x..max.x {
+                    let coord = IVec3::new(x, y, z);
+                    assert_eq!(octree.get_voxel_at(&coord), chunks.get_voxel_at(&coord), "at {}", coord);
+                }
+            }
+        }
+        assert_eq!(octree.get_voxel_at(&IVec3::splat(1000)), 0);
+    }
+
+    #[test]
+    fn uniform_regions_are_collapsed() {
+        let mut chunks = ChunkMap::new();
+        for z in 0..CHUNK_SIZE {
+            for y in 0..CHUNK_SIZE {
+                for x in 0..CHUNK_SIZE {
+                    chunks.set_voxel_at(9, &IVec3::new(x, y, z));
+                }
+            }
+        }
+
+        let octree = SparseVoxelOctree::build(&chunks);
+        assert_eq!(octree.nodes(), &[OctreeNode { first_child: NO_CHILDREN, value: 9 }]);
+
+        chunks.set_voxel_at(0, &IVec3::ZERO);
+        let octree = SparseVoxelOctree::build(&chunks);
+        // one branch per level down to the modified voxel
+        assert_eq!(octree.nodes().len(), 1 + 8 * 4);
+    }
+
+    #[test]
+    fn space_without_chunks_is_skipped() {
+        let mut chunks = ChunkMap::new();
+        chunks.set_voxel_at(1, &IVec3::ZERO);
+        chunks.set_voxel_at(2, &IVec3::new((1 << 20) - 1, 0, 0));
+
+        let octree = SparseVoxelOctree::build(&chunks);
+        assert_eq!(octree.size(), 1 << 20);
+        // the root, then one branch per level down to each voxel
+        assert_eq!(octree.nodes().len(), 1 + 8 + 2 * 19 * 8);
+        assert_eq!(octree.get_voxel_at(&IVec3::new((1 << 20) - 1, 0, 0)), 2);
+    }
+
+    #[test]
+    fn updated_chunks_match_a_new_build() {
+        let mut chunks = ChunkMap::new();
+        chunks.allocate_region(IVec3::new(-16, 0, 0), IVec3::new(48, 32, 32));
+        chunks.set_voxel_at(1, &IVec3::new(-3, 4, 5));
+        chunks.set_voxel_at(2, &IVec3::new(20, 20, 20));
+        let mut octree = SparseVoxelOctree::build(&chunks);
+
+        // filling a whole chunk collapses it, emptying it again restores the first tree
+        let first_nodes = octree.nodes().to_vec();
+        for coord in Region::new(IVec3::new(32, 16, 16), IVec3::new(48, 32, 32)).iter() {
+            chunks.set_voxel_at(7, &coord);
+        }
+        chunks.set_voxel_at(3, &IVec3::new(-10, 30, 1));
+        assert!(octree.update_chunks(&chunks, [IVec3::new(2, 1, 1), IVec3::new(-1, 1, 0)]));
+        let rebuilt = SparseVoxelOctree::build(&chunks);
+        assert_eq!(octree.root, rebuilt.root);
+        assert_eq!(octree.get_voxel_at(&IVec3::new(40, 20, 20)), 7);
+
+        for coord in Region::new(IVec3::new(32, 16, 16), IVec3::new(48, 32, 32)).iter() {
+            chunks.set_voxel_at(0, &coord);
+        }
+        chunks.set_voxel_at(0, &IVec3::new(-10, 30, 1));
+        assert!(octree.update_chunks(&chunks, [IVec3::new(2, 1, 1), IVec3::new(-1, 1, 0)]));
+        assert_eq!(octree.nodes(), first_nodes.as_slice());
+
+        // chunks outside of the tree need a new build
+        chunks.set_voxel_at(1, &IVec3::new(100, 0, 0));
+        assert!(!octree.update_chunks(&chunks, [IVec3::new(6, 0, 0)]));
+    }
+}
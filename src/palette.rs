@@ -0,0 +1,192 @@
+use crate::renderer_context::{BufferHandle, RendererContext};
+
+/// Number of materials, voxel values above the last index use the last material
+pub const PALETTE_SIZE: usize = 256;
+
+/// Material as stored on the GPU, see `Material` in the raymarchers
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Material {
+    pub albedo: [f32; 3],
+    pub roughness: f32,
+    /// Emitted color, can exceed 1 for strong emitters
+    pub emission: [f32; 3],
+    pub metallic: f32,
+    /// 0 for opaque materials, 1 for invisible ones
+    pub transparency: f32,
+    _padding: [f32; 3],
+}
+
+impl Material {
+    pub fn new(albedo: [f32; 3]) -> Self {
+        Self {
+            albedo,
+            roughness: 1.0,
+            emission: [0.0; 3],
+            metallic: 0.0,
+            transparency: 0.0,
+            _padding: [0.0; 3],
+        }
+    }
+
+    /// Opaque rough material, alpha is converted to transparency
+    pub fn from_color(color: [u8; 4]) -> Self {
+        Self {
+            transparency: 1.0 - color[3] as f32 / 255.0,
+            ..Material::new([color[0] as f32 / 255.0, color[1] as f32 / 255.0, color[2] as f32 / 255.0])
+        }
+    }
+
+    /// Albedo and opacity as 8 bit RGBA
+    pub fn color(&self) -> [u8; 4] {
+        let to_u8 = |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+        [to_u8(self.albedo[0]), to_u8(self.albedo[1]), to_u8(self.albedo[2]), to_u8(1.0 - self.transparency)]
+    }
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Material::new([1.0; 3])
+    }
+}
+
+/// Materials indexed by the voxel values, index 0 is empty space
+pub struct Palette {
+    materials: Vec<Material>,
+    buffer: Option<BufferHandle>,
+    dirty: bool,
+}
+
+impl Palette {
+    /// Grey ramp, 255 is white
+    pub fn new() -> Self {
+        Palette::from_colors(&std::array::from_fn(|i| [i as u8, i as u8, i as u8, 255]))
+    }
+
+    pub fn from_colors(colors: &[[u8; 4]; PALETTE_SIZE]) -> Self {
+        Palette::from_materials(colors.iter().map(|color| Material::from_color(*color)).collect())
+    }
+
+    /// Missing materials are white, extra ones are ignored
+    pub fn from_materials(mut materials: Vec<Material>) -> Self {
+        materials.resize(PALETTE_SIZE, Material::default());
+        Self {
+            materials,
+            buffer: None,
+            dirty: true,
+        }
+    }
+
+    pub fn colors(&self) -> [[u8; 4]; PALETTE_SIZE] {
+        std::array::from_fn(|i| self.materials[i].color())
+    }
+
+    pub fn materials(&self) -> &[Material] {
+        &self.materials
+    }
+
+    pub fn get(&self, value: u32) -> &Material {
+        &self.materials[(value as usize).min(PALETTE_SIZE - 1)]
+    }
+
+    pub fn set(&mut self, value: u32, material: Material) {
+        if let Some(entry) = self.materials.get_mut(value as usize) {
+            *entry = material;
+            self.dirty = true;
+        }
+    }
+
+    /// Replaces all the materials, keeping the GPU buffer
+    pub fn set_materials(&mut self, materials: &[Material]) {
+        for (entry, material) in self.materials.iter_mut().zip(materials) {
+            *entry = *material;
+        }
+        self.dirty = true;
+    }
+
+    pub fn get_buffer(&self) -> Option<BufferHandle> {
+        self.buffer
+    }
+
+    pub fn binding_type() -> wgpu::BindingType {
+        wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only: true },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        }
+    }
+
+    /// Uploads the materials if they changed
+    pub fn update_buffer(&mut self, renderer: &mut RendererContext) {
+        if !self.dirty {
+            return;
+        }
+
+        let contents: &[u8] = bytemuck::cast_slice(&self.materials);
+        match self.buffer {
+            Some(buffer) => renderer.update_buffer(buffer, contents),
+            None => {
+                self.buffer = Some(renderer.new_buffer(
+                    &wgpu::util::BufferInitDescriptor {
+                        label: Some("Palette"),
+                        contents,
+                        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+                    }
+                ));
+            }
+        }
+        self.dirty = false;
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::new()
+    }
+}
+
+impl Clone for Palette {
+    /// The copy has no GPU buffer
+    fn clone(&self) -> Self {
+        Palette::from_materials(self.materials.clone())
+    }
+}
+
+impl PartialEq for Palette {
+    fn eq(&self, other: &Self) -> bool {
+        self.materials == other.materials
+    }
+}
+
+impl std::fmt::Debug for Palette {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Palette").field("materials", &self.materials).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gpu_layout() {
+        assert_eq!(std::mem::size_of::<Material>(), 48);
+    }
+
+    #[test]
+    fn colors_round_trip() {
+        let mut colors = Palette::new().colors();
+        colors[3] = [10, 20, 30, 128];
+        let palette = Palette::from_colors(&colors);
+        assert_eq!(palette.colors(), colors);
+        assert!(palette.get(3).transparency > 0.49 && palette.get(3).transparency < 0.51);
+    }
+
+    #[test]
+    fn values_past_the_end_use_the_last_material() {
+        let mut palette = Palette::new();
+        palette.set(255, Material::new([1.0, 0.0, 0.0]));
+        palette.set(1000, Material::new([0.0, 1.0, 0.0]));
+        assert_eq!(palette.get(4000).albedo, [1.0, 0.0, 0.0]);
+    }
+}
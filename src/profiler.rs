@@ -0,0 +1,178 @@
+//! Frame profiler timing named CPU scopes of the frames and the GPU passes of the renderer.
+
+use std::{collections::VecDeque, time::{Duration, Instant}};
+
+/// Frames kept for the graphs
+pub const PROFILER_HISTORY: usize = 300;
+
+/// Time spent in a scope or a pass during a frame, in milliseconds
+#[derive(Debug, Clone, PartialEq)]
+pub struct Timing {
+    pub name: String,
+    pub ms: f32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FrameProfile {
+    /// Time between the start of the frame and the start of the next one, in milliseconds
+    pub duration: f32,
+    /// CPU scopes in the order they ended, the time of a scope entered several times is summed
+    pub scopes: Vec<Timing>,
+    /// GPU passes in the order they were recorded, of an earlier frame since they are read back late
+    pub gpu_passes: Vec<Timing>,
+}
+
+impl FrameProfile {
+    fn add_scope(&mut self, name: &str, ms: f32) {
+        match self.scopes.iter_mut().find(|scope| scope.name == name) {
+            Some(scope) => scope.ms += ms,
+            None => self.scopes.push(Timing {
+                name: name.to_string(),
+                ms,
+            }),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Profiler {
+    history: VecDeque<FrameProfile>,
+    current: FrameProfile,
+    frame_start: Option<Instant>,
+    /// Scopes entered and not ended yet, the innermost last
+    open_scopes: Vec<(&'static str, Instant)>,
+    /// Whether the renderer times its passes
+    pub gpu_timing: bool,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self {
+            history: VecDeque::with_capacity(PROFILER_HISTORY),
+            current: FrameProfile::default(),
+            frame_start: None,
+            open_scopes: vec![],
+            gpu_timing: false,
+        }
+    }
+
+    /// Ends the current frame, adding it to the history, and starts the next one
+    pub fn new_frame(&mut self) {
+        let now = Instant::now();
+        if let Some(start) = self.frame_start {
+            let mut frame = std::mem::take(&mut self.current);
+            frame.duration = as_ms(now - start);
+            self.push(frame);
+        }
+        self.current = FrameProfile::default();
+        self.open_scopes.clear();
+        self.frame_start = Some(now);
+    }
+
+    pub fn begin_scope(&mut self, name: &'static str) {
+        self.open_scopes.push((name, Instant::now()));
+    }
+
+    /// Ends the innermost scope
+    pub fn end_scope(&mut self) {
+        if let Some((name, start)) = self.open_scopes.pop() {
+            self.current.add_scope(name, as_ms(start.elapsed()));
+        }
+    }
+
+    /// Sets the GPU timings of the current frame
+    pub fn set_gpu_passes(&mut self, passes: Vec<Timing>) {
+        self.current.gpu_passes = passes;
+    }
+
+    fn push(&mut self, frame: FrameProfile) {
+        if self.history.len() == PROFILER_HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back(frame);
+    }
+
+    /// Ended frames, from the oldest to the newest
+    pub fn history(&self) -> impl DoubleEndedIterator<Item = &FrameProfile> + ExactSizeIterator {
+        self.history.iter()
+    }
+
+    pub fn last_frame(&self) -> Option<&FrameProfile> {
+        self.history.back()
+    }
+
+    /// Frame duration under which a fraction of the frames of the history are, in milliseconds
+    pub fn percentile(&self, fraction: f32) -> Option<f32> {
+        let mut durations: Vec<f32> = self.history.iter().map(|frame| frame.duration).collect();
+        durations.sort_by(f32::total_cmp);
+        let index = ((durations.len() as f32 - 1.0) * fraction.clamp(0.0, 1.0)).round() as usize;
+        durations.get(index).copied()
+    }
+
+    /// Mean duration of the frames of the history, in milliseconds
+    pub fn mean_frame_time(&self) -> Option<f32> {
+        (!self.history.is_empty()).then(|| self.history.iter().map(|frame| frame.duration).sum::<f32>() / self.history.len() as f32)
+    }
+
+    pub fn clear(&mut self) {
+        self.history.clear();
+    }
+}
+
+fn as_ms(duration: Duration) -> f32 {
+    duration.as_secs_f32() * 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(duration: f32) -> FrameProfile {
+        FrameProfile {
+            duration,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn scopes_are_summed_per_frame() {
+        let mut profiler = Profiler::new();
+        profiler.new_frame();
+        profiler.begin_scope("update");
+        profiler.begin_scope("physics");
+        profiler.end_scope();
+        profiler.end_scope();
+        profiler.begin_scope("physics");
+        profiler.end_scope();
+        // a scope left open is dropped with its frame
+        profiler.begin_scope("render");
+        profiler.new_frame();
+
+        let last = profiler.last_frame().unwrap();
+        let names: Vec<_> = last.scopes.iter().map(|scope| scope.name.as_str()).collect();
+        assert_eq!(names, vec!["physics", "update"]);
+        assert!(last.duration >= last.scopes[1].ms);
+        assert_eq!(profiler.history().len(), 1);
+    }
+
+    #[test]
+    fn percentiles() {
+        let mut profiler = Profiler::new();
+        assert_eq!(profiler.percentile(0.5), None);
+        for duration in (1..=PROFILER_HISTORY + 100).rev() {
+            profiler.push(frame(duration as f32));
+        }
+        // the oldest frames were dropped
+        assert_eq!(profiler.history().len(), PROFILER_HISTORY);
+        assert_eq!(profiler.percentile(0.0), Some(1.0));
+        assert_eq!(profiler.percentile(1.0), Some(PROFILER_HISTORY as f32));
+        assert_eq!(profiler.percentile(0.5), Some(151.0));
+        assert_eq!(profiler.mean_frame_time(), Some(150.5));
+    }
+}
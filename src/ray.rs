@@ -1,4 +1,6 @@
-use glam::{Vec3, Mat4};
+use glam::{IVec3, Mat4, Vec3};
+
+use crate::transform::Transform;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Ray {
@@ -24,4 +26,509 @@ impl Ray {
             direction: mat.transform_vector3(self.direction)
         }
     }
-}
\ No newline at end of file
+
+    /// Slab test against an axis aligned box.
+    /// From inside the box, the exit point is returned.
+    pub fn intersect_aabb(&self, min: Vec3, max: Vec3) -> Option<Intersection> {
+        let inv_direction = self.direction.recip();
+        let t1 = (min - self.origin) * inv_direction;
+        let t2 = (max - self.origin) * inv_direction;
+        // f32 min and max ignore the NaNs of rays parallel to a slab, starting on its plane
+        let t_min = t1.min(t2);
+        let t_max = t1.max(t2);
+        let t_near = t_min.max_element();
+        let t_far = t_max.min_element();
+        if t_far < t_near.max(0.0) {
+            return None;
+        }
+
+        let (distance, axis, sign) = if t_near >= 0.0 {
+            let axis = if t_near == t_min.x { 0 } else if t_near == t_min.y { 1 } else { 2 };
+            (t_near, axis, -self.direction[axis].signum())
+        }
+        else {
+            let axis = if t_far == t_max.x { 0 } else if t_far == t_max.y { 1 } else { 2 };
+            (t_far, axis, self.direction[axis].signum())
+        };
+        let mut normal = Vec3::ZERO;
+        normal[axis] = sign;
+        Some(Intersection {
+            distance,
+            normal,
+        })
+    }
+
+    /// Box of the given half extents, placed by the transform. From inside the box, the exit point is returned.
+    pub fn intersect_obb(&self, transform: &Transform, half_extents: Vec3) -> Option<Intersection> {
+        // affine transforms keep the distances along the ray
+        let world_to_local = transform.compute_matrix().inverse();
+        let hit = self.transform(&world_to_local).intersect_aabb(-half_extents, half_extents)?;
+        Some(Intersection {
+            distance: hit.distance,
+            normal: world_to_local.transpose().transform_vector3(hit.normal).normalize(),
+        })
+    }
+
+    /// From inside the sphere, the exit point is returned
+    pub fn intersect_sphere(&self, center: Vec3, radius: f32) -> Option<Intersection> {
+        let oc = self.origin - center;
+        let a = self.direction.length_squared();
+        let b = oc.dot(self.direction);
+        let c = oc.length_squared() - radius * radius;
+        let discriminant = b * b - a * c;
+        if discriminant < 0.0 || a == 0.0 {
+            return None;
+        }
+
+        let root = discriminant.sqrt();
+        let t_near = (-b - root) / a;
+        let t_far = (-b + root) / a;
+        let distance = if t_near >= 0.0 { t_near } else if t_far >= 0.0 { t_far } else { return None };
+        Some(Intersection {
+            distance,
+            normal: (self.at(distance) - center).normalize(),
+        })
+    }
+
+    /// Plane through `point`, the normal of the intersection faces the ray
+    pub fn intersect_plane(&self, point: Vec3, normal: Vec3) -> Option<Intersection> {
+        let denominator = normal.dot(self.direction);
+        if denominator.abs() <= f32::EPSILON {
+            return None;
+        }
+
+        let distance = normal.dot(point - self.origin) / denominator;
+        if distance < 0.0 {
+            return None;
+        }
+        Some(Intersection {
+            distance,
+            normal: if denominator < 0.0 { normal } else { -normal }.normalize(),
+        })
+    }
+
+    /// Möller-Trumbore, both sides of the triangle are hit and the normal faces the ray
+    pub fn intersect_triangle(&self, a: Vec3, b: Vec3, c: Vec3) -> Option<Intersection> {
+        let edge1 = b - a;
+        let edge2 = c - a;
+        let p = self.direction.cross(edge2);
+        let determinant = edge1.dot(p);
+        if determinant.abs() <= f32::EPSILON {
+            return None;
+        }
+
+        let inv_determinant = determinant.recip();
+        let s = self.origin - a;
+        let u = s.dot(p) * inv_determinant;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let q = s.cross(edge1);
+        let v = self.direction.dot(q) * inv_determinant;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+
+        let distance = edge2.dot(q) * inv_determinant;
+        if distance < 0.0 {
+            return None;
+        }
+        let normal = edge1.cross(edge2).normalize();
+        Some(Intersection {
+            distance,
+            normal: if normal.dot(self.direction) > 0.0 { -normal } else { normal },
+        })
+    }
+
+    /// Cells of a grid crossed by the ray, in order, up to `max_distance` along the ray
+    pub fn traverse_grid(&self, cell_size: f32, max_distance: f32) -> GridTraversal {
+        GridTraversal::new(self, cell_size, max_distance)
+    }
+}
+
+/// Intersection of a ray with a surface
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Intersection {
+    /// Position of the hit along the ray, in units of the ray direction: the point is `ray.at(distance)`
+    pub distance: f32,
+    /// Unit normal of the surface at the hit
+    pub normal: Vec3,
+}
+
+/// Cell of a grid crossed by a ray
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GridCell {
+    pub cell: IVec3,
+    /// Normal of the face the ray entered through, zero for the cell of the origin
+    pub normal: IVec3,
+    /// Position of the entry point along the ray, in units of the ray direction
+    pub distance: f32,
+}
+
+/// Iterator over the cells of a grid crossed by a ray (Amanatides & Woo DDA)
+pub struct GridTraversal {
+    cell: IVec3,
+    step: IVec3,
+    /// Distance along the ray between two crossings of a plane of each axis
+    delta: Vec3,
+    /// Distance along the ray to the next plane of each axis
+    side: Vec3,
+    normal: IVec3,
+    distance: f32,
+    max_distance: f32,
+}
+
+impl GridTraversal {
+    pub fn new(ray: &Ray, cell_size: f32, max_distance: f32) -> Self {
+        let origin = ray.origin / cell_size;
+        let direction = ray.direction / cell_size;
+        let cell = origin.floor().as_ivec3();
+        let delta = direction.recip().abs();
+        let side = Vec3::select(
+            direction.cmpgt(Vec3::ZERO),
+            (cell.as_vec3() + 1.0 - origin) * delta,
+            (origin - cell.as_vec3()) * delta,
+        );
+        Self {
+            cell,
+            step: direction.signum().as_ivec3(),
+            delta,
+            // avoids 0 * inf on the axes the ray is parallel to
+            side: Vec3::select(direction.cmpeq(Vec3::ZERO), Vec3::INFINITY, side),
+            normal: IVec3::ZERO,
+            distance: 0.0,
+            max_distance,
+        }
+    }
+}
+
+impl Iterator for GridTraversal {
+    type Item = GridCell;
+
+    fn next(&mut self) -> Option<GridCell> {
+        if self.distance > self.max_distance {
+            return None;
+        }
+
+        let current = GridCell {
+            cell: self.cell,
+            normal: self.normal,
+            distance: self.distance,
+        };
+
+        let side = self.side;
+        let axis = if side.x < side.y && side.x < side.z { 0 } else if side.y < side.z { 1 } else { 2 };
+        self.distance = side[axis];
+        self.side[axis] += self.delta[axis];
+        self.cell[axis] += self.step[axis];
+        self.normal = IVec3::ZERO;
+        self.normal[axis] = -self.step[axis];
+
+        Some(current)
+    }
+}
+
+/// First solid voxel met by a ray
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RayHit {
+    pub voxel: IVec3,
+    /// Normal of the face the ray entered through, zero if the ray started inside the voxel
+    pub normal: IVec3,
+    /// Position of the entry point along the ray, in units of the ray direction like [`Intersection::distance`]
+    pub distance: f32,
+    /// Voxel value, which indexes the palette
+    pub value: u32,
+}
+
+impl RayHit {
+    /// Position of the entry point
+    pub fn point(&self, ray: &Ray) -> Vec3 {
+        ray.at(self.distance)
+    }
+
+    /// Empty voxel in front of the hit face, where a voxel would be added
+    pub fn adjacent_voxel(&self) -> IVec3 {
+        self.voxel + self.normal
+    }
+}
+
+/// Walks the voxels crossed by the ray in order and returns the first one whose value isn't 0,
+/// up to `max_distance` along the ray, in units of the ray direction
+pub fn raycast_voxels<F: FnMut(&IVec3) -> u32>(ray: &Ray, max_distance: f32, mut voxel_at: F) -> Option<RayHit> {
+    ray.traverse_grid(1.0, max_distance)
+        .find_map(|cell| {
+            let value = voxel_at(&cell.cell);
+            (value != 0).then_some(RayHit {
+                voxel: cell.cell,
+                normal: cell.normal,
+                distance: cell.distance,
+                value,
+            })
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hits_the_first_solid_voxel() {
+        let solid = |coord: &IVec3| if coord.x >= 5 { 7 } else { 0 };
+        let ray = Ray::new(Vec3::new(0.5, 0.5, 0.5), Vec3::new(2.0, 0.0, 0.0));
+        let hit = raycast_voxels(&ray, 100.0, solid).unwrap();
+        assert_eq!(hit.voxel, IVec3::new(5, 0, 0));
+        assert_eq!(hit.normal, IVec3::NEG_X);
+        assert_eq!(hit.value, 7);
+        // the direction is two voxels long
+        assert!((hit.distance - 2.25).abs() < 1e-5);
+        assert!(hit.point(&ray).abs_diff_eq(Vec3::new(5.0, 0.5, 0.5), 1e-5));
+        assert_eq!(hit.adjacent_voxel(), IVec3::new(4, 0, 0));
+
+        assert_eq!(raycast_voxels(&ray, 2.0, solid), None);
+        assert_eq!(raycast_voxels(&Ray::new(ray.origin, -ray.direction), 100.0, solid), None);
+    }
+
+    #[test]
+    fn diagonal_rays_cross_every_voxel() {
+        let ray = Ray::new(Vec3::new(-0.3, 2.7, 0.2), Vec3::new(1.0, -0.6, 0.45));
+        let mut visited = vec![];
+        raycast_voxels(&ray, 20.0, |coord| {
+            visited.push(*coord);
+            0
+        });
+        // consecutive voxels share a face
+        for pair in visited.windows(2) {
+            let step = (pair[1] - pair[0]).abs();
+            assert_eq!(step.x + step.y + step.z, 1, "{:?}", pair);
+        }
+        assert_eq!(visited[0], IVec3::new(-1, 2, 0));
+
+        let target = visited[visited.len() / 2];
+        let hit = raycast_voxels(&ray, 20.0, |coord| (*coord == target) as u32).unwrap();
+        assert_eq!(hit.voxel, target);
+        // the entry point is on the hit face
+        let point = hit.point(&ray);
+        let face = (target.as_vec3() + 0.5 + hit.normal.as_vec3() * 0.5).dot(hit.normal.as_vec3().abs());
+        assert!((point.dot(hit.normal.as_vec3().abs()) - face).abs() < 1e-4);
+    }
+
+    #[test]
+    fn starting_inside_a_voxel() {
+        let hit = raycast_voxels(&Ray::new(Vec3::new(1.5, -0.5, 3.2), Vec3::Y), 10.0, |_| 1).unwrap();
+        assert_eq!(hit.voxel, IVec3::new(1, -1, 3));
+        assert_eq!(hit.normal, IVec3::ZERO);
+        assert_eq!(hit.distance, 0.0);
+    }
+
+    #[test]
+    fn aabb_hits() {
+        let ray = Ray::new(Vec3::new(-5.0, 0.5, 0.5), Vec3::X);
+        let hit = ray.intersect_aabb(Vec3::ZERO, Vec3::ONE).unwrap();
+        assert_eq!(hit.distance, 5.0);
+        assert_eq!(hit.normal, Vec3::NEG_X);
+
+        // from inside, the exit face
+        let hit = Ray::new(Vec3::splat(0.5), Vec3::new(0.0, 2.0, 0.0)).intersect_aabb(Vec3::ZERO, Vec3::ONE).unwrap();
+        assert_eq!(hit.distance, 0.25);
+        assert_eq!(hit.normal, Vec3::Y);
+
+        assert_eq!(Ray::new(Vec3::new(-5.0, 2.0, 0.5), Vec3::X).intersect_aabb(Vec3::ZERO, Vec3::ONE), None);
+        assert_eq!(Ray::new(Vec3::new(5.0, 0.5, 0.5), Vec3::X).intersect_aabb(Vec3::ZERO, Vec3::ONE), None);
+    }
+
+    #[test]
+    fn obb_hits() {
+        let mut transform = Transform::from_position(Vec3::new(10.0, 0.0, 0.0));
+        transform.rotate_y(std::f32::consts::FRAC_PI_4);
+        let hit = Ray::new(Vec3::ZERO, Vec3::X).intersect_obb(&transform, Vec3::ONE).unwrap();
+        // the corner of the rotated box points at the origin
+        assert!((hit.distance - (10.0 - std::f32::consts::SQRT_2)).abs() < 1e-5);
+        assert!(hit.normal.dot(Vec3::NEG_X) > 0.7);
+        assert!((hit.normal.length() - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn sphere_plane_and_triangle_hits() {
+        let ray = Ray::new(Vec3::ZERO, Vec3::Z);
+        let hit = ray.intersect_sphere(Vec3::new(0.0, 0.0, 10.0), 2.0).unwrap();
+        assert_eq!(hit.distance, 8.0);
+        assert_eq!(hit.normal, Vec3::NEG_Z);
+        assert_eq!(ray.intersect_sphere(Vec3::new(0.0, 0.0, -10.0), 2.0), None);
+
+        let hit = ray.intersect_plane(Vec3::new(0.0, 0.0, 3.0), Vec3::Z).unwrap();
+        assert_eq!(hit.distance, 3.0);
+        assert_eq!(hit.normal, Vec3::NEG_Z);
+        assert_eq!(ray.intersect_plane(Vec3::new(0.0, 0.0, 3.0), Vec3::X), None);
+
+        let (a, b, c) = (Vec3::new(-1.0, -1.0, 4.0), Vec3::new(2.0, -1.0, 4.0), Vec3::new(-1.0, 2.0, 4.0));
+        let hit = ray.intersect_triangle(a, b, c).unwrap();
+        assert_eq!(hit.distance, 4.0);
+        assert_eq!(hit.normal, Vec3::NEG_Z);
+        assert_eq!(ray.intersect_triangle(a + Vec3::X * 3.0, b + Vec3::X * 3.0, c + Vec3::X * 3.0), None);
+    }
+
+    #[test]
+    fn grid_traversal_with_large_cells() {
+        let cells: Vec<_> = Ray::new(Vec3::new(1.0, 1.0, 1.0), Vec3::X)
+            .traverse_grid(16.0, 40.0)
+            .map(|cell| (cell.cell, cell.distance))
+            .collect();
+        assert_eq!(cells, vec![(IVec3::ZERO, 0.0), (IVec3::X, 15.0), (IVec3::new(2, 0, 0), 31.0)]);
+
+        // a ray without direction only sees its own cell
+        assert_eq!(Ray::new(Vec3::ONE, Vec3::ZERO).traverse_grid(1.0, 10.0).count(), 1);
+    }
+
+    mod properties {
+        use std::ops::Range;
+
+        use glam::Quat;
+        use proptest::prelude::*;
+
+        use super::*;
+
+        /// Samples taken along the rays, the hits found by sampling are at most this far before the exact ones
+        const STEP: f32 = 1e-3;
+        const MAX_DISTANCE: f32 = 30.0;
+        const TOLERANCE: f32 = 1e-3;
+
+        fn vec3(range: Range<f32>) -> impl Strategy<Value = Vec3> {
+            (range.clone(), range.clone(), range).prop_map(|(x, y, z)| Vec3::new(x, y, z))
+        }
+
+        fn direction() -> impl Strategy<Value = Vec3> {
+            vec3(-1.0..1.0).prop_filter("direction too short", |direction| direction.length() > 0.1)
+        }
+
+        /// Distance of the first sample inside the shape
+        fn first_sample_inside<F: Fn(Vec3) -> bool>(ray: &Ray, inside: F) -> Option<f32> {
+            let samples = (MAX_DISTANCE / STEP) as u32;
+            (0..=samples).map(|i| i as f32 * STEP).find(|t| inside(ray.at(*t)))
+        }
+
+        fn box_distance(p: Vec3, half_extents: Vec3) -> f32 {
+            let q = p.abs() - half_extents;
+            q.max(Vec3::ZERO).length() + q.max_element().min(0.0)
+        }
+
+        /// Compares an intersection with the first sample inside a solid whose origin is outside.
+        /// Rays grazing the surface between two samples are only checked to end on the surface.
+        fn check_solid<F: Fn(Vec3) -> f32>(ray: &Ray, hit: Option<Intersection>, distance: F) -> Result<(), TestCaseError> {
+            let step = STEP * ray.direction.length();
+            match (first_sample_inside(ray, |p| distance(p) <= 0.0), hit) {
+                (Some(sampled), Some(hit)) => {
+                    prop_assert!(hit.distance <= sampled + TOLERANCE && hit.distance >= sampled - STEP - TOLERANCE, "{:?} sampled at {}", hit, sampled);
+                    prop_assert!(hit.normal.dot(ray.direction) <= TOLERANCE, "normal {} faces away", hit.normal);
+                }
+                (Some(sampled), None) => prop_assert!(false, "missed, sampled at {}", sampled),
+                (None, Some(hit)) if hit.distance <= MAX_DISTANCE => {
+                    prop_assert!(distance(ray.at(hit.distance)).abs() < step + TOLERANCE, "{:?} isn't a grazing hit", hit);
+                }
+                _ => {}
+            }
+            Ok(())
+        }
+
+        proptest! {
+            #[test]
+            fn aabb_matches_sampling(origin in vec3(-10.0..10.0), direction in direction(), min in vec3(-5.0..5.0), size in vec3(0.1..5.0)) {
+                let center = min + size * 0.5;
+                let distance = |p: Vec3| box_distance(p - center, size * 0.5);
+                prop_assume!(distance(origin) > 0.0);
+                let ray = Ray::new(origin, direction);
+                check_solid(&ray, ray.intersect_aabb(min, min + size), distance)?;
+            }
+
+            #[test]
+            fn obb_matches_sampling(origin in vec3(-10.0..10.0), direction in direction(), position in vec3(-5.0..5.0), axis in direction(), angle in 0.0f32..6.3, half_extents in vec3(0.1..3.0)) {
+                let transform = Transform {
+                    position,
+                    rotation: Quat::from_axis_angle(axis.normalize(), angle),
+                    scale: Vec3::ONE,
+                };
+                let world_to_local = transform.compute_matrix().inverse();
+                let distance = |p: Vec3| box_distance(world_to_local.transform_point3(p), half_extents);
+                prop_assume!(distance(origin) > 0.0);
+                let ray = Ray::new(origin, direction);
+                check_solid(&ray, ray.intersect_obb(&transform, half_extents), distance)?;
+            }
+
+            #[test]
+            fn sphere_matches_sampling(origin in vec3(-10.0..10.0), direction in direction(), center in vec3(-5.0..5.0), radius in 0.1f32..5.0) {
+                let distance = |p: Vec3| p.distance(center) - radius;
+                prop_assume!(distance(origin) > 0.0);
+                let ray = Ray::new(origin, direction);
+                check_solid(&ray, ray.intersect_sphere(center, radius), distance)?;
+            }
+
+            #[test]
+            fn plane_matches_sampling(origin in vec3(-10.0..10.0), direction in direction(), point in vec3(-5.0..5.0), normal in direction()) {
+                let side = |p: Vec3| normal.dot(p - point);
+                prop_assume!(side(origin).abs() > TOLERANCE);
+                let ray = Ray::new(origin, direction);
+                let crossing = first_sample_inside(&ray, |p| side(p).signum() != side(origin).signum());
+                match (crossing, ray.intersect_plane(point, normal)) {
+                    (Some(sampled), Some(hit)) => {
+                        prop_assert!(hit.distance <= sampled + TOLERANCE && hit.distance >= sampled - STEP - TOLERANCE, "{:?} sampled at {}", hit, sampled);
+                        prop_assert!(hit.normal.dot(ray.direction) < 0.0);
+                    }
+                    (Some(sampled), None) => prop_assert!(false, "missed, sampled at {}", sampled),
+                    (None, Some(hit)) => prop_assert!(hit.distance > MAX_DISTANCE - TOLERANCE, "{:?} wasn't sampled", hit),
+                    (None, None) => {}
+                }
+            }
+
+            #[test]
+            fn triangle_matches_sampling(origin in vec3(-10.0..10.0), direction in direction(), a in vec3(-5.0..5.0), b in vec3(-5.0..5.0), c in vec3(-5.0..5.0)) {
+                let normal = (b - a).cross(c - a);
+                prop_assume!(normal.length() > 1.0);
+                let side = |p: Vec3| normal.dot(p - a);
+                prop_assume!(side(origin).abs() > TOLERANCE);
+                let ray = Ray::new(origin, direction);
+                let hit = ray.intersect_triangle(a, b, c);
+
+                let Some(sampled) = first_sample_inside(&ray, |p| side(p).signum() != side(origin).signum()) else {
+                    prop_assert!(hit.is_none_or(|hit| hit.distance > MAX_DISTANCE - TOLERANCE), "{:?} wasn't sampled", hit);
+                    return Ok(());
+                };
+
+                // barycentric coordinates of the sample from the areas of the sub triangles
+                let p = ray.at(sampled);
+                let area = |u: Vec3, v: Vec3, w: Vec3| (v - u).cross(w - u).dot(normal) / normal.length_squared();
+                let weights = Vec3::new(area(p, b, c), area(a, p, c), area(a, b, p));
+                if weights.min_element() > 0.01 {
+                    let hit = hit.expect("missed a sample inside the triangle");
+                    prop_assert!(hit.distance <= sampled + TOLERANCE && hit.distance >= sampled - STEP - TOLERANCE, "{:?} sampled at {}", hit, sampled);
+                }
+                else if weights.min_element() < -0.01 {
+                    prop_assert_eq!(hit, None);
+                }
+            }
+
+            #[test]
+            fn grid_traversal_matches_sampling(origin in vec3(-10.0..10.0), direction in direction(), cell_size in 0.5f32..4.0) {
+                let ray = Ray::new(origin, direction);
+                let max_distance = 20.0;
+                let cells: Vec<_> = ray.traverse_grid(cell_size, max_distance).collect();
+
+                prop_assert_eq!(cells[0].cell, (origin / cell_size).floor().as_ivec3());
+                for pair in cells.windows(2) {
+                    let step = pair[1].cell - pair[0].cell;
+                    prop_assert_eq!(step.abs().x + step.abs().y + step.abs().z, 1);
+                    prop_assert_eq!(pair[1].normal, -step);
+                    prop_assert!(pair[1].distance >= pair[0].distance);
+                }
+
+                // every sampled cell is crossed, in the same order
+                let mut current = 0;
+                let samples = (max_distance / STEP) as u32;
+                for t in (0..samples).map(|i| i as f32 * STEP) {
+                    let cell = (ray.at(t) / cell_size).floor().as_ivec3();
+                    let found = cells[current..].iter().position(|crossed| crossed.cell == cell);
+                    prop_assert!(found.is_some(), "{} sampled at {} isn't crossed", cell, t);
+                    current += found.unwrap();
+                }
+            }
+        }
+    }
+}
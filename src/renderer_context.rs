@@ -1,16 +1,30 @@
-use std::{ops::Range, sync::Arc};
+use std::{cell::{Cell, RefCell}, ops::Range, sync::{mpsc::{self, Receiver, TryRecvError}, Arc}};
 
 use slotmap::{SlotMap, new_key_type};
 use thiserror::Error;
 use wgpu::{util::DeviceExt, BindGroupLayoutEntry, Color, Extent3d, ImageDataLayout};
 use winit::window::Window;
 
+use crate::{image::Image, profiler::Timing};
+
 #[derive(Error, Debug)]
 pub enum RendererContextError {
     #[error("Surface error")]
     SurfaceError(#[from] wgpu::SurfaceError),
     #[error("Could not create shader module: {0}")]
     CreateShaderModule(String),
+    #[error("No suitable graphics adapter found")]
+    NoAdapter,
+    #[error("Could not request device")]
+    RequestDevice(#[from] wgpu::RequestDeviceError),
+    #[error("Unknown texture")]
+    UnknownTexture,
+    #[error("Texture format {0:?} can't be read back")]
+    UnsupportedReadbackFormat(wgpu::TextureFormat),
+    #[error("Texture was not created with COPY_SRC usage")]
+    MissingCopySrcUsage,
+    #[error("Could not map buffer")]
+    BufferMap(#[from] wgpu::BufferAsyncError),
 }
 
 #[repr(C)]
@@ -20,6 +34,14 @@ pub struct Resolution {
     pub height: u32
 }
 
+/// Format of the offscreen target used by headless contexts
+const OFFSCREEN_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+/// Passes timed in a frame at most, the following ones aren't
+const MAX_TIMED_PASSES: u32 = 32;
+/// Frames whose timestamps can be waiting to be read back, the next ones aren't timed until one is read
+const TIMING_READBACKS: usize = 3;
+
 new_key_type! {
     pub struct TextureId;
     pub struct BufferId;
@@ -84,10 +106,14 @@ impl<'a> ComputePass<'a> {
 }
 
 pub struct ComputePassDesc {
+    /// Name of the pass in the GPU timings and debuggers
+    pub label: &'static str,
     pub bind_group: BindGroupHandle,
     pub pipeline: ComputePipelineHandle,
 }
 pub struct RenderPassDesc {
+    /// Name of the pass in the GPU timings and debuggers
+    pub label: &'static str,
     pub bind_group: BindGroupHandle,
     pub pipeline: RenderPipelineHandle,
     pub load_op: wgpu::LoadOp<Color>,
@@ -108,18 +134,177 @@ pub struct Binding {
     pub resource: BindingResource,
 }
 
-/// Permits render to the current surface texture
+/// Where the frames end up: the window surface or an offscreen texture
+enum RenderTarget {
+    Surface {
+        surface: wgpu::Surface<'static>,
+        conf: wgpu::SurfaceConfiguration,
+    },
+    Offscreen {
+        texture: TextureHandle,
+        format: wgpu::TextureFormat,
+    },
+}
+
+/// Durations of the passes of a frame, read back a few frames after it was committed
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GpuTimingReport {
+    /// Number of committed frames before this one
+    pub frame: u64,
+    /// Passes in the order they were recorded
+    pub passes: Vec<Timing>,
+}
+
+/// Buffer the timestamps of a frame are copied to, mapped once the GPU is done with it
+struct Readback {
+    buffer: wgpu::Buffer,
+    /// `None` when the buffer is free
+    pending: Option<PendingReadback>,
+}
+
+struct PendingReadback {
+    frame: u64,
+    labels: Vec<&'static str>,
+    /// Result of mapping the buffer, not sent before the copy is submitted
+    mapped: Option<Receiver<Result<(), wgpu::BufferAsyncError>>>,
+}
+
+/// Timestamp queries written at the beginning and the end of the passes of a frame,
+/// read back without waiting for the GPU
+struct GpuTimer {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    readbacks: RefCell<Vec<Readback>>,
+    /// Nanoseconds per timestamp tick
+    period: f32,
+    report: RefCell<Option<GpuTimingReport>>,
+}
+
+impl GpuTimer {
+    fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let size = MAX_TIMED_PASSES as u64 * 2 * wgpu::QUERY_SIZE as u64;
+        Self {
+            query_set: device.create_query_set(&wgpu::QuerySetDescriptor {
+                label: Some("Pass timestamps"),
+                ty: wgpu::QueryType::Timestamp,
+                count: MAX_TIMED_PASSES * 2,
+            }),
+            resolve_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Pass timestamps resolve buffer"),
+                size,
+                usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            }),
+            readbacks: RefCell::new(
+                (0..TIMING_READBACKS)
+                    .map(|_| Readback {
+                        buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                            label: Some("Pass timestamps read buffer"),
+                            size,
+                            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                            mapped_at_creation: false,
+                        }),
+                        pending: None,
+                    })
+                    .collect(),
+            ),
+            period: queue.get_timestamp_period(),
+            report: RefCell::default(),
+        }
+    }
+
+    /// True when the timestamps of a frame can be read back
+    fn has_free_readback(&self) -> bool {
+        self.readbacks.borrow().iter().any(|readback| readback.pending.is_none())
+    }
+
+    /// Copies the timestamps of the passes to a free read buffer, returns its index
+    fn resolve(&self, encoder: &mut wgpu::CommandEncoder, frame: u64, labels: Vec<&'static str>) -> Option<usize> {
+        let mut readbacks = self.readbacks.borrow_mut();
+        let (index, readback) = readbacks.iter_mut().enumerate().find(|(_, readback)| readback.pending.is_none())?;
+        let query_count = labels.len() as u32 * 2;
+        encoder.resolve_query_set(&self.query_set, 0..query_count, &self.resolve_buffer, 0);
+        encoder.copy_buffer_to_buffer(&self.resolve_buffer, 0, &readback.buffer, 0, query_count as u64 * wgpu::QUERY_SIZE as u64);
+        readback.pending = Some(PendingReadback {
+            frame,
+            labels,
+            mapped: None,
+        });
+        Some(index)
+    }
+
+    /// Maps a read buffer once the copy to it is submitted
+    fn map(&self, index: usize) {
+        let mut readbacks = self.readbacks.borrow_mut();
+        let readback = &mut readbacks[index];
+        if let Some(pending) = &mut readback.pending {
+            let (sender, receiver) = mpsc::channel();
+            readback
+                .buffer
+                .slice(..pending.labels.len() as u64 * 2 * wgpu::QUERY_SIZE as u64)
+                .map_async(wgpu::MapMode::Read, move |result| {
+                    let _ = sender.send(result);
+                });
+            pending.mapped = Some(receiver);
+        }
+    }
+
+    /// Reads the buffers mapped since the last call, the newest frame read becomes the report
+    fn poll(&self, device: &wgpu::Device) {
+        device.poll(wgpu::Maintain::Poll);
+        for readback in self.readbacks.borrow_mut().iter_mut() {
+            let Some(pending) = &readback.pending else {
+                continue;
+            };
+            match pending.mapped.as_ref().map(Receiver::try_recv) {
+                None | Some(Err(TryRecvError::Empty)) => continue,
+                Some(Ok(Ok(()))) => {
+                    let slice = readback.buffer.slice(..pending.labels.len() as u64 * 2 * wgpu::QUERY_SIZE as u64);
+                    let timestamps: Vec<u64> = slice
+                        .get_mapped_range()
+                        .chunks_exact(8)
+                        .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+                        .collect();
+                    readback.buffer.unmap();
+
+                    let mut report = self.report.borrow_mut();
+                    if report.as_ref().is_none_or(|report| report.frame < pending.frame) {
+                        *report = Some(GpuTimingReport {
+                            frame: pending.frame,
+                            passes: pending
+                                .labels
+                                .iter()
+                                .zip(timestamps.chunks_exact(2))
+                                .map(|(label, pass)| Timing {
+                                    name: label.to_string(),
+                                    ms: pass[1].saturating_sub(pass[0]) as f32 * self.period / 1_000_000.0,
+                                })
+                                .collect(),
+                        });
+                    }
+                }
+                Some(Ok(Err(e))) => log::error!("{}", RendererContextError::BufferMap(e)),
+                Some(Err(TryRecvError::Disconnected)) => log::error!("{}", RendererContextError::BufferMap(wgpu::BufferAsyncError)),
+            }
+            readback.pending = None;
+        }
+    }
+}
+
+/// Permits render to the current surface texture, or to the offscreen target when headless
 pub struct Frame<'a> {
     renderer: &'a RendererContext,
-    surface_texture: wgpu::SurfaceTexture,
+    surface_texture: Option<wgpu::SurfaceTexture>,
     view: wgpu::TextureView,
     encoder: wgpu::CommandEncoder,
+    /// Labels of the passes whose timestamps are written, in the order of their queries
+    timed_passes: Vec<&'static str>,
 }
 
 impl<'a> Frame<'a> {
     pub fn new(
         renderer: &'a RendererContext, 
-        surface_texture: wgpu::SurfaceTexture,
+        surface_texture: Option<wgpu::SurfaceTexture>,
         view: wgpu::TextureView, 
         encoder: wgpu::CommandEncoder) -> Self {
         Self {
@@ -127,10 +312,24 @@ impl<'a> Frame<'a> {
             surface_texture,
             view,
             encoder,
+            timed_passes: vec![],
         }
     }
 
-    pub fn new_render_pass(&mut self, load_op: wgpu::LoadOp<wgpu::Color>) -> wgpu::RenderPass {
+    /// Query set and index of the first query of the next pass, when the passes are timed.
+    /// They aren't while the timestamps of the previous frames are all waiting to be read back.
+    fn next_timestamps(&mut self, label: &'static str) -> Option<(&'a wgpu::QuerySet, u32)> {
+        let timer = self.renderer.gpu_timer.as_ref().filter(|_| self.renderer.gpu_timing)?;
+        let pass = self.timed_passes.len() as u32;
+        if pass == MAX_TIMED_PASSES || (pass == 0 && !timer.has_free_readback()) {
+            return None;
+        }
+        self.timed_passes.push(label);
+        Some((&timer.query_set, pass * 2))
+    }
+
+    pub fn new_render_pass(&mut self, label: &'static str, load_op: wgpu::LoadOp<wgpu::Color>) -> wgpu::RenderPass {
+        let timestamps = self.next_timestamps(label);
         self.encoder.begin_render_pass(
             &wgpu::RenderPassDescriptor {
                 color_attachments: &[Some(wgpu::RenderPassColorAttachment {
@@ -142,8 +341,12 @@ impl<'a> Frame<'a> {
                     },
                 })],
                 depth_stencil_attachment: None,
-                label: Some("Render pass"),
-                timestamp_writes: None,
+                label: Some(label),
+                timestamp_writes: timestamps.map(|(query_set, index)| wgpu::RenderPassTimestampWrites {
+                    query_set,
+                    beginning_of_pass_write_index: Some(index),
+                    end_of_pass_write_index: Some(index + 1),
+                }),
                 occlusion_query_set: None,
             }
         )
@@ -153,7 +356,7 @@ impl<'a> Frame<'a> {
         let render_pipeline = self.renderer.render_pipelines.get(desc.pipeline).unwrap();
         let bind_group = self.renderer.bind_groups.get(desc.bind_group).unwrap();
 
-        let mut render_pass = self.new_render_pass(desc.load_op);
+        let mut render_pass = self.new_render_pass(desc.label, desc.load_op);
         render_pass.set_bind_group(0, &bind_group, &[]);
         render_pass.set_pipeline(&render_pipeline.pipeline);
 
@@ -164,10 +367,15 @@ impl<'a> Frame<'a> {
         let compute_pipeline = self.renderer.compute_pipelines.get(desc.pipeline).unwrap();
         let bind_group = self.renderer.bind_groups.get(desc.bind_group).unwrap();
 
+        let timestamps = self.next_timestamps(desc.label);
         let mut cpass = self.encoder.begin_compute_pass(
             &wgpu::ComputePassDescriptor {
-                label: Some("Compute pass"),
-                timestamp_writes: None,
+                label: Some(desc.label),
+                timestamp_writes: timestamps.map(|(query_set, index)| wgpu::ComputePassTimestampWrites {
+                    query_set,
+                    beginning_of_pass_write_index: Some(index),
+                    end_of_pass_write_index: Some(index + 1),
+                }),
             }
         );
 
@@ -183,8 +391,8 @@ impl<'a> Frame<'a> {
 }
 
 pub struct RendererContext {
-    surface: wgpu::Surface<'static>,
-    surface_conf: wgpu::SurfaceConfiguration,
+    target: RenderTarget,
+    adapter_info: wgpu::AdapterInfo,
     device: wgpu::Device,
     queue: wgpu::Queue,
     resolution: Resolution,
@@ -194,6 +402,11 @@ pub struct RendererContext {
     render_pipelines: SlotMap<RenderPipelineId, RenderPipeline>,
     compute_pipelines: SlotMap<ComputePipelineId, ComputePipeline>,
     bind_groups: SlotMap<BindGroupId, wgpu::BindGroup>,
+    /// `None` when the adapter can't write timestamps
+    gpu_timer: Option<GpuTimer>,
+    gpu_timing: bool,
+    /// Frames committed so far
+    frame_count: Cell<u64>,
 }
 
 impl RendererContext {
@@ -202,7 +415,7 @@ impl RendererContext {
         size.width = size.width.max(1);
         size.height = size.height.max(1);
 
-        env_logger::init();
+        crate::logger::init();
 
         // Instance
         let instance = wgpu::Instance::default();
@@ -222,7 +435,7 @@ impl RendererContext {
         // Device and queue
         let (device, queue) = adapter.request_device(
             &wgpu::DeviceDescriptor {
-                required_features: wgpu::Features::empty(),
+                required_features: adapter.features() & wgpu::Features::TIMESTAMP_QUERY,
                 // WebGL doesn't support all of wgpu's features, so if
                 // we're building for the web we'll have to disable some.
                 required_limits: if cfg!(target_arch = "wasm32") {
@@ -240,24 +453,145 @@ impl RendererContext {
             .unwrap();
         surface.configure(&device, &surface_conf);
 
-        Self {
-            surface,
-            surface_conf,
+        Self::from_parts(
+            RenderTarget::Surface {
+                surface,
+                conf: surface_conf,
+            },
+            adapter.get_info(),
             device,
             queue,
-            resolution: Resolution {
+            Resolution {
                 width: size.width,
                 height: size.height,
             },
+        )
+    }
+
+    /// Creates a context without window nor surface, rendering into an offscreen texture.
+    /// A fallback (software) adapter is preferred so that it runs on machines without GPU.
+    pub async fn new_headless(resolution: Resolution) -> Result<Self, RendererContextError> {
+        let resolution = Resolution {
+            width: resolution.width.max(1),
+            height: resolution.height.max(1),
+        };
+
+        crate::logger::init();
+
+        // Instance
+        let instance = wgpu::Instance::default();
+
+        // Adapter
+        let mut adapter = instance.request_adapter(
+            &wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::default(),
+                compatible_surface: None,
+                force_fallback_adapter: true,
+            },
+        ).await;
+        if adapter.is_none() {
+            adapter = instance.request_adapter(
+                &wgpu::RequestAdapterOptions {
+                    power_preference: wgpu::PowerPreference::LowPower,
+                    compatible_surface: None,
+                    force_fallback_adapter: false,
+                },
+            ).await;
+        }
+        let adapter = adapter.ok_or(RendererContextError::NoAdapter)?;
+
+        // Device and queue
+        let (device, queue) = adapter.request_device(
+            &wgpu::DeviceDescriptor {
+                required_features: adapter.features() & wgpu::Features::TIMESTAMP_QUERY,
+                // Software adapters may not reach the default limits
+                required_limits: wgpu::Limits::downlevel_defaults()
+                    .using_resolution(adapter.limits()),
+                label: None,
+            },
+            None, // Trace path
+        ).await?;
+
+        let mut renderer = Self::from_parts(
+            RenderTarget::Offscreen {
+                texture: TextureHandle::default(),
+                format: OFFSCREEN_FORMAT,
+            },
+            adapter.get_info(),
+            device,
+            queue,
+            resolution,
+        );
+        let texture = renderer.new_texture(&Self::offscreen_texture_desc(resolution));
+        renderer.target = RenderTarget::Offscreen {
+            texture,
+            format: OFFSCREEN_FORMAT,
+        };
+
+        Ok(renderer)
+    }
+
+    fn from_parts(
+        target: RenderTarget,
+        adapter_info: wgpu::AdapterInfo,
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        resolution: Resolution
+    ) -> Self {
+        let gpu_timer = device
+            .features()
+            .contains(wgpu::Features::TIMESTAMP_QUERY)
+            .then(|| GpuTimer::new(&device, &queue));
+        if gpu_timer.is_none() {
+            log::info!("The adapter can't write timestamps, the GPU passes won't be timed");
+        }
+        Self {
+            target,
+            adapter_info,
+            device,
+            queue,
+            resolution,
             textures: SlotMap::default(),
             buffers: SlotMap::default(),
             shaders: SlotMap::default(),
             render_pipelines: SlotMap::default(),
             compute_pipelines: SlotMap::default(),
             bind_groups: SlotMap::default(),
+            gpu_timer,
+            gpu_timing: false,
+            frame_count: Cell::new(0),
         }
     }
 
+    fn offscreen_texture_desc(resolution: Resolution) -> wgpu::TextureDescriptor<'static> {
+        wgpu::TextureDescriptor {
+            label: Some("Offscreen target"),
+            size: wgpu::Extent3d {
+                width: resolution.width,
+                height: resolution.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: OFFSCREEN_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        }
+    }
+
+    /// Format of the surface or offscreen texture frames are rendered to
+    pub fn target_format(&self) -> wgpu::TextureFormat {
+        match &self.target {
+            RenderTarget::Surface { conf, .. } => conf.format,
+            RenderTarget::Offscreen { format, .. } => *format,
+        }
+    }
+
+    pub fn adapter_info(&self) -> &wgpu::AdapterInfo {
+        &self.adapter_info
+    }
+
     pub fn device(&self) -> &wgpu::Device {
         &self.device
     }
@@ -269,9 +603,17 @@ impl RendererContext {
     pub fn resize(&mut self, resolution: Resolution) {
         if resolution.width > 0 && resolution.height > 0 {
             self.resolution = resolution;
-            self.surface_conf.width = resolution.width;
-            self.surface_conf.height = resolution.height;
-            self.surface.configure(&self.device, &self.surface_conf);
+            match &mut self.target {
+                RenderTarget::Surface { surface, conf } => {
+                    conf.width = resolution.width;
+                    conf.height = resolution.height;
+                    surface.configure(&self.device, conf);
+                }
+                RenderTarget::Offscreen { texture, .. } => {
+                    let texture = *texture;
+                    self.update_texture(texture, &Self::offscreen_texture_desc(resolution));
+                }
+            }
         }
     }
 
@@ -319,7 +661,7 @@ impl RendererContext {
             fragment: Some(wgpu::FragmentState {
                 module: &shader_module,
                 entry_point: "fs_main",
-                targets: &[Some(self.surface_conf.format.into())],
+                targets: &[Some(self.target_format().into())],
             }),
             primitive: wgpu::PrimitiveState::default(),
             depth_stencil: None,
@@ -463,11 +805,16 @@ impl RendererContext {
     }
 
     pub fn update_buffer(&mut self, handle: BufferHandle, contents: &[u8]) {
+        self.write_buffer(handle, 0, contents);
+    }
+
+    /// Writes the contents at an offset of the buffer, in bytes
+    pub fn write_buffer(&mut self, handle: BufferHandle, offset: u64, contents: &[u8]) {
         if let Some(buffer) = self.buffers.get_mut(handle) {
-            self.queue.write_buffer(buffer, 0, contents);
+            self.queue.write_buffer(buffer, offset, contents);
         }
         else {
-            print!("Unknown buffer")
+            log::error!("Unknown buffer {:?}", handle)
         }
     }
 
@@ -483,23 +830,20 @@ impl RendererContext {
         )
     }
 
-    pub fn destroy_texture(&mut self, handle: TextureHandle) {
-        self.textures.remove(handle);
-    }
-
     pub fn update_texture(&mut self, handle: TextureHandle, desc: &wgpu::TextureDescriptor) {
         if let Some(texture) = self.textures.get_mut(handle) {
             texture.texture = self.device.create_texture(desc);
             texture.view = texture.texture.create_view(&wgpu::TextureViewDescriptor::default());
         }
         else {
-            print!("Unknown texture")
+            log::error!("Unknown texture {:?}", handle)
         }
     }
 
     pub fn write_texture(
         &mut self, 
         handle: TextureHandle,
+        origin: wgpu::Origin3d,
         data: &[u8],
         data_layout: ImageDataLayout,
         size: Extent3d
@@ -509,7 +853,7 @@ impl RendererContext {
                 wgpu::ImageCopyTexture {
                     texture: &texture.texture,
                     mip_level: 0,
-                    origin: wgpu::Origin3d::ZERO,
+                    origin,
                     aspect: wgpu::TextureAspect::All,
                 }, 
                 data, 
@@ -518,7 +862,7 @@ impl RendererContext {
             )
         }
         else {
-            print!("Unknown texture")
+            log::error!("Unknown texture {:?}", handle)
         }
     }
 
@@ -526,36 +870,205 @@ impl RendererContext {
         self.textures.get(handle)
     }
 
+    /// Copies the first layer of a color texture back to the CPU.
+    /// Blocks until the GPU has finished the copy.
+    pub fn read_texture(&self, handle: TextureHandle) -> Result<Image, RendererContextError> {
+        let texture = &self.textures
+            .get(handle)
+            .ok_or(RendererContextError::UnknownTexture)?
+            .texture;
+
+        let format = texture.format();
+        let bgra = match format {
+            wgpu::TextureFormat::Rgba8Unorm | wgpu::TextureFormat::Rgba8UnormSrgb => false,
+            wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb => true,
+            _ => return Err(RendererContextError::UnsupportedReadbackFormat(format)),
+        };
+
+        let width = texture.width();
+        let height = texture.height();
+        let data = self.read_texture_region(
+            handle,
+            wgpu::Origin3d::ZERO,
+            Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            }
+        )?;
+
+        let mut image = Image::from_padded_rows(width, height, width * Image::BYTES_PER_PIXEL, &data);
+        if bgra {
+            image.swap_red_blue();
+        }
+
+        Ok(image)
+    }
+
+    /// Reads back a box of texels, returned tightly packed row by row then layer by layer.
+    /// Blocks until the copy is done.
+    pub fn read_texture_region(
+        &self,
+        handle: TextureHandle,
+        origin: wgpu::Origin3d,
+        size: Extent3d
+    ) -> Result<Vec<u8>, RendererContextError> {
+        let texture = &self.textures
+            .get(handle)
+            .ok_or(RendererContextError::UnknownTexture)?
+            .texture;
+
+        let format = texture.format();
+        let Some(texel_size) = format.block_copy_size(None).filter(|_| format.block_dimensions() == (1, 1)) else {
+            return Err(RendererContextError::UnsupportedReadbackFormat(format));
+        };
+        if !texture.usage().contains(wgpu::TextureUsages::COPY_SRC) {
+            return Err(RendererContextError::MissingCopySrcUsage);
+        }
+
+        // Rows of a texture to buffer copy must be aligned
+        let unpadded_bytes_per_row = size.width * texel_size;
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_bytes_per_row = unpadded_bytes_per_row.div_ceil(align) * align;
+        let rows = size.height * size.depth_or_array_layers;
+
+        let staging_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Readback buffer"),
+            size: (padded_bytes_per_row * rows) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Readback encoder"),
+        });
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                texture,
+                mip_level: 0,
+                origin,
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &staging_buffer,
+                layout: ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    rows_per_image: Some(size.height),
+                },
+            },
+            size
+        );
+        self.queue.submit(std::iter::once(encoder.finish()));
+
+        let buffer_slice = staging_buffer.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        buffer_slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        self.device.poll(wgpu::Maintain::Wait);
+        receiver
+            .recv()
+            .unwrap_or(Err(wgpu::BufferAsyncError))?;
+
+        let data = buffer_slice
+            .get_mapped_range()
+            .chunks(padded_bytes_per_row as usize)
+            .flat_map(|row| &row[..unpadded_bytes_per_row as usize])
+            .copied()
+            .collect();
+        staging_buffer.unmap();
+
+        Ok(data)
+    }
+
     pub fn begin_frame(&self) -> Option<Frame> {
-        match self.surface.get_current_texture() {
-            Ok(surface_texture) => {
-                let view = surface_texture.texture.create_view(&wgpu::TextureViewDescriptor::default());
-                let encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                    label: None,
-                });
+        let encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: None,
+        });
 
-                return Some(Frame::new(
-                    &self,
-                    surface_texture,
-                    view,
-                    encoder
-                ));
+        match &self.target {
+            RenderTarget::Surface { surface, .. } => match surface.get_current_texture() {
+                Ok(surface_texture) => {
+                    let view = surface_texture.texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+                    return Some(Frame::new(
+                        self,
+                        Some(surface_texture),
+                        view,
+                        encoder
+                    ));
+                }
+                // Reconfigure the surface if lost
+                Err(wgpu::SurfaceError::Lost) => (), // self.resize(self.resolution),
+                // The system is out of memory, we should probably quit
+                //Err(wgpu::SurfaceError::OutOfMemory) => *control_flow = ControlFlow::Exit,
+                // All other errors (Outdated, Timeout) should be resolved by the next frame
+                Err(e) => log::error!("{:?}", e),
+            },
+            RenderTarget::Offscreen { texture, .. } => {
+                if let Some(texture) = self.textures.get(*texture) {
+                    let view = texture.texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+                    return Some(Frame::new(
+                        self,
+                        None,
+                        view,
+                        encoder
+                    ));
+                }
             }
-            // Reconfigure the surface if lost
-            Err(wgpu::SurfaceError::Lost) => (), // self.resize(self.resolution),
-            // The system is out of memory, we should probably quit
-            //Err(wgpu::SurfaceError::OutOfMemory) => *control_flow = ControlFlow::Exit,
-            // All other errors (Outdated, Timeout) should be resolved by the next frame
-            Err(e) => eprintln!("{:?}", e),
         }
 
         None
     }
 
-    pub fn commit_frame(&self, frame: Frame) {    
+    pub fn commit_frame(&self, mut frame: Frame) {    
+        let frame_index = self.frame_count.get();
+        self.frame_count.set(frame_index + 1);
+        let readback = match &self.gpu_timer {
+            Some(timer) if !frame.timed_passes.is_empty() => {
+                timer.resolve(&mut frame.encoder, frame_index, std::mem::take(&mut frame.timed_passes))
+            }
+            _ => None,
+        };
         // submit will accept anything that implements IntoIter
         self.queue.submit(std::iter::once(frame.encoder.finish()));
-        frame.surface_texture.present();
+        if let Some(surface_texture) = frame.surface_texture {
+            surface_texture.present();
+        }
+        if let Some(timer) = &self.gpu_timer {
+            if let Some(index) = readback {
+                timer.map(index);
+            }
+            timer.poll(&self.device);
+        }
+    }
+
+    /// False when the adapter can't write timestamps, the passes are never timed then
+    pub fn supports_gpu_timing(&self) -> bool {
+        self.gpu_timer.is_some()
+    }
+
+    /// Times the passes of the next frames, does nothing when the adapter can't
+    pub fn set_gpu_timing(&mut self, enabled: bool) {
+        self.gpu_timing = enabled;
+        if let Some(timer) = self.gpu_timer.as_ref().filter(|_| !enabled) {
+            timer.report.borrow_mut().take();
+        }
+    }
+
+    /// Durations of the passes of the newest frame read back, `None` while the passes aren't timed
+    pub fn gpu_timing_report(&self) -> Option<GpuTimingReport> {
+        self.gpu_timer
+            .as_ref()
+            .filter(|_| self.gpu_timing)
+            .and_then(|timer| timer.report.borrow().clone())
+    }
+
+    /// Frames committed so far
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count.get()
     }
 
     pub fn render_pipeline_count(&self) -> usize {
@@ -581,4 +1094,43 @@ impl RendererContext {
     pub fn buffer_count(&self) -> usize {
         self.buffers.len()
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::headless_renderer;
+
+    #[test]
+    fn gpu_timing_reports() {
+        let Some(mut renderer) = headless_renderer("gpu_timing_reports") else {
+            return;
+        };
+        renderer.set_gpu_timing(true);
+
+        let mut report = None;
+        for _ in 0..TIMING_READBACKS * 2 {
+            let mut frame = renderer.begin_frame().unwrap();
+            drop(frame.new_render_pass("Clear", wgpu::LoadOp::Clear(Color::BLACK)));
+            drop(frame.new_render_pass("Load", wgpu::LoadOp::Load));
+            renderer.commit_frame(frame);
+            renderer.device().poll(wgpu::Maintain::Wait);
+            report = renderer.gpu_timing_report();
+            if report.is_some() {
+                break;
+            }
+        }
+
+        // adapters without timestamps never report
+        if !renderer.supports_gpu_timing() {
+            assert_eq!(report, None);
+            return;
+        }
+        let report = report.expect("the timestamps are read back a few frames late");
+        let labels: Vec<_> = report.passes.iter().map(|pass| pass.name.as_str()).collect();
+        assert_eq!(labels, vec!["Clear", "Load"]);
+        assert!(report.frame < renderer.frame_count());
+
+        renderer.set_gpu_timing(false);
+        assert_eq!(renderer.gpu_timing_report(), None);
+    }
+}
@@ -0,0 +1,254 @@
+//! Named entities of the game organized in a tree, shown in the editor hierarchy.
+
+use glam::{Mat4, Vec3};
+use slotmap::{new_key_type, SlotMap};
+
+use crate::{sprite::SpriteHandle, transform::Transform, voxel_world::Region};
+
+new_key_type! {
+    pub struct EntityId;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EntityKind {
+    /// Groups other entities
+    Empty,
+    /// The game camera, mirrored by the entity
+    Camera,
+    /// A sprite of the game, mirrored by the entity
+    Sprite(SpriteHandle),
+    Light {
+        color: Vec3,
+        intensity: f32,
+    },
+    /// Voxels of the world added together, such as an imported model
+    Model {
+        region: Region,
+    },
+}
+
+impl EntityKind {
+    /// Entities mirroring an object of the game, which moves them
+    pub fn is_linked(&self) -> bool {
+        matches!(self, EntityKind::Camera | EntityKind::Sprite(_))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Entity {
+    pub name: String,
+    /// Relative to the parent
+    pub transform: Transform,
+    pub kind: EntityKind,
+    parent: Option<EntityId>,
+    children: Vec<EntityId>,
+}
+
+impl Entity {
+    pub fn parent(&self) -> Option<EntityId> {
+        self.parent
+    }
+
+    pub fn children(&self) -> &[EntityId] {
+        &self.children
+    }
+}
+
+#[derive(Default, Clone)]
+pub struct SceneGraph {
+    entities: SlotMap<EntityId, Entity>,
+    roots: Vec<EntityId>,
+}
+
+impl SceneGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an entity as the last child of the parent, or as a root without one
+    pub fn add(&mut self, name: impl Into<String>, kind: EntityKind, transform: Transform, parent: Option<EntityId>) -> EntityId {
+        let parent = parent.filter(|parent| self.entities.contains_key(*parent));
+        let id = self.entities.insert(Entity {
+            name: name.into(),
+            transform,
+            kind,
+            parent,
+            children: vec![],
+        });
+        self.siblings_mut(parent).push(id);
+        id
+    }
+
+    /// Removes the entity and its descendants, returned parents first
+    pub fn remove(&mut self, id: EntityId) -> Vec<Entity> {
+        let Some(parent) = self.entities.get(id).map(Entity::parent) else {
+            return vec![];
+        };
+        self.siblings_mut(parent).retain(|sibling| *sibling != id);
+
+        let mut removed = vec![];
+        let mut pending = vec![id];
+        while let Some(id) = pending.pop() {
+            if let Some(entity) = self.entities.remove(id) {
+                pending.extend(entity.children.iter().rev());
+                removed.push(entity);
+            }
+        }
+        removed
+    }
+
+    pub fn get(&self, id: EntityId) -> Option<&Entity> {
+        self.entities.get(id)
+    }
+
+    pub fn get_mut(&mut self, id: EntityId) -> Option<&mut Entity> {
+        self.entities.get_mut(id)
+    }
+
+    pub fn contains(&self, id: EntityId) -> bool {
+        self.entities.contains_key(id)
+    }
+
+    pub fn rename(&mut self, id: EntityId, name: impl Into<String>) -> bool {
+        match self.entities.get_mut(id) {
+            Some(entity) => {
+                entity.name = name.into();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Entities without parent, in display order
+    pub fn roots(&self) -> &[EntityId] {
+        &self.roots
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (EntityId, &Entity)> {
+        self.entities.iter()
+    }
+
+    /// True when `ancestor` is `id` or one of its parents
+    pub fn is_ancestor(&self, ancestor: EntityId, id: EntityId) -> bool {
+        let mut current = Some(id);
+        while let Some(id) = current {
+            if id == ancestor {
+                return true;
+            }
+            current = self.entities.get(id).and_then(Entity::parent);
+        }
+        false
+    }
+
+    /// Moves the entity under a new parent, or to the roots, keeping where it is in the world.
+    /// An entity can't be moved under itself or one of its descendants.
+    pub fn set_parent(&mut self, id: EntityId, parent: Option<EntityId>) -> bool {
+        let Some(old_parent) = self.entities.get(id).map(Entity::parent) else {
+            return false;
+        };
+        if let Some(parent) = parent {
+            if !self.entities.contains_key(parent) || self.is_ancestor(id, parent) {
+                return false;
+            }
+        }
+        if old_parent == parent {
+            return true;
+        }
+
+        let world = self.world_matrix(id);
+        self.siblings_mut(old_parent).retain(|sibling| *sibling != id);
+        self.siblings_mut(parent).push(id);
+        self.entities[id].parent = parent;
+        self.entities[id].transform = Transform::from_matrix(self.parent_matrix(id).inverse() * world);
+        true
+    }
+
+    /// Transform of the entity in the world, through all its parents
+    pub fn world_transform(&self, id: EntityId) -> Transform {
+        Transform::from_matrix(self.world_matrix(id))
+    }
+
+    /// Moves the entity to a transform in the world, whatever its parents
+    pub fn set_world_transform(&mut self, id: EntityId, transform: &Transform) {
+        if self.entities.contains_key(id) {
+            let local = self.parent_matrix(id).inverse() * transform.compute_matrix();
+            self.entities[id].transform = Transform::from_matrix(local);
+        }
+    }
+
+    fn world_matrix(&self, id: EntityId) -> Mat4 {
+        match self.entities.get(id) {
+            Some(entity) => self.parent_matrix(id) * entity.transform.compute_matrix(),
+            None => Mat4::IDENTITY,
+        }
+    }
+
+    fn parent_matrix(&self, id: EntityId) -> Mat4 {
+        match self.entities.get(id).and_then(Entity::parent) {
+            Some(parent) => self.world_matrix(parent),
+            None => Mat4::IDENTITY,
+        }
+    }
+
+    fn siblings_mut(&mut self, parent: Option<EntityId>) -> &mut Vec<EntityId> {
+        match parent {
+            Some(parent) => &mut self.entities[parent].children,
+            None => &mut self.roots,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tree() {
+        let mut scene = SceneGraph::new();
+        let root = scene.add("root", EntityKind::Empty, Transform::IDENTITY, None);
+        let child = scene.add("child", EntityKind::Empty, Transform::IDENTITY, Some(root));
+        let grandchild = scene.add("grandchild", EntityKind::Empty, Transform::IDENTITY, Some(child));
+        let other = scene.add("other", EntityKind::Empty, Transform::IDENTITY, None);
+        assert_eq!(scene.roots(), &[root, other]);
+        assert_eq!(scene.get(child).unwrap().parent(), Some(root));
+        assert!(scene.is_ancestor(root, grandchild));
+        assert!(!scene.is_ancestor(other, grandchild));
+
+        // no cycles
+        assert!(!scene.set_parent(root, Some(grandchild)));
+        assert!(!scene.set_parent(root, Some(root)));
+
+        assert!(scene.set_parent(child, Some(other)));
+        assert_eq!(scene.get(root).unwrap().children(), &[]);
+        assert_eq!(scene.get(other).unwrap().children(), &[child]);
+
+        assert!(scene.rename(child, "renamed"));
+        let removed: Vec<_> = scene.remove(other).into_iter().map(|entity| entity.name).collect();
+        assert_eq!(removed, vec!["other", "renamed", "grandchild"]);
+        assert_eq!(scene.roots(), &[root]);
+        assert_eq!(scene.iter().count(), 1);
+        assert!(!scene.contains(grandchild));
+    }
+
+    #[test]
+    fn reparenting_keeps_the_world_transform() {
+        let mut scene = SceneGraph::new();
+        let mut parent_transform = Transform::from_position(Vec3::new(10.0, 0.0, 0.0));
+        parent_transform.rotate_y(std::f32::consts::FRAC_PI_2);
+        let parent = scene.add("parent", EntityKind::Empty, parent_transform, None);
+        let child = scene.add("child", EntityKind::Empty, Transform::from_position(Vec3::new(0.0, 0.0, 5.0)), Some(parent));
+
+        let world = scene.world_transform(child);
+        assert!(world.position.abs_diff_eq(Vec3::new(15.0, 0.0, 0.0), 1e-4));
+
+        assert!(scene.set_parent(child, None));
+        assert!(scene.world_transform(child).position.abs_diff_eq(world.position, 1e-4));
+        assert!(scene.get(child).unwrap().transform.rotation.abs_diff_eq(world.rotation, 1e-4));
+
+        assert!(scene.set_parent(child, Some(parent)));
+        assert!(scene.get(child).unwrap().transform.position.abs_diff_eq(Vec3::new(0.0, 0.0, 5.0), 1e-4));
+
+        scene.set_world_transform(child, &Transform::from_position(Vec3::new(10.0, 1.0, 0.0)));
+        assert!(scene.get(child).unwrap().transform.position.abs_diff_eq(Vec3::Y, 1e-4));
+    }
+}
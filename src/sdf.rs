@@ -0,0 +1,428 @@
+//! Shapes described by signed distance functions, combined with CSG operations and voxelized into the world.
+//!
+//! A [`SdfScene`] is a list of primitives applied in order to the shape built so far.
+//! Voxelization runs on the CPU with [`SdfScene::voxelize`] or on the GPU with a [`Sprite`](crate::sprite::Sprite) of the scene,
+//! which passes the primitives through a uniform to `src/shaders/compute_sdf.wgsl`.
+
+use bytemuck::Zeroable;
+use glam::{IVec3, Vec2, Vec3, Vec3Swizzles};
+
+use crate::{sprite::SpriteUniform, transform::Transform, voxel_world::{ChunkMap, Region, VoxelWorld}};
+
+pub const SDF_SHADER: &str = "src/shaders/compute_sdf.wgsl";
+
+/// Size of the primitive array of the shader uniform
+pub const MAX_PRIMITIVES: usize = 32;
+
+/// Shapes centered on the origin of their transform, dimensions in voxels
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Shape {
+    Sphere {
+        radius: f32,
+    },
+    Box {
+        half_extents: Vec3,
+    },
+    /// Vertical segment of length `2 * half_height` swept by a sphere
+    Capsule {
+        half_height: f32,
+        radius: f32,
+    },
+    /// Vertical cylinder
+    Cylinder {
+        half_height: f32,
+        radius: f32,
+    },
+    /// Torus lying in the XZ plane
+    Torus {
+        major_radius: f32,
+        minor_radius: f32,
+    },
+}
+
+impl Shape {
+    /// Signed distance from a point in the shape space
+    pub fn distance(&self, p: Vec3) -> f32 {
+        match *self {
+            Shape::Sphere { radius } => p.length() - radius,
+            Shape::Box { half_extents } => {
+                let q = p.abs() - half_extents;
+                q.max(Vec3::ZERO).length() + q.max_element().min(0.0)
+            }
+            Shape::Capsule { half_height, radius } => {
+                let mut p = p;
+                p.y -= p.y.clamp(-half_height, half_height);
+                p.length() - radius
+            }
+            Shape::Cylinder { half_height, radius } => {
+                let d = Vec2::new(p.xz().length(), p.y).abs() - Vec2::new(radius, half_height);
+                d.max_element().min(0.0) + d.max(Vec2::ZERO).length()
+            }
+            Shape::Torus { major_radius, minor_radius } => {
+                Vec2::new(p.xz().length() - major_radius, p.y).length() - minor_radius
+            }
+        }
+    }
+
+    /// Half extents of the box bounding the shape
+    pub fn half_extents(&self) -> Vec3 {
+        match *self {
+            Shape::Sphere { radius } => Vec3::splat(radius),
+            Shape::Box { half_extents } => half_extents,
+            Shape::Capsule { half_height, radius } => Vec3::new(radius, half_height + radius, radius),
+            Shape::Cylinder { half_height, radius } => Vec3::new(radius, half_height, radius),
+            Shape::Torus { major_radius, minor_radius } => {
+                Vec3::new(major_radius + minor_radius, minor_radius, major_radius + minor_radius)
+            }
+        }
+    }
+
+    /// Id and dimensions as read by the shader
+    fn gpu_params(&self) -> (u32, [f32; 4]) {
+        match *self {
+            Shape::Sphere { radius } => (0, [radius, 0.0, 0.0, 0.0]),
+            Shape::Box { half_extents } => (1, [half_extents.x, half_extents.y, half_extents.z, 0.0]),
+            Shape::Capsule { half_height, radius } => (2, [half_height, radius, 0.0, 0.0]),
+            Shape::Cylinder { half_height, radius } => (3, [half_height, radius, 0.0, 0.0]),
+            Shape::Torus { major_radius, minor_radius } => (4, [major_radius, minor_radius, 0.0, 0.0]),
+        }
+    }
+}
+
+/// How a primitive is combined with the shape built by the previous ones
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CsgOp {
+    Union,
+    /// Carves the primitive out, keeping the existing materials
+    Subtract,
+    /// Keeps what is inside both, with the existing materials
+    Intersect,
+    /// Union rounded over `smoothness` voxels around the junction
+    SmoothUnion {
+        smoothness: f32,
+    },
+}
+
+impl CsgOp {
+    fn gpu_params(&self) -> (u32, f32) {
+        match *self {
+            CsgOp::Union => (0, 0.0),
+            CsgOp::Subtract => (1, 0.0),
+            CsgOp::Intersect => (2, 0.0),
+            CsgOp::SmoothUnion { smoothness } => (3, smoothness),
+        }
+    }
+
+    /// Combines the distance and material so far with the ones of a primitive
+    fn apply(&self, (distance, material): (f32, u32), (d, m): (f32, u32)) -> (f32, u32) {
+        match *self {
+            CsgOp::Union => if d < distance { (d, m) } else { (distance, material) },
+            CsgOp::Subtract => (distance.max(-d), material),
+            CsgOp::Intersect => (distance.max(d), material),
+            CsgOp::SmoothUnion { smoothness } => {
+                let h = (0.5 + 0.5 * (d - distance) / smoothness.max(f32::EPSILON)).clamp(0.0, 1.0);
+                let blended = d + (distance - d) * h - smoothness * h * (1.0 - h);
+                (blended, if d < distance { m } else { material })
+            }
+        }
+    }
+}
+
+/// Shape placed in the world, filled with a palette entry
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Primitive {
+    pub shape: Shape,
+    pub transform: Transform,
+    pub material: u32,
+}
+
+impl Primitive {
+    pub fn new(shape: Shape, transform: Transform, material: u32) -> Self {
+        Self {
+            shape,
+            transform,
+            material,
+        }
+    }
+
+    /// Signed distance from a world position.
+    /// Non uniform scales give a bound of the distance rather than the exact distance.
+    pub fn distance(&self, p: Vec3) -> f32 {
+        let local = self.transform.compute_matrix().inverse().transform_point3(p);
+        self.shape.distance(local) * self.transform.scale.abs().min_element()
+    }
+
+    /// World space bounding box, as min and max corners
+    pub fn bounds(&self) -> (Vec3, Vec3) {
+        let matrix = self.transform.compute_matrix();
+        let half_extents = self.shape.half_extents();
+        let mut min = Vec3::splat(f32::MAX);
+        let mut max = Vec3::splat(f32::MIN);
+        for corner in 0..8 {
+            let sign = Vec3::new(
+                if corner & 1 == 0 { -1.0 } else { 1.0 },
+                if corner & 2 == 0 { -1.0 } else { 1.0 },
+                if corner & 4 == 0 { -1.0 } else { 1.0 },
+            );
+            let p = matrix.transform_point3(sign * half_extents);
+            min = min.min(p);
+            max = max.max(p);
+        }
+        (min, max)
+    }
+}
+
+/// Primitive as stored in the uniform, see `SdfPrimitive` in the sdf shader
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+struct GpuPrimitive {
+    world_to_local: [[f32; 4]; 4],
+    params: [f32; 4],
+    shape: u32,
+    op: u32,
+    material: u32,
+    smoothness: f32,
+    scale: f32,
+    _padding: [f32; 3],
+}
+
+/// Header of the uniform, followed by `MAX_PRIMITIVES` primitives
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+struct SdfHeader {
+    origin: [i32; 3],
+    primitive_count: u32,
+    size: [u32; 3],
+    _padding: u32,
+}
+
+/// Primitives combined in order, the first one is usually a union
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SdfScene {
+    operations: Vec<(CsgOp, Primitive)>,
+}
+
+impl SdfScene {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, op: CsgOp, primitive: Primitive) {
+        self.operations.push((op, primitive));
+    }
+
+    pub fn union(mut self, primitive: Primitive) -> Self {
+        self.push(CsgOp::Union, primitive);
+        self
+    }
+
+    pub fn subtract(mut self, primitive: Primitive) -> Self {
+        self.push(CsgOp::Subtract, primitive);
+        self
+    }
+
+    pub fn intersect(mut self, primitive: Primitive) -> Self {
+        self.push(CsgOp::Intersect, primitive);
+        self
+    }
+
+    pub fn smooth_union(mut self, primitive: Primitive, smoothness: f32) -> Self {
+        self.push(CsgOp::SmoothUnion { smoothness }, primitive);
+        self
+    }
+
+    pub fn operations(&self) -> &[(CsgOp, Primitive)] {
+        &self.operations
+    }
+
+    /// Signed distance and material of the combined shape at a world position
+    pub fn evaluate(&self, p: Vec3) -> (f32, u32) {
+        self.operations.iter().fold((f32::MAX, 0), |sample, (op, primitive)| {
+            op.apply(sample, (primitive.distance(p), primitive.material))
+        })
+    }
+
+    /// Material of the voxel, sampled at its center, 0 outside of the shape
+    pub fn voxel_at(&self, coord: &IVec3) -> u32 {
+        let (distance, material) = self.evaluate(coord.as_vec3() + 0.5);
+        if distance <= 0.0 { material } else { 0 }
+    }
+
+    /// Voxels possibly inside the shape. Only unions can grow the shape, so the others are ignored.
+    pub fn bounds(&self) -> Region {
+        let mut min = Vec3::splat(f32::MAX);
+        let mut max = Vec3::splat(f32::MIN);
+        for (op, primitive) in &self.operations {
+            let margin = match op {
+                CsgOp::Union => 0.0,
+                CsgOp::SmoothUnion { smoothness } => smoothness.max(0.0),
+                CsgOp::Subtract | CsgOp::Intersect => continue,
+            };
+            let (primitive_min, primitive_max) = primitive.bounds();
+            min = min.min(primitive_min - margin);
+            max = max.max(primitive_max + margin);
+        }
+        if min.cmpgt(max).any() {
+            return Region::new(IVec3::ZERO, IVec3::ZERO);
+        }
+        Region::new(min.floor().as_ivec3(), max.ceil().as_ivec3() + 1)
+    }
+
+    /// Calls `set` for the voxels of the region inside the shape
+    fn for_each_voxel<F: FnMut(IVec3, u32)>(&self, region: &Region, mut set: F) {
+        for coord in region.iter() {
+            let value = self.voxel_at(&coord);
+            if value != 0 {
+                set(coord, value);
+            }
+        }
+    }
+
+    /// Writes the voxels inside the shape, the others are left untouched
+    pub fn voxelize_chunks(&self, chunks: &mut ChunkMap) {
+        self.for_each_voxel(&self.bounds(), |coord, value| chunks.set_voxel_at(value, &coord));
+    }
+
+    /// Writes the voxels inside the shape, the others are left untouched
+    pub fn voxelize(&self, world: &mut VoxelWorld) {
+        self.for_each_voxel(&self.bounds(), |coord, value| world.set_voxel_at(value, &coord));
+    }
+}
+
+/// Voxelized over its bounds, only the first `MAX_PRIMITIVES` primitives are passed to the shader
+impl SpriteUniform for SdfScene {
+    const LABEL: &'static str = "Voxelize SDF";
+
+    fn region(&self) -> Region {
+        self.bounds()
+    }
+
+    /// Header and primitives laid out as the `Sdf` uniform of the shader
+    fn contents(&self) -> Vec<u8> {
+        let region = self.bounds();
+        let size = region.size().max(IVec3::ZERO).as_uvec3();
+        let header = SdfHeader {
+            origin: region.min.to_array(),
+            primitive_count: self.operations.len().min(MAX_PRIMITIVES) as u32,
+            size: size.to_array(),
+            _padding: 0,
+        };
+        let mut primitives = [GpuPrimitive::zeroed(); MAX_PRIMITIVES];
+        for (gpu, (op, primitive)) in primitives.iter_mut().zip(&self.operations) {
+            let (shape, params) = primitive.shape.gpu_params();
+            let (op, smoothness) = op.gpu_params();
+            *gpu = GpuPrimitive {
+                world_to_local: primitive.transform.compute_matrix().inverse().to_cols_array_2d(),
+                params,
+                shape,
+                op,
+                material: primitive.material,
+                smoothness,
+                scale: primitive.transform.scale.abs().min_element(),
+                _padding: [0.0; 3],
+            };
+        }
+
+        let mut contents = bytemuck::bytes_of(&header).to_vec();
+        contents.extend_from_slice(bytemuck::cast_slice(&primitives));
+        contents
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Quat;
+
+    use super::*;
+
+    fn sphere(center: Vec3, radius: f32, material: u32) -> Primitive {
+        Primitive::new(Shape::Sphere { radius }, Transform::from_position(center), material)
+    }
+
+    #[test]
+    fn shape_distances() {
+        let p = Vec3::new(0.0, 5.0, 0.0);
+        assert_eq!(Shape::Sphere { radius: 2.0 }.distance(p), 3.0);
+        assert_eq!(Shape::Box { half_extents: Vec3::new(1.0, 2.0, 3.0) }.distance(p), 3.0);
+        assert_eq!(Shape::Box { half_extents: Vec3::new(1.0, 2.0, 3.0) }.distance(Vec3::ZERO), -1.0);
+        assert_eq!(Shape::Capsule { half_height: 2.0, radius: 1.0 }.distance(p), 2.0);
+        assert_eq!(Shape::Cylinder { half_height: 2.0, radius: 1.0 }.distance(p), 3.0);
+        assert_eq!(Shape::Cylinder { half_height: 2.0, radius: 1.0 }.distance(Vec3::new(3.0, 0.0, 0.0)), 2.0);
+        assert_eq!(Shape::Torus { major_radius: 4.0, minor_radius: 1.0 }.distance(Vec3::new(4.0, 0.0, 0.0)), -1.0);
+        assert_eq!(Shape::Torus { major_radius: 4.0, minor_radius: 1.0 }.distance(Vec3::ZERO), 3.0);
+    }
+
+    #[test]
+    fn transformed_primitives() {
+        let mut transform = Transform::from_position(Vec3::new(10.0, 0.0, 0.0));
+        transform.rotate(Quat::from_rotation_z(std::f32::consts::FRAC_PI_2));
+        transform.scale = Vec3::splat(2.0);
+        let capsule = Primitive::new(Shape::Capsule { half_height: 2.0, radius: 1.0 }, transform, 1);
+
+        // the capsule now lies along X, from 4 to 16 with a radius of 2
+        assert!(capsule.distance(Vec3::new(15.5, 0.0, 0.0)) < 0.0);
+        assert!((capsule.distance(Vec3::new(10.0, 5.0, 0.0)) - 3.0).abs() < 1e-4);
+
+        let (min, max) = capsule.bounds();
+        assert!(min.abs_diff_eq(Vec3::new(4.0, -2.0, -2.0), 1e-4), "{}", min);
+        assert!(max.abs_diff_eq(Vec3::new(16.0, 2.0, 2.0), 1e-4), "{}", max);
+    }
+
+    #[test]
+    fn csg_operations() {
+        let scene = SdfScene::new()
+            .union(sphere(Vec3::ZERO, 4.0, 1))
+            .union(sphere(Vec3::new(6.0, 0.0, 0.0), 3.0, 2))
+            .subtract(sphere(Vec3::new(0.0, 4.0, 0.0), 2.0, 3));
+        assert_eq!(scene.voxel_at(&IVec3::new(-1, -1, -1)), 1);
+        assert_eq!(scene.voxel_at(&IVec3::new(7, 0, 0)), 2);
+        // carved out
+        assert_eq!(scene.voxel_at(&IVec3::new(0, 3, 0)), 0);
+
+        let intersection = SdfScene::new()
+            .union(sphere(Vec3::ZERO, 4.0, 1))
+            .intersect(Primitive::new(Shape::Box { half_extents: Vec3::new(10.0, 1.0, 10.0) }, Transform::IDENTITY, 2));
+        assert_eq!(intersection.voxel_at(&IVec3::new(2, 0, 0)), 1);
+        assert_eq!(intersection.voxel_at(&IVec3::new(0, 2, 0)), 0);
+    }
+
+    #[test]
+    fn smooth_union_fills_the_junction() {
+        let a = sphere(Vec3::new(-3.5, 0.0, 0.0), 3.0, 1);
+        let b = sphere(Vec3::new(3.5, 0.0, 0.0), 3.0, 2);
+        let junction = Vec3::new(0.0, 0.0, 0.0);
+        assert!(SdfScene::new().union(a).union(b).evaluate(junction).0 > 0.0);
+        assert!(SdfScene::new().union(a).smooth_union(b, 4.0).evaluate(junction).0 < 0.0);
+        assert!(SdfScene::new().union(a).smooth_union(b, 4.0).bounds().contains(&IVec3::new(10, 0, 0)));
+    }
+
+    #[test]
+    fn voxelization_stays_in_bounds() {
+        let scene = SdfScene::new()
+            .union(Primitive::new(Shape::Torus { major_radius: 6.0, minor_radius: 2.0 }, Transform::from_position(Vec3::splat(20.0)), 5))
+            .smooth_union(sphere(Vec3::new(20.0, 24.0, 20.0), 3.0, 6), 2.0);
+        let bounds = scene.bounds();
+        let mut chunks = ChunkMap::new();
+        scene.voxelize_chunks(&mut chunks);
+
+        let mut solid = 0;
+        let around = Region::new(bounds.min - 4, bounds.max + 4);
+        for coord in around.iter() {
+            let value = chunks.get_voxel_at(&coord);
+            if value != 0 {
+                assert!(bounds.contains(&coord), "{} outside of {:?}", coord, bounds);
+                assert_eq!(value, scene.voxel_at(&coord));
+                solid += 1;
+            }
+        }
+        assert!(solid > 0);
+    }
+
+    #[test]
+    fn uniform_layout() {
+        assert_eq!(std::mem::size_of::<GpuPrimitive>(), 112);
+        assert_eq!(std::mem::size_of::<SdfHeader>(), 32);
+        let scene = SdfScene::new().union(sphere(Vec3::ZERO, 1.0, 1));
+        assert_eq!(scene.contents().len(), 32 + 112 * MAX_PRIMITIVES);
+    }
+}
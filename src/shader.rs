@@ -0,0 +1,78 @@
+//! Loading of the WGSL shaders.
+//!
+//! Shaders share code with `#include "<file>"` lines, replaced by the file found next to the including shader.
+//! Each file is included once, where it is first met.
+//! The constants the shaders share with the engine are generated from the Rust ones and prepended to every shader.
+
+use std::{collections::HashSet, path::{Path, PathBuf}};
+
+use crate::{renderer_context::{RendererContext, RendererContextError, ShaderHandle}, voxel_world::VoxelWorld};
+
+const INCLUDE_DIRECTIVE: &str = "#include";
+
+/// Source of the shader at `path`, with its includes resolved
+pub fn source<P: AsRef<Path>>(path: P) -> std::io::Result<String> {
+    let mut source = VoxelWorld::shader_constants();
+    let mut included = HashSet::new();
+    append_file(path.as_ref(), &mut included, &mut source)?;
+    Ok(source)
+}
+
+/// Creates the shader at `path`
+pub fn load<P: AsRef<Path>>(renderer: &mut RendererContext, path: P) -> Result<ShaderHandle, RendererContextError> {
+    let source = source(path).map_err(|e| RendererContextError::CreateShaderModule(e.to_string()))?;
+    renderer.new_shader(source.as_str())
+}
+
+fn append_file(path: &Path, included: &mut HashSet<PathBuf>, source: &mut String) -> std::io::Result<()> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| std::io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+
+    for line in text.lines() {
+        match include_file(line) {
+            Some(file) => {
+                let include_path = path.parent().unwrap_or(Path::new("")).join(file);
+                if included.insert(include_path.clone()) {
+                    append_file(&include_path, included, source)?;
+                }
+            }
+            None => {
+                source.push_str(line);
+                source.push('\n');
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn include_file(line: &str) -> Option<&str> {
+    line.trim()
+        .strip_prefix(INCLUDE_DIRECTIVE)?
+        .trim()
+        .strip_prefix('"')?
+        .strip_suffix('"')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sprite::SPHERE_SHADER;
+
+    #[test]
+    fn includes_are_resolved_once() {
+        let source = source(SPHERE_SHADER).unwrap();
+        assert!(!source.contains(INCLUDE_DIRECTIVE));
+        assert_eq!(source.matches("struct ChunkTable").count(), 1);
+        assert_eq!(source.matches("fn store_voxel").count(), 1);
+        assert_eq!(source.matches("const CHUNK_SIZE: i32 = 16;").count(), 1);
+    }
+
+    #[test]
+    fn include_directive() {
+        assert_eq!(include_file("#include \"chunk_table.wgsl\""), Some("chunk_table.wgsl"));
+        assert_eq!(include_file("// #include \"chunk_table.wgsl\""), None);
+        let error = source("src/shaders/missing.wgsl").unwrap_err();
+        assert!(error.to_string().contains("missing.wgsl"));
+    }
+}
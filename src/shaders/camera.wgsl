@@ -0,0 +1,28 @@
+// Camera rays of the raymarchers, the including shader declares the `camera` binding
+
+struct Ray {
+    origin: vec3<f32>,
+    direction: vec3<f32>
+}
+
+fn ray_at(ray: Ray, t: f32) -> vec3<f32> {
+    return ray.origin + t * ray.direction;
+}
+
+// Must match CameraData in src/camera.rs
+struct Camera {
+    model: mat4x4<f32>,
+    size: vec2<f32>,
+    fov_scale: f32,
+}
+
+// Ray through a pixel of the output, from the bottom left corner, see Camera::screen_ray in src/camera.rs.
+// The direction isn't normalized.
+fn camera_ray(pixel: vec2<u32>) -> Ray {
+    let screen_pos = (vec2<f32>(pixel) / camera.size) * 2.0 - 1.0;
+    let camera_plane_u = camera.model[0].xyz * camera.fov_scale;
+    let camera_plane_v = camera.model[1].xyz * camera.fov_scale * camera.size.y / camera.size.x;
+    let camera_direction = camera.model[2].xyz;
+    let camera_position = camera.model[3].xyz;
+    return Ray(camera_position, camera_direction + screen_pos.x * camera_plane_u + screen_pos.y * camera_plane_v);
+}
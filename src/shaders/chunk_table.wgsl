@@ -0,0 +1,29 @@
+// Chunk table of the world atlas, the including shader declares the `chunk_table` binding.
+// CHUNK_SIZE, ATLAS_SLOTS and EMPTY_SLOT are generated from src/voxel_world.rs
+
+struct ChunkTable {
+    origin: vec4<i32>,
+    size: vec4<u32>,
+    slots: array<u32>,
+}
+
+fn div_floor(a: vec3<i32>, b: i32) -> vec3<i32> {
+    return select(a / b, (a - b + 1) / b, a < vec3(0));
+}
+
+fn chunk_slot(chunk: vec3<i32>) -> u32 {
+    let table_coord = chunk - chunk_table.origin.xyz;
+    let size = vec3<i32>(chunk_table.size.xyz);
+    if (any(table_coord < vec3(0)) || any(table_coord >= size)) {
+        return EMPTY_SLOT;
+    }
+    return chunk_table.slots[table_coord.x + table_coord.y * size.x + table_coord.z * size.x * size.y];
+}
+
+fn slot_origin(slot: u32) -> vec3<i32> {
+    return vec3<i32>(
+        i32(slot % ATLAS_SLOTS),
+        i32(slot / ATLAS_SLOTS % ATLAS_SLOTS),
+        i32(slot / (ATLAS_SLOTS * ATLAS_SLOTS))
+    ) * CHUNK_SIZE;
+}
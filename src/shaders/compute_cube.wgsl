@@ -1,11 +1,21 @@
-@group(0) @binding(0) var world: texture_storage_3d<r32uint, write>;
+#include "chunk_table.wgsl"
+#include "store_voxel.wgsl"
 
+// Must match SpriteParams in src/sprite.rs
+struct SpriteParams {
+    position: vec3<i32>,
+    material: u32,
+    size: vec3<u32>,
+}
+
+@group(0) @binding(2) var<uniform> sprite: SpriteParams;
+
+// fills the bounds
 @compute
-@workgroup_size(1)
+@workgroup_size(4, 4, 4)
 fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
-    let offset = vec3<u32>(6);
-    let voxel_idx = global_id + offset;
-    if voxel_idx.y < 8 {
-        textureStore(world, voxel_idx, vec4<u32>(255, 0, 0, 255));
+    if any(global_id >= sprite.size) {
+        return;
     }
-}
\ No newline at end of file
+    store_voxel(sprite.position + vec3<i32>(global_id), sprite.material);
+}
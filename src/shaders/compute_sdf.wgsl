@@ -0,0 +1,126 @@
+#include "chunk_table.wgsl"
+#include "store_voxel.wgsl"
+
+// Must match GpuPrimitive in src/sdf.rs
+struct SdfPrimitive {
+    world_to_local: mat4x4<f32>,
+    params: vec4<f32>,
+    shape: u32,
+    op: u32,
+    material: u32,
+    smoothness: f32,
+    scale: f32,
+    _padding0: f32,
+    _padding1: f32,
+    _padding2: f32,
+}
+
+const MAX_PRIMITIVES: u32 = 32u;
+
+// Must match SdfHeader in src/sdf.rs
+struct Sdf {
+    origin: vec3<i32>,
+    primitive_count: u32,
+    size: vec3<u32>,
+    _padding: u32,
+    primitives: array<SdfPrimitive, MAX_PRIMITIVES>,
+}
+
+@group(0) @binding(2) var<uniform> sdf: Sdf;
+
+const SPHERE: u32 = 0u;
+const BOX: u32 = 1u;
+const CAPSULE: u32 = 2u;
+const CYLINDER: u32 = 3u;
+const TORUS: u32 = 4u;
+
+const UNION: u32 = 0u;
+const SUBTRACT: u32 = 1u;
+const INTERSECT: u32 = 2u;
+const SMOOTH_UNION: u32 = 3u;
+
+const FAR: f32 = 1e30;
+
+// Same distances as Shape::distance in src/sdf.rs
+
+fn shape_distance(shape: u32, params: vec4<f32>, p: vec3<f32>) -> f32 {
+    switch shape {
+        case SPHERE: {
+            return length(p) - params.x;
+        }
+        case BOX: {
+            let q = abs(p) - params.xyz;
+            return length(max(q, vec3(0.0))) + min(max(q.x, max(q.y, q.z)), 0.0);
+        }
+        case CAPSULE: {
+            let q = vec3(p.x, p.y - clamp(p.y, -params.x, params.x), p.z);
+            return length(q) - params.y;
+        }
+        case CYLINDER: {
+            let d = abs(vec2(length(p.xz), p.y)) - vec2(params.y, params.x);
+            return min(max(d.x, d.y), 0.0) + length(max(d, vec2(0.0)));
+        }
+        case TORUS: {
+            return length(vec2(length(p.xz) - params.x, p.y)) - params.y;
+        }
+        default: {
+            return FAR;
+        }
+    }
+}
+
+struct Sample {
+    distance: f32,
+    material: u32,
+}
+
+// Same as CsgOp::apply in src/sdf.rs
+fn apply(op: u32, smoothness: f32, sample: Sample, d: f32, material: u32) -> Sample {
+    switch op {
+        case UNION: {
+            if d < sample.distance {
+                return Sample(d, material);
+            }
+            return sample;
+        }
+        case SUBTRACT: {
+            return Sample(max(sample.distance, -d), sample.material);
+        }
+        case INTERSECT: {
+            return Sample(max(sample.distance, d), sample.material);
+        }
+        case SMOOTH_UNION: {
+            let h = clamp(0.5 + 0.5 * (d - sample.distance) / max(smoothness, 1e-6), 0.0, 1.0);
+            let blended = mix(d, sample.distance, h) - smoothness * h * (1.0 - h);
+            return Sample(blended, select(sample.material, material, d < sample.distance));
+        }
+        default: {
+            return sample;
+        }
+    }
+}
+
+fn evaluate(p: vec3<f32>) -> Sample {
+    var sample = Sample(FAR, 0u);
+    for (var i = 0u; i < min(sdf.primitive_count, MAX_PRIMITIVES); i++) {
+        let primitive = sdf.primitives[i];
+        let local = (primitive.world_to_local * vec4(p, 1.0)).xyz;
+        let d = shape_distance(primitive.shape, primitive.params, local) * primitive.scale;
+        sample = apply(primitive.op, primitive.smoothness, sample, d, primitive.material);
+    }
+    return sample;
+}
+
+@compute
+@workgroup_size(4, 4, 4)
+fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    if any(global_id >= sdf.size) {
+        return;
+    }
+    let pos = sdf.origin.xyz + vec3<i32>(global_id);
+    // voxels are sampled at their center
+    let sample = evaluate(vec3<f32>(pos) + 0.5);
+    if sample.distance <= 0.0 {
+        store_voxel(pos, sample.material);
+    }
+}
@@ -1,11 +1,25 @@
-@group(0) @binding(0) var world: texture_storage_3d<r32uint, write>;
+#include "chunk_table.wgsl"
+#include "store_voxel.wgsl"
 
+// Must match SpriteParams in src/sprite.rs
+struct SpriteParams {
+    position: vec3<i32>,
+    material: u32,
+    size: vec3<u32>,
+}
+
+@group(0) @binding(2) var<uniform> sprite: SpriteParams;
+
+// sphere inscribed in the bounds
 @compute
-@workgroup_size(1)
+@workgroup_size(4, 4, 4)
 fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
-    let offset = vec3<u32>(0);
-    let voxel_idx = global_id + offset;
-    if distance(vec3<f32>(voxel_idx), vec3<f32>(16.0)) < 16 {
-        textureStore(world, voxel_idx, vec4<u32>(255, 0, 0, 255));
+    if any(global_id >= sprite.size) {
+        return;
+    }
+    let center = vec3<f32>(sprite.size) * 0.5;
+    let radius = min(center.x, min(center.y, center.z));
+    if distance(vec3<f32>(global_id) + 0.5, center) < radius {
+        store_voxel(sprite.position + vec3<i32>(global_id), sprite.material);
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,170 @@
+#include "chunk_table.wgsl"
+#include "store_voxel.wgsl"
+
+// Must match TerrainParams in src/terrain.rs
+struct TerrainParams {
+    seed: u32,
+    octaves: u32,
+    frequency: f32,
+    lacunarity: f32,
+    gain: f32,
+    warp_strength: f32,
+    base_height: f32,
+    height_scale: f32,
+    sea_level: f32,
+    snow_height: f32,
+    cave_frequency: f32,
+    cave_threshold: f32,
+    biome_frequency: f32,
+}
+
+// Must match TerrainUniform in src/terrain.rs
+struct Terrain {
+    origin: vec4<i32>,
+    size: vec4<u32>,
+    params: TerrainParams,
+}
+
+@group(0) @binding(2) var<uniform> terrain: Terrain;
+
+const STONE: u32 = 1u;
+const DIRT: u32 = 2u;
+const GRASS: u32 = 3u;
+const SAND: u32 = 4u;
+const SNOW: u32 = 5u;
+
+const WARP_SEED_OFFSET: u32 = 1000u;
+const CAVE_SEED_OFFSET: u32 = 2000u;
+const BIOME_SEED_OFFSET: u32 = 3000u;
+
+// Same noise as src/noise.rs
+
+fn hash(p: vec3<i32>, seed: u32) -> u32 {
+    var h = seed
+        ^ (bitcast<u32>(p.x) * 0x8da6b343u)
+        ^ (bitcast<u32>(p.y) * 0xd8163841u)
+        ^ (bitcast<u32>(p.z) * 0xcb1ab31fu);
+    h ^= h >> 16u;
+    h *= 0x7feb352du;
+    h ^= h >> 15u;
+    h *= 0x846ca68bu;
+    h ^= h >> 16u;
+    return h;
+}
+
+fn gradient(hashed: u32, d: vec3<f32>) -> f32 {
+    let h = hashed & 15u;
+    let u = select(d.y, d.x, h < 8u);
+    let v = select(select(d.z, d.x, h == 12u || h == 14u), d.y, h < 4u);
+    return select(-u, u, (h & 1u) == 0u) + select(-v, v, (h & 2u) == 0u);
+}
+
+fn fade(t: vec3<f32>) -> vec3<f32> {
+    return t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+}
+
+fn corner(cell: vec3<i32>, f: vec3<f32>, offset: vec3<i32>, seed: u32) -> f32 {
+    return gradient(hash(cell + offset, seed), f - vec3<f32>(offset));
+}
+
+fn perlin(p: vec3<f32>, seed: u32) -> f32 {
+    let floored = floor(p);
+    let cell = vec3<i32>(floored);
+    let f = p - floored;
+    let t = fade(f);
+
+    return mix(
+        mix(
+            mix(corner(cell, f, vec3(0, 0, 0), seed), corner(cell, f, vec3(1, 0, 0), seed), t.x),
+            mix(corner(cell, f, vec3(0, 1, 0), seed), corner(cell, f, vec3(1, 1, 0), seed), t.x),
+            t.y
+        ),
+        mix(
+            mix(corner(cell, f, vec3(0, 0, 1), seed), corner(cell, f, vec3(1, 0, 1), seed), t.x),
+            mix(corner(cell, f, vec3(0, 1, 1), seed), corner(cell, f, vec3(1, 1, 1), seed), t.x),
+            t.y
+        ),
+        t.z
+    );
+}
+
+fn fbm(p: vec3<f32>, seed: u32, octaves: u32, lacunarity: f32, gain: f32) -> f32 {
+    var sum = 0.0;
+    var amplitude = 1.0;
+    var total_amplitude = 0.0;
+    var frequency = 1.0;
+    for (var octave = 0u; octave < octaves; octave++) {
+        sum += amplitude * perlin(p * frequency, seed + octave);
+        total_amplitude += amplitude;
+        amplitude *= gain;
+        frequency *= lacunarity;
+    }
+    return select(0.0, sum / total_amplitude, total_amplitude > 0.0);
+}
+
+// Same terrain as src/terrain.rs
+
+fn height(x: i32, z: i32) -> f32 {
+    let params = terrain.params;
+    let p = vec3(f32(x), 0.0, f32(z)) * params.frequency;
+    let warp_seed = params.seed + WARP_SEED_OFFSET;
+    let warp = vec3(
+        fbm(p + vec3(5.2, 0.0, 1.3), warp_seed, 2u, params.lacunarity, params.gain),
+        0.0,
+        fbm(p + vec3(1.7, 0.0, 9.2), warp_seed, 2u, params.lacunarity, params.gain),
+    );
+    let warped = p + warp * params.warp_strength * params.frequency;
+    return params.base_height + params.height_scale * fbm(warped, params.seed, params.octaves, params.lacunarity, params.gain);
+}
+
+fn is_desert(x: i32, z: i32) -> bool {
+    let p = vec3(f32(x), 0.0, f32(z)) * terrain.params.biome_frequency;
+    return perlin(p + vec3(0.5, 0.5, 0.5), terrain.params.seed + BIOME_SEED_OFFSET) > 0.15;
+}
+
+fn is_cave(coord: vec3<i32>) -> bool {
+    let threshold = terrain.params.cave_threshold;
+    if (threshold <= 0.0) {
+        return false;
+    }
+    let p = vec3<f32>(coord) * terrain.params.cave_frequency;
+    let seed = terrain.params.seed + CAVE_SEED_OFFSET;
+    return abs(perlin(p, seed)) < threshold
+        && abs(perlin(p + vec3(31.7, 17.3, 5.9), seed + 1u)) < threshold;
+}
+
+fn material(coord: vec3<i32>, surface: i32, desert: bool) -> u32 {
+    if (coord.y > surface) {
+        return 0u;
+    }
+    if (coord.y < surface - 2 && is_cave(coord)) {
+        return 0u;
+    }
+
+    let depth = surface - coord.y;
+    let beach = f32(surface) <= terrain.params.sea_level + 1.0;
+    if ((beach || desert) && depth < 4) {
+        return SAND;
+    }
+    if (depth == 0 && f32(surface) >= terrain.params.snow_height) {
+        return SNOW;
+    }
+    if (depth == 0) {
+        return GRASS;
+    }
+    if (depth < 4) {
+        return DIRT;
+    }
+    return STONE;
+}
+
+@compute
+@workgroup_size(4, 4, 4)
+fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    if any(global_id >= terrain.size.xyz) {
+        return;
+    }
+    let coord = terrain.origin.xyz + vec3<i32>(global_id);
+    let surface = i32(floor(height(coord.x, coord.z)));
+    store_voxel(coord, material(coord, surface, is_desert(coord.x, coord.z)));
+}
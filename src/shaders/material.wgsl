@@ -0,0 +1,41 @@
+// Materials of the palette, the including shader declares the `palette` binding
+
+// Must match Material in src/palette.rs
+struct Material {
+    albedo: vec3<f32>,
+    roughness: f32,
+    emission: vec3<f32>,
+    metallic: f32,
+    transparency: f32,
+    _padding0: f32,
+    _padding1: f32,
+    _padding2: f32,
+}
+
+// rays stop once almost no light goes through the transparent materials they crossed
+const MIN_TRANSMITTANCE: f32 = 0.01;
+
+// voxel values index the palette, 0 is empty
+fn get_material(value: u32) -> Material {
+    return palette[min(value, arrayLength(&palette) - 1u)];
+}
+
+// Crude shading with a fixed light: shade per face axis, highlights on smooth and metallic materials
+fn shade(material: Material, mask: vec3<bool>, direction: vec3<f32>) -> vec3<f32> {
+    var face_shade = 1.0;
+    if (mask.x) {
+        face_shade = 0.5;
+    }
+    if (mask.z) {
+        face_shade = 0.75;
+    }
+    let normal = -sign(direction) * vec3<f32>(mask);
+    let light_direction = normalize(vec3(0.4, 1.0, 0.6));
+    let smoothness = 1.0 - material.roughness;
+    let highlight = smoothness * smoothness
+        * pow(max(dot(reflect(normalize(direction), normal), light_direction), 0.0), mix(4.0, 128.0, smoothness));
+
+    let diffuse = material.albedo * face_shade * mix(1.0, 0.3, material.metallic);
+    let specular = highlight * mix(vec3(1.0), material.albedo, material.metallic);
+    return diffuse + specular + material.emission;
+}
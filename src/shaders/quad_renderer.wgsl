@@ -13,10 +13,7 @@ fn vs_main(@builtin(vertex_index) vertex_idx : u32) -> VertexOutput {
     return out;
 }
 
-struct Camera {
-    model: mat4x4<f32>,
-    size: vec2<f32>,
-}
+#include "camera.wgsl"
 
 @group(0) @binding(0) var t_color : texture_2d<f32>;
 @group(0) @binding(1) var<uniform> camera : Camera;
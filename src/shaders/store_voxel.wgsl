@@ -0,0 +1,13 @@
+// World bindings of the passes writing voxels, include after chunk_table.wgsl
+
+@group(0) @binding(0) var world: texture_storage_3d<r32uint, write>;
+@group(0) @binding(1) var<storage, read> chunk_table: ChunkTable;
+
+// voxels outside of the allocated chunks are dropped
+fn store_voxel(pos: vec3<i32>, value: u32) {
+    let chunk = div_floor(pos, CHUNK_SIZE);
+    let slot = chunk_slot(chunk);
+    if (slot != EMPTY_SLOT) {
+        textureStore(world, slot_origin(slot) + pos - chunk * CHUNK_SIZE, vec4<u32>(value, 0u, 0u, 255u));
+    }
+}
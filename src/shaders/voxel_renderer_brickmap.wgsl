@@ -0,0 +1,113 @@
+#include "camera.wgsl"
+#include "material.wgsl"
+
+// The coarse grid is stored first, its cells hold the index of their brick in the pool that follows
+struct Brickmap {
+    origin: vec4<i32>,
+    grid_size: vec3<u32>,
+    bricks_offset: u32,
+    data: array<u32>,
+}
+
+const BRICK_SIZE: i32 = 8;
+const EMPTY_BRICK: u32 = 0xffffffffu;
+
+@group(0) @binding(0) var<storage, read> brickmap: Brickmap;
+@group(0) @binding(1) var output_texture: texture_storage_2d<rgba8unorm, write>;
+@group(0) @binding(2) var<uniform> camera : Camera;
+@group(0) @binding(4) var<storage, read> palette: array<Material>;
+
+fn get_brick(cell: vec3<i32>) -> u32 {
+    let size = vec3<i32>(brickmap.grid_size);
+    return brickmap.data[cell.x + cell.y * size.x + cell.z * size.x * size.y];
+}
+
+fn get_brick_voxel(brick: u32, pos: vec3<i32>) -> u32 {
+    let index = pos.x + pos.y * BRICK_SIZE + pos.z * BRICK_SIZE * BRICK_SIZE;
+    return brickmap.data[brickmap.bricks_offset + brick * u32(BRICK_SIZE * BRICK_SIZE * BRICK_SIZE) + u32(index)];
+}
+
+@compute
+@workgroup_size(1)
+fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    // traversal is done relatively to the brickmap origin, in voxels
+    let view_ray = camera_ray(global_id.xy);
+    let ray = Ray(view_ray.origin - vec3<f32>(brickmap.origin.xyz), view_ray.direction);
+    let inv_dir = 1.0 / ray.direction;
+    let ray_step = vec3<i32>(sign(ray.direction));
+    let grid_size = vec3<i32>(brickmap.grid_size);
+
+    // clip the ray to the grid
+    let grid_t0 = -ray.origin * inv_dir;
+    let grid_t1 = (vec3<f32>(grid_size * BRICK_SIZE) - ray.origin) * inv_dir;
+    let grid_t_min = min(grid_t0, grid_t1);
+    let grid_t_max = max(grid_t0, grid_t1);
+    let t_start = max(max(grid_t_min.x, grid_t_min.y), max(grid_t_min.z, 0.0));
+    let t_far = min(grid_t_max.x, min(grid_t_max.y, grid_t_max.z));
+    var mask = grid_t_min.xyz >= max(grid_t_min.yzx, grid_t_min.zxy);
+
+    // each step of the coarse DDA crosses a whole brick,
+    // the fine DDA only runs inside non empty bricks
+    let MAX_RAY_STEPS = 64;
+    let MAX_BRICK_STEPS = 3 * BRICK_SIZE;
+    let EPSILON = 1e-3;
+    var color = vec3(0.0);
+    var transmittance = 1.0;
+    var previous_value = 0u;
+    var opaque = false;
+    if (t_start < t_far) {
+        let start = ray_at(ray, t_start + EPSILON);
+        var cell = clamp(vec3<i32>(floor(start / f32(BRICK_SIZE))), vec3(0), grid_size - 1);
+        let brick_t_delta = abs(f32(BRICK_SIZE) * inv_dir);
+        var brick_t_max = (vec3<f32>((cell + max(ray_step, vec3(0))) * BRICK_SIZE) - ray.origin) * inv_dir;
+        var t = t_start;
+
+        for (var i: i32 = 0; i < MAX_RAY_STEPS; i++) {
+            let brick = get_brick(cell);
+            if (brick != EMPTY_BRICK) {
+                let brick_min = cell * BRICK_SIZE;
+                let entry = ray_at(ray, t + EPSILON);
+                var voxel = clamp(vec3<i32>(floor(entry)), brick_min, brick_min + BRICK_SIZE - 1);
+                let voxel_t_delta = abs(inv_dir);
+                var voxel_t_max = (vec3<f32>(voxel + max(ray_step, vec3(0))) - ray.origin) * inv_dir;
+
+                for (var j: i32 = 0; j < MAX_BRICK_STEPS; j++) {
+                    let value = get_brick_voxel(brick, voxel - brick_min);
+                    if (value != 0u && value != previous_value) {
+                        let material = get_material(value);
+                        color += transmittance * (1.0 - material.transparency) * shade(material, mask, ray.direction);
+                        transmittance *= material.transparency;
+                        if (transmittance < MIN_TRANSMITTANCE) {
+                            opaque = true;
+                            break;
+                        }
+                    }
+                    previous_value = value;
+                    let voxel_mask = voxel_t_max.xyz <= min(voxel_t_max.yzx, voxel_t_max.zxy);
+                    voxel_t_max += select(vec3(0.0), voxel_t_delta, voxel_mask);
+                    voxel += select(vec3(0), ray_step, voxel_mask);
+                    if (any(voxel < brick_min) || any(voxel >= brick_min + BRICK_SIZE)) {
+                        break;
+                    }
+                    mask = voxel_mask;
+                }
+                if (opaque) {
+                    break;
+                }
+            }
+            else {
+                previous_value = 0u;
+            }
+
+            mask = brick_t_max.xyz <= min(brick_t_max.yzx, brick_t_max.zxy);
+            t = min(brick_t_max.x, min(brick_t_max.y, brick_t_max.z));
+            brick_t_max += select(vec3(0.0), brick_t_delta, mask);
+            cell += select(vec3(0), ray_step, mask);
+            if (any(cell < vec3(0)) || any(cell >= grid_size)) {
+                break;
+            }
+        }
+    }
+
+    textureStore(output_texture, vec2(i32(global_id.x), i32(u32(camera.size.y) - global_id.y)), vec4<f32>(color, 1.0));
+}
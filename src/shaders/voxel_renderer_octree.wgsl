@@ -0,0 +1,93 @@
+#include "camera.wgsl"
+#include "material.wgsl"
+
+// Branches point to their 8 contiguous children (x), leaves hold a value (y)
+struct Octree {
+    origin: vec4<i32>,
+    size: u32,
+    depth: u32,
+    node_count: u32,
+    _padding: u32,
+    nodes: array<vec2<u32>>,
+}
+
+struct Leaf {
+    value: u32,
+    min: vec3<f32>,
+    size: f32,
+}
+
+@group(0) @binding(0) var<storage, read> octree: Octree;
+@group(0) @binding(1) var output_texture: texture_storage_2d<rgba8unorm, write>;
+@group(0) @binding(2) var<uniform> camera : Camera;
+@group(0) @binding(4) var<storage, read> palette: array<Material>;
+
+// Leaf containing a position expressed relatively to the octree origin
+fn find_leaf(pos: vec3<f32>) -> Leaf {
+    var node = octree.nodes[0];
+    var node_min = vec3(0.0);
+    var size = f32(octree.size);
+    for (var level = 0u; level < octree.depth; level++) {
+        if (node.x == 0u) {
+            break;
+        }
+        size *= 0.5;
+        let child = select(vec3(0u), vec3(1u), pos >= node_min + vec3(size));
+        node_min += vec3<f32>(child) * size;
+        node = octree.nodes[node.x + child.x + (child.y << 1u) + (child.z << 2u)];
+    }
+    return Leaf(node.y, node_min, size);
+}
+
+@compute
+@workgroup_size(1)
+fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    // traversal is done in octree space
+    let view_ray = camera_ray(global_id.xy);
+    let ray = Ray(view_ray.origin - vec3<f32>(octree.origin.xyz), view_ray.direction);
+    let inv_dir = 1.0 / ray.direction;
+    let root_size = f32(octree.size);
+
+    // clip the ray to the root node
+    let root_t0 = -ray.origin * inv_dir;
+    let root_t1 = (vec3(root_size) - ray.origin) * inv_dir;
+    let root_t_min = min(root_t0, root_t1);
+    let root_t_max = max(root_t0, root_t1);
+    var t = max(max(root_t_min.x, root_t_min.y), max(root_t_min.z, 0.0));
+    let t_far = min(root_t_max.x, min(root_t_max.y, root_t_max.z));
+    var mask = root_t_min.xyz >= max(root_t_min.yzx, root_t_min.zxy);
+
+    // steps go from leaf to leaf, skipping empty space at once
+    let MAX_RAY_STEPS = 64;
+    let EPSILON = 1e-3;
+    var color = vec3(0.0);
+    var transmittance = 1.0;
+    var previous_value = 0u;
+    if (t < t_far) {
+        for (var i: i32 = 0; i < MAX_RAY_STEPS; i++) {
+            let pos = ray_at(ray, t + EPSILON);
+            if (any(pos < vec3(0.0)) || any(pos >= vec3(root_size))) {
+                break;
+            }
+
+            let leaf = find_leaf(pos);
+            if (leaf.value != 0u && leaf.value != previous_value) {
+                let material = get_material(leaf.value);
+                color += transmittance * (1.0 - material.transparency) * shade(material, mask, ray.direction);
+                transmittance *= material.transparency;
+                if (transmittance < MIN_TRANSMITTANCE) {
+                    break;
+                }
+            }
+            previous_value = leaf.value;
+
+            let leaf_t0 = (leaf.min - ray.origin) * inv_dir;
+            let leaf_t1 = (leaf.min + vec3(leaf.size) - ray.origin) * inv_dir;
+            let leaf_t_max = max(leaf_t0, leaf_t1);
+            t = min(leaf_t_max.x, min(leaf_t_max.y, leaf_t_max.z));
+            mask = leaf_t_max.xyz <= min(leaf_t_max.yzx, leaf_t_max.zxy);
+        }
+    }
+
+    textureStore(output_texture, vec2(i32(global_id.x), i32(u32(camera.size.y) - global_id.y)), vec4<f32>(color, 1.0));
+}
@@ -1,36 +1,124 @@
-use crate::{renderer_context::{BindGroupHandle, Binding, BindingResource, ComputePipelineHandle, PipelineDesc, RendererContext, ShaderHandle}, voxel_world::VoxelWorld};
+use std::path::{Path, PathBuf};
 
+use glam::{IVec3, UVec3};
+use slotmap::new_key_type;
 
-pub struct Sprite {
-    pub compute_shader: Option<ShaderHandle>,
+use crate::{renderer_context::{BindGroupHandle, Binding, BindingResource, BufferHandle, ComputePassDesc, ComputePipelineHandle, Frame, PipelineDesc, RendererContext, RendererContextError, ShaderHandle}, shader, voxel_world::{Region, VoxelWorld}};
+
+pub const SPHERE_SHADER: &str = "src/shaders/compute_sphere.wgsl";
+pub const CUBE_SHADER: &str = "src/shaders/compute_cube.wgsl";
+
+/// Must match the `@workgroup_size` of the sprite shaders
+pub const WORKGROUP_SIZE: u32 = 4;
+
+/// Parameters of a generator, uploaded as the uniform at binding 2 of its shader
+pub trait SpriteUniform: PartialEq {
+    /// Label of the compute pass
+    const LABEL: &'static str;
+
+    /// Voxels the generator can write
+    fn region(&self) -> Region;
+
+    /// Bytes of the uniform
+    fn contents(&self) -> Vec<u8>;
+}
+
+new_key_type! {
+    pub struct SpriteId;
+}
+
+pub type SpriteHandle = SpriteId;
+
+/// Parameters of a sprite, laid out as the `SpriteParams` uniform of the sprite shaders
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct SpriteParams {
+    /// Lowest corner of the bounds, in voxels
+    pub position: [i32; 3],
+    /// Palette entry written by the generator
+    pub material: u32,
+    pub size: [u32; 3],
+    _padding: u32,
+}
+
+impl SpriteParams {
+    pub fn new(position: IVec3, size: UVec3, material: u32) -> Self {
+        Self {
+            position: position.to_array(),
+            material,
+            size: size.to_array(),
+            _padding: 0,
+        }
+    }
+}
+
+impl SpriteUniform for SpriteParams {
+    const LABEL: &'static str = "Sprite";
+
+    fn region(&self) -> Region {
+        let min = IVec3::from_array(self.position);
+        Region::new(min, min + UVec3::from_array(self.size).as_ivec3())
+    }
+
+    fn contents(&self) -> Vec<u8> {
+        bytemuck::bytes_of(self).to_vec()
+    }
+}
+
+/// Voxel generator running a compute shader over its bounds.
+/// The shader receives the world at bindings 0 and 1 and the params at binding 2,
+/// with one invocation per voxel of the bounds.
+/// It runs once, and again when the params change, so later edits of the generated voxels are kept.
+pub struct Sprite<P: SpriteUniform = SpriteParams> {
+    shader_path: PathBuf,
+    params: P,
+    params_dirty: bool,
+    /// The voxels of the current params were written
+    generated: bool,
+    /// Bounds of voxels written with previous params, to clear before generating again
+    stale_region: Option<Region>,
+    params_buffer: BufferHandle,
+    pub compute_shader: ShaderHandle,
     pub compute_pipeline: ComputePipelineHandle,
     pub compute_bind_group: BindGroupHandle,
 }
 
-impl Sprite {
-    pub fn new(renderer: &mut RendererContext, world: &VoxelWorld) -> Self {
-        let shader_src = std::fs::read_to_string("src/shaders/compute_sphere.wgsl").unwrap();
-        let compute_shader = 
-            match renderer.new_shader(shader_src.as_str()) {
-                Ok(shader) => Some(shader),
-                Err(e) => 
-                {
-                    println!("error: {}", e);
-                    None
-                }
-            };
+impl<P: SpriteUniform> Sprite<P> {
+    /// Creates a sprite generated by the given compute shader, writing into the world texture
+    pub fn new<S: AsRef<Path>>(renderer: &mut RendererContext, world: &VoxelWorld, path: S, params: P) -> Result<Self, RendererContextError> {
+        let compute_shader = shader::load(renderer, path.as_ref())?;
+
+        let params_buffer = renderer.new_buffer(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Sprite params"),
+                contents: &params.contents(),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            }
+        );
 
         let compute_pipeline = renderer.new_compute_pipeline(
             &PipelineDesc {
-                shader: compute_shader.unwrap(),
+                shader: compute_shader,
                 bindings_layout: &[
                     wgpu::BindGroupLayoutEntry {
                         binding: 0,
                         visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::StorageTexture {
-                            access: wgpu::StorageTextureAccess::WriteOnly,
-                            format: wgpu::TextureFormat::R32Uint,
-                            view_dimension: wgpu::TextureViewDimension::D3,
+                        ty: VoxelWorld::storage_binding_type(),
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: VoxelWorld::chunk_table_binding_type(),
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
                         },
                         count: None,
                     },
@@ -39,18 +127,188 @@ impl Sprite {
         );
 
         let compute_bind_group = renderer.new_compute_bind_group(
-            compute_pipeline, 
+            compute_pipeline,
             &[
             Binding {
                 binding: 0,
                 resource: BindingResource::Texture(world.get_texture()),
+            },
+            Binding {
+                binding: 1,
+                resource: BindingResource::Buffer(world.get_chunk_table()),
+            },
+            Binding {
+                binding: 2,
+                resource: BindingResource::Buffer(params_buffer),
             }]
         );
 
-        Self {
+        Ok(Self {
+            shader_path: path.as_ref().to_path_buf(),
+            params,
+            params_dirty: false,
+            generated: false,
+            stale_region: None,
+            params_buffer,
             compute_shader,
             compute_pipeline,
             compute_bind_group,
+        })
+    }
+
+    pub fn shader_path(&self) -> &Path {
+        &self.shader_path
+    }
+
+    pub fn params(&self) -> &P {
+        &self.params
+    }
+
+    /// The voxels generated with the previous params are left for the owner to clear, see [`Sprite::take_stale_region`]
+    pub fn set_params(&mut self, params: P) {
+        if params != self.params {
+            if self.generated && self.stale_region.is_none() {
+                self.stale_region = Some(self.params.region());
+            }
+            self.params = params;
+            self.params_dirty = true;
+            self.generated = false;
+        }
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.params_dirty
+    }
+
+    /// Params and whether their voxels were written, to go back to them with [`Sprite::restore`]
+    pub fn state(&self) -> (P, bool) where P: Clone {
+        (self.params.clone(), self.generated)
+    }
+
+    /// Goes back to a previous state, along with the world it was written in
+    pub fn restore(&mut self, (params, generated): (P, bool)) {
+        self.set_params(params);
+        self.generated = generated;
+        self.stale_region = None;
+    }
+
+    /// Bounds of the voxels generated with previous params, which are still in the world
+    pub fn take_stale_region(&mut self) -> Option<Region> {
+        self.stale_region.take()
+    }
+
+    /// Uploads the params if they changed
+    pub fn update_buffer(&mut self, renderer: &mut RendererContext) {
+        if self.params_dirty {
+            renderer.update_buffer(self.params_buffer, &self.params.contents());
+            self.params_dirty = false;
+        }
+    }
+
+    /// Number of workgroups covering the bounds
+    pub fn dispatch_size(&self) -> UVec3 {
+        let size = self.params.region().size().max(IVec3::ZERO).as_uvec3();
+        UVec3::new(size.x.div_ceil(WORKGROUP_SIZE), size.y.div_ceil(WORKGROUP_SIZE), size.z.div_ceil(WORKGROUP_SIZE))
+    }
+
+    /// Records the generation unless the voxels of the current params were already written,
+    /// returns whether it was recorded
+    pub fn dispatch(&mut self, frame: &mut Frame) -> bool {
+        let size = self.dispatch_size();
+        if self.generated || size.cmpeq(UVec3::ZERO).any() {
+            return false;
         }
+        self.generated = true;
+        let mut cpass = frame.begin_compute_pass(
+            &ComputePassDesc {
+            label: P::LABEL,
+            pipeline: self.compute_pipeline,
+            bind_group: self.compute_bind_group,
+        });
+        cpass.dispatch(size.x, size.y, size.z);
+        true
     }
-}
\ No newline at end of file
+
+    pub fn destroy(self, renderer: &mut RendererContext) {
+        renderer.destroy_bind_group(self.compute_bind_group);
+        renderer.destroy_compute_pipeline(self.compute_pipeline);
+        renderer.destroy_shader(self.compute_shader);
+        renderer.destroy_buffer(self.params_buffer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3;
+
+    use super::*;
+    use crate::{sdf::{Primitive, SdfScene, Shape, SDF_SHADER}, terrain::{TerrainParams, TerrainRegion, TERRAIN_SHADER}, test_utils::headless_renderer, transform::Transform};
+
+    #[test]
+    fn uniform_layout() {
+        assert_eq!(std::mem::size_of::<SpriteParams>(), 32);
+    }
+
+    #[test]
+    fn dispatch_covers_the_bounds() {
+        let Some(mut renderer) = headless_renderer("dispatch_covers_the_bounds") else {
+            return;
+        };
+
+        let world = VoxelWorld::new(&mut renderer);
+        let params = SpriteParams::new(IVec3::new(-3, 0, 5), UVec3::new(9, 4, 1), 7);
+        let mut sprite = Sprite::new(&mut renderer, &world, CUBE_SHADER, params).unwrap();
+        assert_eq!(sprite.dispatch_size(), UVec3::new(3, 1, 1));
+        assert_eq!(sprite.params().region(), Region::new(IVec3::new(-3, 0, 5), IVec3::new(6, 4, 6)));
+
+        sprite.set_params(params);
+        assert!(!sprite.is_dirty());
+        sprite.set_params(SpriteParams::new(IVec3::ZERO, UVec3::splat(32), 7));
+        assert!(sprite.is_dirty());
+        sprite.update_buffer(&mut renderer);
+        assert!(!sprite.is_dirty());
+        assert_eq!(sprite.dispatch_size(), UVec3::splat(8));
+
+        // the generated voxels are only written again for new params
+        let mut frame = renderer.begin_frame().expect("Headless frames are always available");
+        assert!(sprite.dispatch(&mut frame));
+        assert!(!sprite.dispatch(&mut frame));
+        sprite.set_params(params);
+        assert_eq!(sprite.take_stale_region(), Some(Region::new(IVec3::ZERO, IVec3::splat(32))));
+        // params which never generated anything leave nothing to clear
+        sprite.set_params(SpriteParams::new(IVec3::ONE, UVec3::ONE, 7));
+        assert_eq!(sprite.take_stale_region(), None);
+        assert!(sprite.dispatch(&mut frame));
+        renderer.commit_frame(frame);
+        sprite.destroy(&mut renderer);
+    }
+
+    #[test]
+    fn generator_shaders_compile() {
+        let Some(mut renderer) = headless_renderer("generator_shaders_compile") else {
+            return;
+        };
+
+        let world = VoxelWorld::new(&mut renderer);
+        let region = Region::new(IVec3::ZERO, IVec3::splat(16));
+        for path in [SPHERE_SHADER, CUBE_SHADER] {
+            let params = SpriteParams::new(region.min, region.size().as_uvec3(), 1);
+            Sprite::new(&mut renderer, &world, path, params).unwrap().destroy(&mut renderer);
+        }
+        let terrain = TerrainRegion { params: TerrainParams::default(), region };
+        Sprite::new(&mut renderer, &world, TERRAIN_SHADER, terrain).unwrap().destroy(&mut renderer);
+        let scene = SdfScene::new().union(Primitive::new(Shape::Sphere { radius: 4.0 }, Transform::from_position(Vec3::splat(8.0)), 1));
+        Sprite::new(&mut renderer, &world, SDF_SHADER, scene).unwrap().destroy(&mut renderer);
+    }
+
+    #[test]
+    fn missing_shader() {
+        let Some(mut renderer) = headless_renderer("missing_shader") else {
+            return;
+        };
+
+        let world = VoxelWorld::new(&mut renderer);
+        let params = SpriteParams::new(IVec3::ZERO, UVec3::ONE, 1);
+        assert!(Sprite::new(&mut renderer, &world, "src/shaders/missing.wgsl", params).is_err());
+    }
+}
@@ -0,0 +1,318 @@
+//! Procedural terrain: a height map of domain warped fBm, carved by caves,
+//! with surface materials picked per biome.
+//!
+//! Generation runs on the CPU with [`TerrainGenerator`] or on the GPU with a [`Sprite`](crate::sprite::Sprite) of [`TerrainRegion`],
+//! both deterministic for a given seed.
+
+use glam::{IVec3, Vec3};
+
+use crate::{
+    noise::{fbm, perlin}, palette::{Material, Palette}, sprite::SpriteUniform, voxel_world::{ChunkMap, Region, VoxelWorld}
+};
+
+pub const TERRAIN_SHADER: &str = "src/shaders/compute_terrain.wgsl";
+
+/// Palette entries written by the generator
+pub const STONE: u32 = 1;
+pub const DIRT: u32 = 2;
+pub const GRASS: u32 = 3;
+pub const SAND: u32 = 4;
+pub const SNOW: u32 = 5;
+
+/// Seeds of the different noises are derived from the terrain seed
+const WARP_SEED_OFFSET: u32 = 1000;
+const CAVE_SEED_OFFSET: u32 = 2000;
+const BIOME_SEED_OFFSET: u32 = 3000;
+
+/// Generation settings, laid out as the `TerrainParams` uniform of the terrain shader.
+/// Frequencies are in cycles per voxel, heights and distances in voxels.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct TerrainParams {
+    pub seed: u32,
+    pub octaves: u32,
+    pub frequency: f32,
+    pub lacunarity: f32,
+    pub gain: f32,
+    pub warp_strength: f32,
+    pub base_height: f32,
+    pub height_scale: f32,
+    pub sea_level: f32,
+    pub snow_height: f32,
+    pub cave_frequency: f32,
+    /// Width of the cave tunnels in noise units, 0 disables caves
+    pub cave_threshold: f32,
+    pub biome_frequency: f32,
+    _padding: [u32; 3],
+}
+
+impl Default for TerrainParams {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            octaves: 5,
+            frequency: 0.02,
+            lacunarity: 2.0,
+            gain: 0.5,
+            warp_strength: 8.0,
+            base_height: 16.0,
+            height_scale: 12.0,
+            sea_level: 10.0,
+            snow_height: 24.0,
+            cave_frequency: 0.06,
+            cave_threshold: 0.1,
+            biome_frequency: 0.005,
+            _padding: [0; 3],
+        }
+    }
+}
+
+impl TerrainParams {
+    pub fn with_seed(seed: u32) -> Self {
+        Self {
+            seed,
+            ..Default::default()
+        }
+    }
+}
+
+/// Laid out as the `Terrain` uniform of the terrain shader
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+struct TerrainUniform {
+    origin: [i32; 4],
+    size: [u32; 4],
+    params: TerrainParams,
+}
+
+/// Terrain generated on the GPU over a region.
+/// Only the allocated chunks are filled, and the CPU copy is only updated by [`VoxelWorld::read_back`].
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TerrainRegion {
+    pub params: TerrainParams,
+    pub region: Region,
+}
+
+impl SpriteUniform for TerrainRegion {
+    const LABEL: &'static str = "Terrain";
+
+    fn region(&self) -> Region {
+        self.region
+    }
+
+    fn contents(&self) -> Vec<u8> {
+        let min = self.region.min;
+        let size = self.region.size().max(IVec3::ZERO).as_uvec3();
+        bytemuck::bytes_of(&TerrainUniform {
+            origin: [min.x, min.y, min.z, 0],
+            size: [size.x, size.y, size.z, 0],
+            params: self.params,
+        }).to_vec()
+    }
+}
+
+/// Generates terrain on the CPU
+pub struct TerrainGenerator {
+    params: TerrainParams,
+}
+
+impl TerrainGenerator {
+    pub fn new(params: TerrainParams) -> Self {
+        Self {
+            params,
+        }
+    }
+
+    /// Height of the terrain surface in a column
+    pub fn height(&self, x: i32, z: i32) -> f32 {
+        let params = &self.params;
+        let p = Vec3::new(x as f32, 0.0, z as f32) * params.frequency;
+        let warp_seed = params.seed.wrapping_add(WARP_SEED_OFFSET);
+        let warp = Vec3::new(
+            fbm(p + Vec3::new(5.2, 0.0, 1.3), warp_seed, 2, params.lacunarity, params.gain),
+            0.0,
+            fbm(p + Vec3::new(1.7, 0.0, 9.2), warp_seed, 2, params.lacunarity, params.gain),
+        );
+        let warped = p + warp * params.warp_strength * params.frequency;
+        params.base_height + params.height_scale * fbm(warped, params.seed, params.octaves, params.lacunarity, params.gain)
+    }
+
+    /// Columns where the low frequency biome noise is high are deserts
+    fn is_desert(&self, x: i32, z: i32) -> bool {
+        let p = Vec3::new(x as f32, 0.0, z as f32) * self.params.biome_frequency;
+        perlin(p + Vec3::new(0.5, 0.5, 0.5), self.params.seed.wrapping_add(BIOME_SEED_OFFSET)) > 0.15
+    }
+
+    /// Caves are the intersection of the zero sets of two noises, which gives tunnels
+    fn is_cave(&self, coord: IVec3) -> bool {
+        if self.params.cave_threshold <= 0.0 {
+            return false;
+        }
+        let p = coord.as_vec3() * self.params.cave_frequency;
+        let seed = self.params.seed.wrapping_add(CAVE_SEED_OFFSET);
+        perlin(p, seed).abs() < self.params.cave_threshold
+            && perlin(p + Vec3::new(31.7, 17.3, 5.9), seed.wrapping_add(1)).abs() < self.params.cave_threshold
+    }
+
+    /// Material of a voxel, given the surface height of its column
+    fn material(&self, coord: IVec3, surface: i32, desert: bool) -> u32 {
+        if coord.y > surface {
+            return 0;
+        }
+        // a thin crust is kept so caves don't riddle the surface
+        if coord.y < surface - 2 && self.is_cave(coord) {
+            return 0;
+        }
+
+        let depth = surface - coord.y;
+        let beach = surface as f32 <= self.params.sea_level + 1.0;
+        if (beach || desert) && depth < 4 {
+            SAND
+        }
+        else if depth == 0 && surface as f32 >= self.params.snow_height {
+            SNOW
+        }
+        else if depth == 0 {
+            GRASS
+        }
+        else if depth < 4 {
+            DIRT
+        }
+        else {
+            STONE
+        }
+    }
+
+    pub fn voxel_at(&self, coord: &IVec3) -> u32 {
+        let surface = self.height(coord.x, coord.z).floor() as i32;
+        self.material(*coord, surface, self.is_desert(coord.x, coord.z))
+    }
+
+    /// Calls `set` for every voxel of the region, the height map is computed once per column
+    fn for_each_voxel<F: FnMut(IVec3, u32)>(&self, region: &Region, mut set: F) {
+        for z in region.min.z..region.max.z {
+            for x in region.min.x..region.max.x {
+                let surface = self.height(x, z).floor() as i32;
+                let desert = self.is_desert(x, z);
+                for y in region.min.y..region.max.y {
+                    let coord = IVec3::new(x, y, z);
+                    set(coord, self.material(coord, surface, desert));
+                }
+            }
+        }
+    }
+
+    pub fn generate_chunks(&self, chunks: &mut ChunkMap, region: &Region) {
+        self.for_each_voxel(region, |coord, value| chunks.set_voxel_at(value, &coord));
+    }
+
+    /// Fills the region of the world and sets the terrain materials in its palette
+    pub fn generate(&self, world: &mut VoxelWorld, region: &Region) {
+        set_materials(world.palette_mut());
+        self.for_each_voxel(region, |coord, value| world.set_voxel_at(value, &coord));
+    }
+}
+
+/// Colors of the terrain materials
+pub fn set_materials(palette: &mut Palette) {
+    palette.set(STONE, Material::new([0.45, 0.45, 0.5]));
+    palette.set(DIRT, Material::new([0.45, 0.3, 0.18]));
+    palette.set(GRASS, Material::new([0.3, 0.6, 0.2]));
+    palette.set(SAND, Material::new([0.85, 0.78, 0.5]));
+    palette.set(SNOW, Material::new([0.95, 0.95, 1.0]));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sprite::Sprite, test_utils::storage_renderer};
+
+    fn generate(params: TerrainParams) -> ChunkMap {
+        let mut chunks = ChunkMap::new();
+        TerrainGenerator::new(params).generate_chunks(&mut chunks, &Region::new(IVec3::new(-16, -16, -16), IVec3::splat(48)));
+        chunks
+    }
+
+    #[test]
+    fn deterministic_per_seed() {
+        let a = generate(TerrainParams::with_seed(1));
+        let b = generate(TerrainParams::with_seed(1));
+        let c = generate(TerrainParams::with_seed(2));
+
+        let voxels = |chunks: &ChunkMap| {
+            let mut voxels: Vec<_> = chunks.iter().map(|(coord, chunk)| (coord.to_array(), chunk.voxels().to_vec())).collect();
+            voxels.sort();
+            voxels
+        };
+        assert_eq!(voxels(&a), voxels(&b));
+        assert_ne!(voxels(&a), voxels(&c));
+    }
+
+    #[test]
+    fn surface_follows_the_height_map() {
+        let params = TerrainParams {
+            cave_threshold: 0.0,
+            ..TerrainParams::with_seed(3)
+        };
+        let generator = TerrainGenerator::new(params);
+        for (x, z) in [(0, 0), (13, -7), (40, 25)] {
+            let surface = generator.height(x, z).floor() as i32;
+            assert_ne!(generator.voxel_at(&IVec3::new(x, surface, z)), 0);
+            assert_eq!(generator.voxel_at(&IVec3::new(x, surface + 1, z)), 0);
+            assert_eq!(generator.voxel_at(&IVec3::new(x, surface - 10, z)), STONE);
+        }
+    }
+
+    #[test]
+    fn caves_carve_the_ground() {
+        let solid = |params: TerrainParams| {
+            generate(params).iter().map(|(_, chunk)| chunk.voxels().iter().filter(|voxel| **voxel != 0).count()).sum::<usize>()
+        };
+        let without_caves = solid(TerrainParams {
+            cave_threshold: 0.0,
+            ..TerrainParams::with_seed(4)
+        });
+        assert!(solid(TerrainParams::with_seed(4)) < without_caves);
+    }
+
+    #[test]
+    fn biomes_pick_surface_materials() {
+        let chunks = generate(TerrainParams {
+            biome_frequency: 0.05,
+            ..TerrainParams::with_seed(5)
+        });
+        let has = |material: u32| chunks.iter().any(|(_, chunk)| chunk.voxels().contains(&material));
+        assert!(has(STONE) && has(DIRT) && has(GRASS) && has(SAND));
+    }
+
+    #[test]
+    fn gpu_matches_cpu() {
+        let Some(mut renderer) = storage_renderer("gpu_matches_cpu") else {
+            return;
+        };
+
+        let params = TerrainParams::with_seed(6);
+        let region = Region::new(IVec3::new(-8, 0, 4), IVec3::new(24, 32, 20));
+        let mut world = VoxelWorld::new(&mut renderer);
+        world.allocate_region(region.min, region.max);
+        world.update_texture(&mut renderer);
+
+        let mut sprite = Sprite::new(&mut renderer, &world, TERRAIN_SHADER, TerrainRegion { params, region }).unwrap();
+        let mut frame = renderer.begin_frame().expect("Headless frames are always available");
+        assert!(sprite.dispatch(&mut frame));
+        renderer.commit_frame(frame);
+        world.read_back(&renderer).unwrap();
+        sprite.destroy(&mut renderer);
+
+        let generator = TerrainGenerator::new(params);
+        // the noise is computed in single precision on both sides, but with different rounding
+        let mismatches = region.iter().filter(|coord| world.get_voxel_at(coord) != generator.voxel_at(coord)).count();
+        assert!(mismatches * 100 < region.volume(), "{} voxels differ", mismatches);
+    }
+
+    #[test]
+    fn uniform_layout() {
+        assert_eq!(std::mem::size_of::<TerrainParams>() % 16, 0);
+        assert_eq!(std::mem::size_of::<TerrainUniform>(), 32 + std::mem::size_of::<TerrainParams>());
+    }
+}
@@ -0,0 +1,38 @@
+//! Fixtures shared by the tests needing an adapter.
+//! Tests fail when the fixture isn't available on the machine,
+//! set `OCTO_SKIP_GPU_TESTS=1` to skip them instead on machines without a suitable adapter.
+
+use crate::renderer_context::{RendererContext, Resolution};
+
+/// The tests render into their own textures, the frame target is never read
+const RESOLUTION: Resolution = Resolution {
+    width: 1,
+    height: 1,
+};
+
+/// Returns None when GPU tests are skipped, fails the test otherwise
+fn unavailable(test: &str, reason: &str) -> Option<RendererContext> {
+    if std::env::var_os("OCTO_SKIP_GPU_TESTS").is_none() {
+        panic!("{}: {}, set OCTO_SKIP_GPU_TESTS=1 to skip the GPU tests", test, reason);
+    }
+    eprintln!("skipping {}: {}", test, reason);
+    None
+}
+
+/// Headless renderer, or None when there is no adapter and GPU tests are skipped
+pub fn headless_renderer(test: &str) -> Option<RendererContext> {
+    match pollster::block_on(RendererContext::new_headless(RESOLUTION)) {
+        Ok(renderer) => Some(renderer),
+        Err(e) => unavailable(test, &e.to_string()),
+    }
+}
+
+/// Headless renderer whose compute shaders can write the whole world texture.
+/// Not available on wgpu's GL backend, which binds 3D storage textures as a single layer.
+pub fn storage_renderer(test: &str) -> Option<RendererContext> {
+    let renderer = headless_renderer(test)?;
+    if renderer.adapter_info().backend == wgpu::Backend::Gl {
+        return unavailable(test, "3D storage textures are not supported by the GL backend");
+    }
+    Some(renderer)
+}
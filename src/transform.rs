@@ -1,5 +1,6 @@
 use glam::{Mat3, Mat4, Quat, Vec3};
 
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct Transform {
     pub position: Vec3,
     pub rotation: Quat,
@@ -0,0 +1,724 @@
+//! MagicaVoxel `.vox` import and export.
+//!
+//! MagicaVoxel is Z up while the engine is Y up: a voxel at `(x, y, z)` in the file
+//! is placed at `(x, z, -y - 1)` in the world, which keeps the models unmirrored.
+//! Voxel values are palette indices, 0 being empty.
+
+use std::{collections::HashMap, path::Path};
+
+use glam::{IVec3, UVec3};
+
+use crate::{palette::{Palette, PALETTE_SIZE}, voxel_world::{Chunk, ChunkMap, VoxelWorld, CHUNK_SIZE}};
+
+const MAGIC: &[u8; 4] = b"VOX ";
+const VERSION: u32 = 150;
+/// Maximum size of a model side
+pub const MAX_MODEL_SIZE: i32 = 256;
+
+#[derive(Debug, thiserror::Error)]
+pub enum VoxError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("Not a .vox file")]
+    InvalidMagic,
+    #[error("Unexpected end of file")]
+    UnexpectedEof,
+    #[error("Missing {0} chunk")]
+    MissingChunk(&'static str),
+    #[error("Invalid scene graph: {0}")]
+    InvalidSceneGraph(String),
+    #[error("Voxel value {0} doesn't fit in a palette index")]
+    ValueOutOfRange(u32),
+}
+
+/// Voxels of a model, positions relative to the model corner in file coordinates
+#[derive(Debug, Clone, PartialEq)]
+pub struct VoxModel {
+    pub size: UVec3,
+    pub voxels: Vec<(UVec3, u8)>,
+}
+
+/// Rotation with axis aligned rows, as stored in the `_r` byte of transform nodes
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Rotation {
+    rows: [IVec3; 3],
+}
+
+impl Rotation {
+    const IDENTITY: Rotation = Rotation {
+        rows: [IVec3::X, IVec3::Y, IVec3::Z],
+    };
+
+    fn from_byte(byte: u8) -> Result<Self, VoxError> {
+        let first = (byte & 3) as usize;
+        let second = ((byte >> 2) & 3) as usize;
+        if first > 2 || second > 2 || first == second {
+            return Err(VoxError::InvalidSceneGraph(format!("invalid rotation {}", byte)));
+        }
+        let third = 3 - first - second;
+
+        let mut rows = [IVec3::ZERO; 3];
+        for (row, (column, sign_bit)) in [(first, 4), (second, 5), (third, 6)].into_iter().enumerate() {
+            rows[row][column] = if byte & (1 << sign_bit) != 0 { -1 } else { 1 };
+        }
+        Ok(Self { rows })
+    }
+
+    fn to_byte(self) -> u8 {
+        let column = |row: IVec3| (0..3).find(|i| row[*i] != 0).unwrap_or(0) as u8;
+        let mut byte = column(self.rows[0]) | column(self.rows[1]) << 2;
+        for (row, sign_bit) in self.rows.iter().zip([4, 5, 6]) {
+            if row.min_element() < 0 {
+                byte |= 1 << sign_bit;
+            }
+        }
+        byte
+    }
+
+    fn apply(&self, v: IVec3) -> IVec3 {
+        IVec3::new(self.rows[0].dot(v), self.rows[1].dot(v), self.rows[2].dot(v))
+    }
+
+    fn then(&self, child: &Rotation) -> Rotation {
+        // rows of self * child
+        let columns = [
+            self.apply(IVec3::new(child.rows[0].x, child.rows[1].x, child.rows[2].x)),
+            self.apply(IVec3::new(child.rows[0].y, child.rows[1].y, child.rows[2].y)),
+            self.apply(IVec3::new(child.rows[0].z, child.rows[1].z, child.rows[2].z)),
+        ];
+        Rotation {
+            rows: std::array::from_fn(|row| IVec3::new(columns[0][row], columns[1][row], columns[2][row])),
+        }
+    }
+}
+
+/// Placement of a model in the scene, in file coordinates
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Placement {
+    rotation: Rotation,
+    translation: IVec3,
+}
+
+impl Placement {
+    const IDENTITY: Placement = Placement {
+        rotation: Rotation::IDENTITY,
+        translation: IVec3::ZERO,
+    };
+
+    fn then(&self, child: &Placement) -> Placement {
+        Placement {
+            rotation: self.rotation.then(&child.rotation),
+            translation: self.rotation.apply(child.translation) + self.translation,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VoxInstance {
+    pub model: usize,
+    placement: Placement,
+}
+
+/// Content of a `.vox` file: models, where the scene graph places them, and the palette.
+/// Files without palette get the default grey ramp, not MagicaVoxel's default palette.
+#[derive(Debug, Clone)]
+pub struct VoxScene {
+    pub models: Vec<VoxModel>,
+    pub instances: Vec<VoxInstance>,
+    pub palette: Palette,
+}
+
+enum Node {
+    Transform { child: i32, placement: Placement },
+    Group { children: Vec<i32> },
+    Shape { models: Vec<i32> },
+}
+
+/// Chunk id, content and children
+type RawChunk<'a> = ([u8; 4], &'a [u8], &'a [u8]);
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8], VoxError> {
+        if self.data.len() < count {
+            return Err(VoxError::UnexpectedEof);
+        }
+        let (bytes, rest) = self.data.split_at(count);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, VoxError> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn i32(&mut self) -> Result<i32, VoxError> {
+        Ok(self.u32()? as i32)
+    }
+
+    fn string(&mut self) -> Result<String, VoxError> {
+        let len = self.u32()? as usize;
+        Ok(String::from_utf8_lossy(self.bytes(len)?).into_owned())
+    }
+
+    fn dict(&mut self) -> Result<HashMap<String, String>, VoxError> {
+        let count = self.u32()?;
+        let mut dict = HashMap::new();
+        for _ in 0..count {
+            let key = self.string()?;
+            let value = self.string()?;
+            dict.insert(key, value);
+        }
+        Ok(dict)
+    }
+
+    fn chunk(&mut self) -> Result<RawChunk<'a>, VoxError> {
+        let id = self.bytes(4)?;
+        let content_size = self.u32()? as usize;
+        let children_size = self.u32()? as usize;
+        let content = self.bytes(content_size)?;
+        let children = self.bytes(children_size)?;
+        Ok(([id[0], id[1], id[2], id[3]], content, children))
+    }
+}
+
+fn parse_placement(frame: &HashMap<String, String>) -> Result<Placement, VoxError> {
+    let rotation = match frame.get("_r") {
+        Some(r) => Rotation::from_byte(
+            r.parse().map_err(|_| VoxError::InvalidSceneGraph(format!("invalid rotation {}", r)))?
+        )?,
+        None => Rotation::IDENTITY,
+    };
+    let translation = match frame.get("_t") {
+        Some(t) => {
+            let values = t
+                .split_whitespace()
+                .map(|value| value.parse::<i32>())
+                .collect::<Result<Vec<_>, _>>()
+                .ok()
+                .filter(|values| values.len() == 3)
+                .ok_or_else(|| VoxError::InvalidSceneGraph(format!("invalid translation {}", t)))?;
+            IVec3::new(values[0], values[1], values[2])
+        }
+        None => IVec3::ZERO,
+    };
+
+    Ok(Placement { rotation, translation })
+}
+
+impl VoxScene {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, VoxError> {
+        VoxScene::parse(&std::fs::read(path)?)
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self, VoxError> {
+        let mut reader = Reader { data };
+        if reader.bytes(4)? != MAGIC {
+            return Err(VoxError::InvalidMagic);
+        }
+        let _version = reader.u32()?;
+
+        let (id, _, children) = reader.chunk()?;
+        if &id != b"MAIN" {
+            return Err(VoxError::MissingChunk("MAIN"));
+        }
+
+        let mut sizes = vec![];
+        let mut models = vec![];
+        let mut nodes = HashMap::new();
+        let mut palette = None;
+
+        let mut reader = Reader { data: children };
+        while !reader.data.is_empty() {
+            let (id, content, _) = reader.chunk()?;
+            let mut content = Reader { data: content };
+            match &id {
+                b"SIZE" => sizes.push(UVec3::new(content.u32()?, content.u32()?, content.u32()?)),
+                b"XYZI" => {
+                    let size = *sizes.get(models.len()).ok_or(VoxError::MissingChunk("SIZE"))?;
+                    let count = content.u32()?;
+                    // the count isn't trusted for the allocation, each voxel takes 4 bytes
+                    if count as usize > content.data.len() / 4 {
+                        return Err(VoxError::UnexpectedEof);
+                    }
+                    let mut voxels = Vec::with_capacity(count as usize);
+                    for _ in 0..count {
+                        let voxel = content.bytes(4)?;
+                        voxels.push((UVec3::new(voxel[0] as u32, voxel[1] as u32, voxel[2] as u32), voxel[3]));
+                    }
+                    models.push(VoxModel { size, voxels });
+                }
+                b"RGBA" => {
+                    let mut colors = Palette::new().colors();
+                    // the color i of the chunk is the palette index i + 1
+                    for color in colors.iter_mut().skip(1) {
+                        let bytes = content.bytes(4)?;
+                        *color = [bytes[0], bytes[1], bytes[2], bytes[3]];
+                    }
+                    palette = Some(colors);
+                }
+                b"nTRN" => {
+                    let node_id = content.i32()?;
+                    let _attributes = content.dict()?;
+                    let child = content.i32()?;
+                    let _reserved = content.i32()?;
+                    let _layer = content.i32()?;
+                    let frame_count = content.u32()?;
+                    let placement = match frame_count {
+                        0 => Placement::IDENTITY,
+                        _ => parse_placement(&content.dict()?)?,
+                    };
+                    nodes.insert(node_id, Node::Transform { child, placement });
+                }
+                b"nGRP" => {
+                    let node_id = content.i32()?;
+                    let _attributes = content.dict()?;
+                    let count = content.u32()?;
+                    let children = (0..count).map(|_| content.i32()).collect::<Result<_, _>>()?;
+                    nodes.insert(node_id, Node::Group { children });
+                }
+                b"nSHP" => {
+                    let node_id = content.i32()?;
+                    let _attributes = content.dict()?;
+                    let count = content.u32()?;
+                    let mut shape_models = vec![];
+                    for _ in 0..count {
+                        shape_models.push(content.i32()?);
+                        let _model_attributes = content.dict()?;
+                    }
+                    nodes.insert(node_id, Node::Shape { models: shape_models });
+                }
+                // PACK, layers, materials, cameras...
+                _ => {}
+            }
+        }
+
+        if models.is_empty() && sizes.is_empty() {
+            return Err(VoxError::MissingChunk("SIZE"));
+        }
+
+        let instances = if nodes.is_empty() {
+            // files without scene graph have the corner of their models at the origin
+            models
+                .iter()
+                .enumerate()
+                .map(|(model, VoxModel { size, .. })| VoxInstance {
+                    model,
+                    placement: Placement {
+                        rotation: Rotation::IDENTITY,
+                        translation: (*size / 2).as_ivec3(),
+                    },
+                })
+                .collect()
+        }
+        else {
+            let mut instances = vec![];
+            VoxScene::collect_instances(&nodes, 0, Placement::IDENTITY, models.len(), &mut instances, 0)?;
+            instances
+        };
+
+        Ok(Self {
+            models,
+            instances,
+            palette: palette.map(|colors| Palette::from_colors(&colors)).unwrap_or_default(),
+        })
+    }
+
+    fn collect_instances(
+        nodes: &HashMap<i32, Node>,
+        node_id: i32,
+        parent: Placement,
+        model_count: usize,
+        instances: &mut Vec<VoxInstance>,
+        depth: usize
+    ) -> Result<(), VoxError> {
+        if depth > nodes.len() {
+            return Err(VoxError::InvalidSceneGraph("cycle between nodes".to_string()));
+        }
+        let node = nodes
+            .get(&node_id)
+            .ok_or_else(|| VoxError::InvalidSceneGraph(format!("missing node {}", node_id)))?;
+
+        match node {
+            Node::Transform { child, placement } => {
+                VoxScene::collect_instances(nodes, *child, parent.then(placement), model_count, instances, depth + 1)?;
+            }
+            Node::Group { children } => {
+                for child in children {
+                    VoxScene::collect_instances(nodes, *child, parent, model_count, instances, depth + 1)?;
+                }
+            }
+            Node::Shape { models } => {
+                for model in models {
+                    let model = usize::try_from(*model)
+                        .ok()
+                        .filter(|model| *model < model_count)
+                        .ok_or_else(|| VoxError::InvalidSceneGraph(format!("missing model {}", model)))?;
+                    // translations place the center of the model
+                    instances.push(VoxInstance {
+                        model,
+                        placement: parent,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Voxels of all the instances, in world coordinates
+    pub fn voxels(&self) -> impl Iterator<Item = (IVec3, u8)> + '_ {
+        self.instances.iter().flat_map(move |instance| {
+            let model = &self.models[instance.model];
+            let pivot = (model.size / 2).as_ivec3();
+            model.voxels.iter().map(move |(position, value)| {
+                let p = instance.placement.rotation.apply(position.as_ivec3() - pivot) + instance.placement.translation;
+                (file_to_world(p), *value)
+            })
+        })
+    }
+
+    /// Sets the voxels of the scene in the chunks, moved by `offset`
+    pub fn fill_chunks(&self, chunks: &mut ChunkMap, offset: IVec3) {
+        for (coord, value) in self.voxels() {
+            chunks.set_voxel_at(value as u32, &(coord + offset));
+        }
+    }
+
+    /// Sets the voxels of the scene in the world, moved by `offset`, and replaces the world palette
+    pub fn fill_world(&self, world: &mut VoxelWorld, offset: IVec3) {
+        world.set_palette(&self.palette);
+        for (coord, value) in self.voxels() {
+            world.set_voxel_at(value as u32, &(coord + offset));
+        }
+    }
+
+    /// Scene with the voxels of the chunks, split in models of at most [`MAX_MODEL_SIZE`] per side
+    pub fn from_chunks(chunks: &ChunkMap, palette: Palette) -> Result<Self, VoxError> {
+        let mut blocks: HashMap<IVec3, Vec<(IVec3, u8)>> = HashMap::new();
+        for (chunk_coord, chunk) in chunks.iter() {
+            for (index, value) in chunk.voxels().iter().enumerate() {
+                if *value == 0 {
+                    continue;
+                }
+                let value = u8::try_from(*value).map_err(|_| VoxError::ValueOutOfRange(*value))?;
+                let coord = *chunk_coord * CHUNK_SIZE + Chunk::local_coord(index);
+                let position = world_to_file(coord);
+                blocks
+                    .entry(position.div_euclid(IVec3::splat(MAX_MODEL_SIZE)))
+                    .or_default()
+                    .push((position, value));
+            }
+        }
+
+        // files need at least a model
+        if blocks.is_empty() {
+            return Ok(Self {
+                models: vec![VoxModel { size: UVec3::ONE, voxels: vec![] }],
+                instances: vec![VoxInstance { model: 0, placement: Placement::IDENTITY }],
+                palette,
+            });
+        }
+
+        let mut block_coords: Vec<_> = blocks.keys().copied().collect();
+        block_coords.sort_by_key(|coord| (coord.z, coord.y, coord.x));
+
+        let mut models = vec![];
+        let mut instances = vec![];
+        for block_coord in block_coords {
+            let voxels = &blocks[&block_coord];
+            let min = voxels.iter().fold(IVec3::MAX, |min, (position, _)| min.min(*position));
+            let max = voxels.iter().fold(IVec3::MIN, |max, (position, _)| max.max(*position));
+            let size = (max - min + IVec3::ONE).as_uvec3();
+
+            instances.push(VoxInstance {
+                model: models.len(),
+                placement: Placement {
+                    rotation: Rotation::IDENTITY,
+                    translation: min + (size / 2).as_ivec3(),
+                },
+            });
+            models.push(VoxModel {
+                size,
+                voxels: voxels
+                    .iter()
+                    .map(|(position, value)| ((*position - min).as_uvec3(), *value))
+                    .collect(),
+            });
+        }
+
+        Ok(Self {
+            models,
+            instances,
+            palette,
+        })
+    }
+
+    pub fn from_world(world: &VoxelWorld) -> Result<Self, VoxError> {
+        VoxScene::from_chunks(world.chunks(), world.palette().clone())
+    }
+
+    /// Encodes the scene, with a root transform and group holding a transform and shape per instance
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut children = vec![];
+        for model in &self.models {
+            let mut size = vec![];
+            for value in model.size.to_array() {
+                write_u32(&mut size, value);
+            }
+            write_chunk(&mut children, b"SIZE", &size);
+
+            let mut xyzi = vec![];
+            write_u32(&mut xyzi, model.voxels.len() as u32);
+            for (position, value) in &model.voxels {
+                xyzi.extend_from_slice(&[position.x as u8, position.y as u8, position.z as u8, *value]);
+            }
+            write_chunk(&mut children, b"XYZI", &xyzi);
+        }
+
+        write_chunk(&mut children, b"nTRN", &transform_node(0, 1, &Placement::IDENTITY));
+        let mut group = vec![];
+        write_u32(&mut group, 1);
+        write_dict(&mut group, &[]);
+        write_u32(&mut group, self.instances.len() as u32);
+        for i in 0..self.instances.len() {
+            write_u32(&mut group, 2 + 2 * i as u32);
+        }
+        write_chunk(&mut children, b"nGRP", &group);
+
+        for (i, instance) in self.instances.iter().enumerate() {
+            let transform_id = 2 + 2 * i as u32;
+            write_chunk(&mut children, b"nTRN", &transform_node(transform_id, transform_id + 1, &instance.placement));
+
+            let mut shape = vec![];
+            write_u32(&mut shape, transform_id + 1);
+            write_dict(&mut shape, &[]);
+            write_u32(&mut shape, 1);
+            write_u32(&mut shape, instance.model as u32);
+            write_dict(&mut shape, &[]);
+            write_chunk(&mut children, b"nSHP", &shape);
+        }
+
+        let mut rgba = vec![];
+        let colors = self.palette.colors();
+        for i in 1..=PALETTE_SIZE {
+            rgba.extend_from_slice(&colors.get(i).copied().unwrap_or([0; 4]));
+        }
+        write_chunk(&mut children, b"RGBA", &rgba);
+
+        let mut bytes = MAGIC.to_vec();
+        write_u32(&mut bytes, VERSION);
+        bytes.extend_from_slice(b"MAIN");
+        write_u32(&mut bytes, 0);
+        write_u32(&mut bytes, children.len() as u32);
+        bytes.extend_from_slice(&children);
+        bytes
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), VoxError> {
+        std::fs::write(path, self.to_bytes())?;
+        Ok(())
+    }
+}
+
+fn file_to_world(p: IVec3) -> IVec3 {
+    IVec3::new(p.x, p.z, -p.y - 1)
+}
+
+fn world_to_file(p: IVec3) -> IVec3 {
+    IVec3::new(p.x, -p.z - 1, p.y)
+}
+
+fn write_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn write_string(out: &mut Vec<u8>, value: &str) {
+    write_u32(out, value.len() as u32);
+    out.extend_from_slice(value.as_bytes());
+}
+
+fn write_dict(out: &mut Vec<u8>, entries: &[(&str, String)]) {
+    write_u32(out, entries.len() as u32);
+    for (key, value) in entries {
+        write_string(out, key);
+        write_string(out, value);
+    }
+}
+
+fn write_chunk(out: &mut Vec<u8>, id: &[u8; 4], content: &[u8]) {
+    out.extend_from_slice(id);
+    write_u32(out, content.len() as u32);
+    write_u32(out, 0);
+    out.extend_from_slice(content);
+}
+
+fn transform_node(node_id: u32, child: u32, placement: &Placement) -> Vec<u8> {
+    let mut content = vec![];
+    write_u32(&mut content, node_id);
+    write_dict(&mut content, &[]);
+    write_u32(&mut content, child);
+    write_u32(&mut content, u32::MAX);
+    write_u32(&mut content, 0);
+    write_u32(&mut content, 1);
+    let t = placement.translation;
+    let mut frame = vec![("_t", format!("{} {} {}", t.x, t.y, t.z))];
+    if placement.rotation != Rotation::IDENTITY {
+        frame.push(("_r", placement.rotation.to_byte().to_string()));
+    }
+    write_dict(&mut content, &frame);
+    content
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted_voxels(chunks: &ChunkMap) -> Vec<(IVec3, u32)> {
+        let mut voxels: Vec<_> = chunks
+            .iter()
+            .flat_map(|(chunk_coord, chunk)| {
+                chunk.voxels().iter().enumerate().filter(|(_, value)| **value != 0).map(move |(index, value)| {
+                    (*chunk_coord * CHUNK_SIZE + Chunk::local_coord(index), *value)
+                })
+            })
+            .collect();
+        voxels.sort_by_key(|(coord, _)| coord.to_array());
+        voxels
+    }
+
+    fn round_trip(chunks: &ChunkMap) -> ChunkMap {
+        let scene = VoxScene::from_chunks(chunks, Palette::new()).unwrap();
+        let parsed = VoxScene::parse(&scene.to_bytes()).unwrap();
+        let mut result = ChunkMap::new();
+        parsed.fill_chunks(&mut result, IVec3::ZERO);
+        result
+    }
+
+    #[test]
+    fn world_round_trip() {
+        let mut chunks = ChunkMap::new();
+        chunks.set_voxel_at(1, &IVec3::new(0, 0, 0));
+        chunks.set_voxel_at(2, &IVec3::new(-3, 7, 12));
+        chunks.set_voxel_at(255, &IVec3::new(20, -5, -1));
+
+        assert_eq!(sorted_voxels(&round_trip(&chunks)), sorted_voxels(&chunks));
+    }
+
+    #[test]
+    fn large_worlds_are_split_in_models() {
+        let mut chunks = ChunkMap::new();
+        chunks.set_voxel_at(1, &IVec3::new(0, 0, 0));
+        chunks.set_voxel_at(2, &IVec3::new(300, 1, 0));
+
+        let scene = VoxScene::from_chunks(&chunks, Palette::new()).unwrap();
+        assert_eq!(scene.models.len(), 2);
+        assert_eq!(sorted_voxels(&round_trip(&chunks)), sorted_voxels(&chunks));
+    }
+
+    #[test]
+    fn palette_round_trip() {
+        let mut colors = Palette::new().colors();
+        colors[1] = [255, 0, 0, 255];
+        colors[255] = [1, 2, 3, 4];
+        let palette = Palette::from_colors(&colors);
+
+        let scene = VoxScene::from_chunks(&ChunkMap::new(), palette.clone()).unwrap();
+        let parsed = VoxScene::parse(&scene.to_bytes()).unwrap();
+        assert_eq!(parsed.palette, palette);
+    }
+
+    #[test]
+    fn values_out_of_the_palette_are_rejected() {
+        let mut chunks = ChunkMap::new();
+        chunks.set_voxel_at(256, &IVec3::ZERO);
+        assert!(matches!(
+            VoxScene::from_chunks(&chunks, Palette::new()),
+            Err(VoxError::ValueOutOfRange(256))
+        ));
+    }
+
+    fn file(children: &[u8]) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        write_u32(&mut bytes, VERSION);
+        bytes.extend_from_slice(b"MAIN");
+        write_u32(&mut bytes, 0);
+        write_u32(&mut bytes, children.len() as u32);
+        bytes.extend_from_slice(children);
+        bytes
+    }
+
+    fn model_chunks(out: &mut Vec<u8>, size: [u32; 3], voxels: &[[u8; 4]]) {
+        let mut content = vec![];
+        for value in size {
+            write_u32(&mut content, value);
+        }
+        write_chunk(out, b"SIZE", &content);
+
+        let mut content = vec![];
+        write_u32(&mut content, voxels.len() as u32);
+        for voxel in voxels {
+            content.extend_from_slice(voxel);
+        }
+        write_chunk(out, b"XYZI", &content);
+    }
+
+    #[test]
+    fn file_without_scene_graph() {
+        let mut children = vec![];
+        model_chunks(&mut children, [3, 3, 3], &[[0, 0, 0, 4], [2, 1, 0, 5]]);
+
+        let scene = VoxScene::parse(&file(&children)).unwrap();
+        assert_eq!(scene.palette, Palette::new());
+        let voxels: Vec<_> = scene.voxels().collect();
+        assert_eq!(voxels, vec![(IVec3::new(0, 0, -1), 4), (IVec3::new(2, 0, -2), 5)]);
+    }
+
+    #[test]
+    fn rotated_and_translated_instances() {
+        let mut children = vec![];
+        model_chunks(&mut children, [2, 1, 1], &[[0, 0, 0, 1], [1, 0, 0, 2]]);
+
+        let placement = Placement {
+            // quarter turn around z, x goes to y
+            rotation: Rotation::from_byte(0b0001_0001).unwrap(),
+            translation: IVec3::new(10, 0, 0),
+        };
+        assert_eq!(placement.rotation.apply(IVec3::X), IVec3::Y);
+        assert_eq!(Rotation::from_byte(placement.rotation.to_byte()).unwrap(), placement.rotation);
+
+        write_chunk(&mut children, b"nTRN", &transform_node(0, 1, &placement));
+        let mut shape = vec![];
+        write_u32(&mut shape, 1);
+        write_dict(&mut shape, &[]);
+        write_u32(&mut shape, 1);
+        write_u32(&mut shape, 0);
+        write_dict(&mut shape, &[]);
+        write_chunk(&mut children, b"nSHP", &shape);
+
+        let scene = VoxScene::parse(&file(&children)).unwrap();
+        let voxels: Vec<_> = scene.voxels().collect();
+        // pivot at (1, 0, 0), file positions (10, -1, 0) and (10, 0, 0)
+        assert_eq!(voxels, vec![(IVec3::new(10, 0, 0), 1), (IVec3::new(10, 0, -1), 2)]);
+    }
+
+    #[test]
+    fn invalid_files() {
+        assert!(matches!(VoxScene::parse(b"RIFF"), Err(VoxError::InvalidMagic)));
+        assert!(matches!(VoxScene::parse(MAGIC), Err(VoxError::UnexpectedEof)));
+
+        let mut children = vec![];
+        write_chunk(&mut children, b"XYZI", &[0; 4]);
+        assert!(matches!(VoxScene::parse(&file(&children)), Err(VoxError::MissingChunk("SIZE"))));
+
+        // a voxel count larger than the chunk
+        let mut children = vec![];
+        model_chunks(&mut children, [1, 1, 1], &[[0, 0, 0, 1]]);
+        let count_offset = children.len() - 8;
+        children[count_offset..count_offset + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(VoxScene::parse(&file(&children)), Err(VoxError::UnexpectedEof)));
+    }
+}
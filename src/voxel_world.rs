@@ -1,56 +1,470 @@
-use glam::UVec3;
+use std::{collections::{HashMap, HashSet}, path::Path};
+
+use glam::{IVec3, UVec3};
 use wgpu::Extent3d;
 
-use crate::renderer_context::{TextureHandle, RendererContext};
+use crate::{brickmap::Brickmap, octree::SparseVoxelOctree, ray::{raycast_voxels, Ray, RayHit}, renderer_context::{BufferHandle, TextureHandle, RendererContext, RendererContextError}, palette::Palette, world_file::{self, WorldFileError}};
+
+/// Size of a chunk side in voxels
+pub const CHUNK_SIZE: i32 = 16;
+const CHUNK_VOLUME: usize = (CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE) as usize;
+/// Number of chunk slots per side of the GPU atlas
+const ATLAS_SLOTS: u32 = 8;
+const ATLAS_CAPACITY: u32 = ATLAS_SLOTS * ATLAS_SLOTS * ATLAS_SLOTS;
+/// Maximum number of chunks per side of the window visible from the GPU
+const TABLE_SIZE: i32 = 16;
+const TABLE_VOLUME: usize = (TABLE_SIZE * TABLE_SIZE * TABLE_SIZE) as usize;
+/// Marks a chunk table entry without chunk
+const EMPTY_SLOT: u32 = u32::MAX;
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ChunkTableHeader {
+    origin: [i32; 4],
+    size: [u32; 4],
+}
+
+/// Box of voxels, min included and max excluded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub min: IVec3,
+    pub max: IVec3,
+}
+
+impl Region {
+    pub fn new(min: IVec3, max: IVec3) -> Self {
+        Self {
+            min,
+            max,
+        }
+    }
+
+    pub fn from_voxel(coord: IVec3) -> Self {
+        Self {
+            min: coord,
+            max: coord + IVec3::ONE,
+        }
+    }
+
+    /// Smallest region containing all the voxels, `None` without voxels
+    pub fn bounding<I: IntoIterator<Item = IVec3>>(coords: I) -> Option<Region> {
+        coords
+            .into_iter()
+            .map(Region::from_voxel)
+            .reduce(|region, voxel| region.union(&voxel))
+    }
+
+    pub fn size(&self) -> IVec3 {
+        self.max - self.min
+    }
+
+    pub fn volume(&self) -> usize {
+        let size = self.size().max(IVec3::ZERO);
+        (size.x * size.y * size.z) as usize
+    }
+
+    pub fn contains(&self, coord: &IVec3) -> bool {
+        coord.cmpge(self.min).all() && coord.cmplt(self.max).all()
+    }
+
+    pub fn union(&self, other: &Region) -> Region {
+        Region::new(self.min.min(other.min), self.max.max(other.max))
+    }
+
+    /// Coordinates of the voxels in the region, x first, then y, then z
+    pub fn iter(&self) -> impl Iterator<Item = IVec3> {
+        let Region { min, max } = *self;
+        (min.z..max.z).flat_map(move |z| {
+            (min.y..max.y).flat_map(move |y| {
+                (min.x..max.x).map(move |x| IVec3::new(x, y, z))
+            })
+        })
+    }
+}
+
+/// Modified voxels of a chunk since the last upload
+struct DirtyChunk {
+    /// Box around the modified voxels, uploaded in a single copy
+    region: Region,
+    /// One bit per voxel, in [`Chunk::voxels`] order
+    voxels: Vec<u64>,
+}
+
+impl DirtyChunk {
+    fn new(region: Region, all: bool) -> Self {
+        Self {
+            region,
+            voxels: vec![if all { u64::MAX } else { 0 }; CHUNK_VOLUME / 64],
+        }
+    }
+
+    fn contains(&self, index: usize) -> bool {
+        self.voxels[index / 64] & (1 << (index % 64)) != 0
+    }
+}
+
+/// Modified voxels since the last upload, with a box per chunk in chunk local coordinates
+#[derive(Default)]
+pub struct DirtyRegions {
+    chunks: HashMap<IVec3, DirtyChunk>,
+}
+
+impl DirtyRegions {
+    fn chunk_region() -> Region {
+        Region::new(IVec3::ZERO, IVec3::splat(CHUNK_SIZE))
+    }
+
+    pub fn mark_voxel(&mut self, coord: &IVec3) {
+        let local = ChunkMap::local_coord(coord);
+        let dirty = self.chunks
+            .entry(ChunkMap::chunk_coord(coord))
+            .and_modify(|dirty| dirty.region = dirty.region.union(&Region::from_voxel(local)))
+            .or_insert_with(|| DirtyChunk::new(Region::from_voxel(local), false));
+        let index = Chunk::index(local);
+        dirty.voxels[index / 64] |= 1 << (index % 64);
+    }
+
+    pub fn mark_chunk(&mut self, chunk_coord: IVec3) {
+        self.chunks.insert(chunk_coord, DirtyChunk::new(DirtyRegions::chunk_region(), true));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    pub fn take(&mut self) -> HashMap<IVec3, Region> {
+        std::mem::take(&mut self.chunks)
+            .into_iter()
+            .map(|(chunk_coord, dirty)| (chunk_coord, dirty.region))
+            .collect()
+    }
+}
+
+#[derive(Clone)]
+pub struct Chunk {
+    voxels: Vec<u32>,
+}
+
+impl Chunk {
+    fn new() -> Self {
+        Self {
+            voxels: vec![0; CHUNK_VOLUME],
+        }
+    }
+
+    /// Voxels are stored x first, then y, then z, as in the GPU textures
+    fn index(local: IVec3) -> usize {
+        (local.x + local.y * CHUNK_SIZE + local.z * CHUNK_SIZE * CHUNK_SIZE) as usize
+    }
+
+    /// Local coordinates of the voxel at an index of [`Chunk::voxels`]
+    pub fn local_coord(index: usize) -> IVec3 {
+        let index = index as i32;
+        IVec3::new(index % CHUNK_SIZE, index / CHUNK_SIZE % CHUNK_SIZE, index / (CHUNK_SIZE * CHUNK_SIZE))
+    }
+
+    pub fn voxels(&self) -> &[u32] {
+        &self.voxels
+    }
+
+    pub fn voxels_mut(&mut self) -> &mut [u32] {
+        &mut self.voxels
+    }
+
+    pub fn get(&self, local: IVec3) -> u32 {
+        self.voxels[Chunk::index(local)]
+    }
+
+    pub fn set(&mut self, local: IVec3, value: u32) {
+        self.voxels[Chunk::index(local)] = value;
+    }
+
+    /// Copies the voxels of a region given in chunk local coordinates, x first, then y, then z
+    pub fn extract(&self, local: &Region) -> Vec<u32> {
+        local.iter().map(|coord| self.get(coord)).collect()
+    }
+}
+
+/// CPU voxel data of an unbounded world, made of chunks allocated on demand
+#[derive(Default, Clone)]
+pub struct ChunkMap {
+    chunks: HashMap<IVec3, Chunk>,
+}
+
+impl ChunkMap {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn chunk_coord(coord: &IVec3) -> IVec3 {
+        coord.div_euclid(IVec3::splat(CHUNK_SIZE))
+    }
 
-const WORLD_SIZE: usize = 32; 
+    pub fn local_coord(coord: &IVec3) -> IVec3 {
+        coord.rem_euclid(IVec3::splat(CHUNK_SIZE))
+    }
+
+    pub fn set_voxel_at(&mut self, value: u32, coord: &IVec3) {
+        let chunk_coord = ChunkMap::chunk_coord(coord);
+        let local = ChunkMap::local_coord(coord);
+        match self.chunks.get_mut(&chunk_coord) {
+            Some(chunk) => chunk.set(local, value),
+            None if value != 0 => self.allocate_chunk(chunk_coord).set(local, value),
+            None => {}
+        }
+    }
+
+    pub fn get_voxel_at(&self, coord: &IVec3) -> u32 {
+        self.chunks
+            .get(&ChunkMap::chunk_coord(coord))
+            .map(|chunk| chunk.get(ChunkMap::local_coord(coord)))
+            .unwrap_or(0)
+    }
+
+    /// First solid voxel along the ray, up to `max_distance` in units of the ray direction
+    pub fn raycast(&self, ray: &Ray, max_distance: f32) -> Option<RayHit> {
+        raycast_voxels(ray, max_distance, |coord| self.get_voxel_at(coord))
+    }
+
+    /// Allocates the chunk at the given chunk coordinates if it does not exist yet
+    pub fn allocate_chunk(&mut self, chunk_coord: IVec3) -> &mut Chunk {
+        self.chunks.entry(chunk_coord).or_insert_with(Chunk::new)
+    }
+
+    /// Allocates all the chunks overlapping the voxels between min (included) and max (excluded)
+    pub fn allocate_region(&mut self, min: IVec3, max: IVec3) {
+        let min_chunk = ChunkMap::chunk_coord(&min);
+        let max_chunk = ChunkMap::chunk_coord(&(max - IVec3::ONE));
+        for z in min_chunk.z..=max_chunk.z {
+            for y in min_chunk.y..=max_chunk.y {
+                for x in min_chunk.x..=max_chunk.x {
+                    self.allocate_chunk(IVec3::new(x, y, z));
+                }
+            }
+        }
+    }
 
+    pub fn chunk_mut(&mut self, chunk_coord: IVec3) -> Option<&mut Chunk> {
+        self.chunks.get_mut(&chunk_coord)
+    }
+
+    pub fn chunk(&self, chunk_coord: IVec3) -> Option<&Chunk> {
+        self.chunks.get(&chunk_coord)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&IVec3, &Chunk)> {
+        self.chunks.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.chunks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    pub fn contains(&self, chunk_coord: &IVec3) -> bool {
+        self.chunks.contains_key(chunk_coord)
+    }
+
+    /// Voxel bounds of the allocated chunks, min included and max excluded
+    pub fn bounds(&self) -> Option<(IVec3, IVec3)> {
+        let mut coords = self.chunks.keys();
+        let first = *coords.next()?;
+        let (min, max) = coords.fold((first, first), |(min, max), coord| (min.min(*coord), max.max(*coord)));
+        Some((min * CHUNK_SIZE, (max + IVec3::ONE) * CHUNK_SIZE))
+    }
+}
+
+/// How the world is laid out on the GPU for the raymarcher
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WorldRepresentation {
+    /// Dense chunks in a 3D texture atlas
+    Grid,
+    /// Sparse voxel octree in a storage buffer, built from the CPU data only
+    Octree,
+    /// Coarse grid of 8³ bricks in a storage buffer, built from the CPU data only
+    Brickmap,
+}
+
+/// Unbounded voxel world and its GPU representation.
+///
+/// On the GPU, chunks are stored in the slots of a 3D texture atlas,
+/// and a chunk table maps the chunk coordinates of a window around the chunks to their slot.
+/// Only the CPU chunks are unbounded: the atlas holds 512 chunks and the window spans 16³ chunks,
+/// the chunks beyond them aren't rendered.
+/// The world can also be represented by an acceleration structure in a storage buffer, see [`WorldRepresentation`].
+///
+/// The CPU chunks are the reference copy. Only the regions modified on the CPU are uploaded,
+/// so voxels written on the GPU by compute shaders elsewhere in the atlas are kept,
+/// and [`VoxelWorld::read_back`] copies them into the CPU chunks.
 pub struct VoxelWorld {
-    data: [[[u32; WORLD_SIZE]; WORLD_SIZE]; WORLD_SIZE],
-    size: UVec3,
-    texture: TextureHandle
+    chunks: ChunkMap,
+    representation: WorldRepresentation,
+    slots: HashMap<IVec3, u32>,
+    free_slots: Vec<u32>,
+    /// Set while chunks are missing a slot, so that the full atlas is reported once
+    atlas_full: bool,
+    texture: TextureHandle,
+    chunk_table: BufferHandle,
+    palette: Palette,
+    dirty: DirtyRegions,
+    structure_buffer: Option<BufferHandle>,
+    structure_buffer_size: usize,
+    octree: Option<SparseVoxelOctree>,
+    brickmap: Option<Brickmap>,
+    /// Chunks modified since the acceleration structure was updated
+    structure_dirty: HashSet<IVec3>,
+    /// Set when the acceleration structure has to be built again from all the chunks
+    structure_outdated: bool,
 }
 
 impl VoxelWorld {
     pub fn new(renderer: &mut RendererContext) -> Self {
-        let data = [[[0; WORLD_SIZE]; WORLD_SIZE]; WORLD_SIZE];
-
+        let atlas_size = ATLAS_SLOTS * CHUNK_SIZE as u32;
         let texture = renderer.new_texture(
             &wgpu::TextureDescriptor {
-                label: None,
+                label: Some("Voxel atlas"),
                 size: wgpu::Extent3d {
-                    width: WORLD_SIZE as u32,
-                    height: WORLD_SIZE as u32,
-                    depth_or_array_layers: WORLD_SIZE as u32,
+                    width: atlas_size,
+                    height: atlas_size,
+                    depth_or_array_layers: atlas_size,
                 },
                 mip_level_count: 1,
                 sample_count: 1,
                 dimension: wgpu::TextureDimension::D3,
                 format: wgpu::TextureFormat::R32Uint,
-                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::STORAGE_BINDING,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::COPY_DST
+                    | wgpu::TextureUsages::COPY_SRC
+                    | wgpu::TextureUsages::STORAGE_BINDING,
                 view_formats: &[],
             }
         );
 
-        VoxelWorld { 
-            data,
-            size: UVec3::new(WORLD_SIZE as u32, WORLD_SIZE as u32, WORLD_SIZE as u32),
+        let chunk_table = renderer.new_buffer(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Chunk table"),
+                contents: &VoxelWorld::chunk_table_bytes(IVec3::ZERO, UVec3::ZERO, &[]),
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            }
+        );
+
+        VoxelWorld {
+            chunks: ChunkMap::new(),
+            representation: WorldRepresentation::Grid,
+            slots: HashMap::new(),
+            // popped from the back, slot 0 first
+            free_slots: (0..ATLAS_CAPACITY).rev().collect(),
+            atlas_full: false,
             texture,
+            chunk_table,
+            palette: Palette::new(),
+            dirty: DirtyRegions::default(),
+            structure_buffer: None,
+            structure_buffer_size: 0,
+            octree: None,
+            brickmap: None,
+            structure_dirty: HashSet::new(),
+            structure_outdated: true,
+        }
+    }
+
+    pub fn representation(&self) -> WorldRepresentation {
+        self.representation
+    }
+
+    pub fn set_representation(&mut self, representation: WorldRepresentation) {
+        self.representation = representation;
+        self.octree = None;
+        self.brickmap = None;
+        self.structure_outdated = true;
+    }
+
+    pub fn set_voxel_at(&mut self, value: u32, coord: &IVec3) {
+        self.chunks.set_voxel_at(value, coord);
+        self.dirty.mark_voxel(coord);
+        self.structure_dirty.insert(ChunkMap::chunk_coord(coord));
+    }
+
+    pub fn get_voxel_at(&self, coord: &IVec3) -> u32 {
+        self.chunks.get_voxel_at(coord)
+    }
+
+    /// First solid voxel along the ray in the CPU copy of the world, see [`VoxelWorld::read_back`]
+    pub fn raycast(&self, ray: &Ray, max_distance: f32) -> Option<RayHit> {
+        self.chunks.raycast(ray, max_distance)
+    }
+
+    /// Allocates all the chunks overlapping the voxels between min (included) and max (excluded)
+    pub fn allocate_region(&mut self, min: IVec3, max: IVec3) {
+        // new chunks are empty, the acceleration structure only changes if they extend the world
+        self.chunks.allocate_region(min, max);
+    }
+
+    pub fn chunks(&self) -> &ChunkMap {
+        &self.chunks
+    }
+
+    /// Replaces all the chunks of the world
+    pub fn set_chunks(&mut self, chunks: ChunkMap) {
+        self.chunks = chunks;
+        for (coord, _) in self.chunks.iter() {
+            self.dirty.mark_chunk(*coord);
         }
+        self.structure_outdated = true;
+    }
+
+    pub fn palette(&self) -> &Palette {
+        &self.palette
     }
 
-    pub fn set_voxel_at(&mut self, value: u32, coord: &UVec3) {
-        self.data[coord.x as usize][coord.y as usize][coord.z as usize] = value; 
+    pub fn palette_mut(&mut self) -> &mut Palette {
+        &mut self.palette
     }
 
-    pub fn get_size(&self) -> &UVec3 {
-        &self.size
+    /// Copies the materials of a palette
+    pub fn set_palette(&mut self, palette: &Palette) {
+        self.palette.set_materials(palette.materials());
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), WorldFileError> {
+        world_file::save(path, &self.chunks, &self.palette)
+    }
+
+    /// Replaces the world by the one saved in the file, left untouched on error
+    pub fn load<P: AsRef<Path>>(&mut self, path: P) -> Result<(), WorldFileError> {
+        let (chunks, palette) = world_file::load(path)?;
+        self.set_chunks(chunks);
+        self.set_palette(&palette);
+        Ok(())
     }
 
     pub fn get_texture(&self) -> TextureHandle {
         self.texture
     }
 
+    pub fn get_chunk_table(&self) -> BufferHandle {
+        self.chunk_table
+    }
+
+    /// Acceleration structure buffer, available once the world has been updated
+    /// with the octree or brickmap representation
+    pub fn get_structure_buffer(&self) -> Option<BufferHandle> {
+        self.structure_buffer
+    }
+
+    /// WGSL declarations of the constants the shaders share with the world, see [`crate::shader`]
+    pub fn shader_constants() -> String {
+        format!(
+            "const CHUNK_SIZE: i32 = {};\nconst ATLAS_SLOTS: u32 = {}u;\nconst EMPTY_SLOT: u32 = {:#x}u;\n",
+            CHUNK_SIZE,
+            ATLAS_SLOTS,
+            EMPTY_SLOT
+        )
+    }
+
     pub fn binding_type() -> wgpu::BindingType {
         wgpu::BindingType::Texture {
             sample_type: wgpu::TextureSampleType::Uint,
@@ -59,20 +473,414 @@ impl VoxelWorld {
         }
     }
 
-    pub fn update_texture(&self, renderer: &mut RendererContext) {
+    pub fn storage_binding_type() -> wgpu::BindingType {
+        wgpu::BindingType::StorageTexture {
+            access: wgpu::StorageTextureAccess::WriteOnly,
+            format: wgpu::TextureFormat::R32Uint,
+            view_dimension: wgpu::TextureViewDimension::D3,
+        }
+    }
+
+    pub fn chunk_table_binding_type() -> wgpu::BindingType {
+        wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only: true },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        }
+    }
+
+    pub fn structure_binding_type() -> wgpu::BindingType {
+        wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only: true },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        }
+    }
+
+    /// Origin of a slot in the atlas texture
+    fn slot_origin(slot: u32) -> wgpu::Origin3d {
+        let size = CHUNK_SIZE as u32;
+        wgpu::Origin3d {
+            x: (slot % ATLAS_SLOTS) * size,
+            y: (slot / ATLAS_SLOTS % ATLAS_SLOTS) * size,
+            z: (slot / (ATLAS_SLOTS * ATLAS_SLOTS)) * size,
+        }
+    }
+
+    fn chunk_table_bytes(origin: IVec3, size: UVec3, slots: &[u32]) -> Vec<u8> {
+        let header = ChunkTableHeader {
+            origin: [origin.x, origin.y, origin.z, 0],
+            size: [size.x, size.y, size.z, 0],
+        };
+        let mut table = vec![EMPTY_SLOT; TABLE_VOLUME];
+        table[..slots.len()].copy_from_slice(slots);
+
+        let mut bytes = bytemuck::bytes_of(&header).to_vec();
+        bytes.extend_from_slice(bytemuck::cast_slice(&table));
+        bytes
+    }
+
+    /// Gives a slot to the new chunks and releases the slots of the removed ones.
+    /// Returns whether the chunk table has to be updated.
+    fn sync_slots(&mut self) -> bool {
+        let mut changed = false;
+
+        let chunks = &self.chunks;
+        let free_slots = &mut self.free_slots;
+        self.slots.retain(|coord, slot| {
+            let keep = chunks.contains(coord);
+            if !keep {
+                free_slots.push(*slot);
+                changed = true;
+            }
+            keep
+        });
+
+        let mut unslotted = 0;
+        for (coord, _) in self.chunks.iter() {
+            if !self.slots.contains_key(coord) {
+                match self.free_slots.pop() {
+                    Some(slot) => {
+                        self.slots.insert(*coord, slot);
+                        self.dirty.mark_chunk(*coord);
+                        changed = true;
+                    }
+                    None => unslotted += 1,
+                }
+            }
+        }
+        if unslotted > 0 && !self.atlas_full {
+            log::warn!("Voxel atlas is full, {} chunks won't be rendered", unslotted);
+        }
+        self.atlas_full = unslotted > 0;
+
+        changed
+    }
+
+    /// Chunk table covering the allocated chunks, clamped to the maximum table size
+    fn build_chunk_table(&self) -> (IVec3, UVec3, Vec<u32>) {
+        let Some((min, max)) = self.chunks.bounds() else {
+            return (IVec3::ZERO, UVec3::ZERO, vec![]);
+        };
+        let origin = min / CHUNK_SIZE;
+        let full_size = (max - min) / CHUNK_SIZE;
+        let size = full_size.min(IVec3::splat(TABLE_SIZE));
+        if size != full_size {
+            log::warn!("Voxel world is larger than the chunk table, far chunks won't be rendered");
+        }
+
+        let mut table = vec![EMPTY_SLOT; (size.x * size.y * size.z) as usize];
+        for (coord, slot) in self.slots.iter() {
+            let table_coord = *coord - origin;
+            if table_coord.cmplt(size).all() {
+                let index = table_coord.x + table_coord.y * size.x + table_coord.z * size.x * size.y;
+                table[index as usize] = *slot;
+            }
+        }
+
+        (origin, size.as_uvec3(), table)
+    }
+
+    fn update_structure(&mut self, renderer: &mut RendererContext) {
+        if !self.structure_outdated && self.structure_dirty.is_empty() {
+            return;
+        }
+
+        let dirty = std::mem::take(&mut self.structure_dirty);
+        let bytes = match self.representation {
+            WorldRepresentation::Grid => return,
+            WorldRepresentation::Octree => {
+                // only the modified chunks are built again while the tree still covers the world
+                let updated = !self.structure_outdated
+                    && self.octree.as_mut().is_some_and(|octree| octree.update_chunks(&self.chunks, dirty));
+                if !updated {
+                    self.octree = Some(SparseVoxelOctree::build(&self.chunks));
+                }
+                self.octree.as_ref().map(SparseVoxelOctree::to_bytes).unwrap_or_default()
+            }
+            WorldRepresentation::Brickmap => {
+                let writes = match self.brickmap.as_mut() {
+                    Some(brickmap) if !self.structure_outdated => brickmap.update_chunks(&self.chunks, dirty),
+                    _ => None,
+                };
+                if writes.is_none() {
+                    self.brickmap = None;
+                }
+                let brickmap = self.brickmap.get_or_insert_with(|| Brickmap::build(&self.chunks));
+
+                // only the modified bricks are uploaded while the brickmap fits in the buffer
+                if let (Some(writes), Some(buffer)) = (writes, self.structure_buffer) {
+                    if brickmap.byte_size() <= self.structure_buffer_size {
+                        for write in writes {
+                            renderer.write_buffer(buffer, write.offset, &write.bytes);
+                        }
+                        return;
+                    }
+                }
+                brickmap.to_bytes()
+            }
+        };
+        match self.structure_buffer {
+            Some(buffer) if bytes.len() <= self.structure_buffer_size => {
+                renderer.update_buffer(buffer, &bytes);
+            }
+            _ => {
+                if let Some(buffer) = self.structure_buffer {
+                    renderer.destroy_buffer(buffer);
+                }
+                self.structure_buffer = Some(renderer.new_buffer(
+                    &wgpu::util::BufferInitDescriptor {
+                        label: Some("World structure"),
+                        contents: &bytes,
+                        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+                    }
+                ));
+                self.structure_buffer_size = bytes.len();
+            }
+        }
+        self.structure_outdated = false;
+    }
+
+    /// Copies the chunks resident in the atlas back into the CPU chunks, to keep what compute shaders wrote.
+    /// Voxels modified on the CPU and not uploaded yet keep their CPU value.
+    pub fn read_back(&mut self, renderer: &RendererContext) -> Result<(), RendererContextError> {
+        let Some(last_slot) = self.slots.values().max() else {
+            return Ok(());
+        };
+
+        // a single copy of the atlas layers holding the resident chunks
+        let atlas_size = ATLAS_SLOTS * CHUNK_SIZE as u32;
+        let layers = VoxelWorld::slot_origin(*last_slot).z + CHUNK_SIZE as u32;
+        let data = renderer.read_texture_region(
+            self.texture,
+            wgpu::Origin3d::ZERO,
+            Extent3d {
+                width: atlas_size,
+                height: atlas_size,
+                depth_or_array_layers: layers,
+            }
+        )?;
+        let atlas: &[u32] = bytemuck::cast_slice(&data);
+
+        for (coord, slot) in self.slots.iter() {
+            let Some(chunk) = self.chunks.chunk_mut(*coord) else {
+                continue;
+            };
+            let pending = self.dirty.chunks.get(coord);
+            let slot_origin = VoxelWorld::slot_origin(*slot);
+            for (index, local) in DirtyRegions::chunk_region().iter().enumerate() {
+                if pending.is_some_and(|dirty| dirty.contains(index)) {
+                    continue;
+                }
+                let texel = UVec3::new(slot_origin.x, slot_origin.y, slot_origin.z) + local.as_uvec3();
+                let value = atlas[(texel.x + texel.y * atlas_size + texel.z * atlas_size * atlas_size) as usize];
+                if chunk.get(local) != value {
+                    chunk.set(local, value);
+                    self.structure_dirty.insert(*coord);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether voxels modified on the CPU wait for the next upload
+    pub fn has_pending_edits(&self) -> bool {
+        !self.dirty.is_empty()
+    }
+
+    pub fn update_texture(&mut self, renderer: &mut RendererContext) {
+        self.palette.update_buffer(renderer);
+        // the atlas is kept in every representation, compute shaders write into it
+        self.update_structure(renderer);
+
+        if self.sync_slots() {
+            let (origin, size, table) = self.build_chunk_table();
+            renderer.update_buffer(self.chunk_table, &VoxelWorld::chunk_table_bytes(origin, size, &table));
+        }
+
+        // only the modified boxes are uploaded
+        for (coord, region) in self.dirty.take() {
+            let (Some(chunk), Some(slot)) = (self.chunks.chunk(coord), self.slots.get(&coord)) else {
+                continue;
+            };
+
+            let slot_origin = VoxelWorld::slot_origin(*slot);
+            let size = region.size().as_uvec3();
+            renderer.write_texture(
+                self.texture,
+                wgpu::Origin3d {
+                    x: slot_origin.x + region.min.x as u32,
+                    y: slot_origin.y + region.min.y as u32,
+                    z: slot_origin.z + region.min.z as u32,
+                },
+                bytemuck::cast_slice(&chunk.extract(&region)),
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(4 * size.x),
+                    rows_per_image: Some(size.y),
+                },
+                Extent3d {
+                    width: size.x,
+                    height: size.y,
+                    depth_or_array_layers: size.z,
+                }
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3;
+
+    use super::*;
+    use crate::test_utils::headless_renderer;
+
+    #[test]
+    fn chunk_and_local_coords() {
+        let coord = IVec3::new(17, -1, -16);
+        assert_eq!(ChunkMap::chunk_coord(&coord), IVec3::new(1, -1, -1));
+        assert_eq!(ChunkMap::local_coord(&coord), IVec3::new(1, 15, 0));
+    }
+
+    #[test]
+    fn chunks_allocated_on_demand() {
+        let mut chunks = ChunkMap::new();
+        chunks.set_voxel_at(0, &IVec3::new(100, 0, 0));
+        assert!(chunks.is_empty());
+
+        chunks.set_voxel_at(255, &IVec3::new(-1, 40, 3));
+        chunks.set_voxel_at(7, &IVec3::new(100, 0, 0));
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks.get_voxel_at(&IVec3::new(-1, 40, 3)), 255);
+        assert_eq!(chunks.get_voxel_at(&IVec3::new(100, 0, 0)), 7);
+        assert_eq!(chunks.get_voxel_at(&IVec3::new(5, 5, 5)), 0);
+        assert_eq!(chunks.bounds(), Some((IVec3::new(-16, 0, 0), IVec3::new(112, 48, 16))));
+    }
+
+    #[test]
+    fn dirty_voxels_are_merged_per_chunk() {
+        let mut dirty = DirtyRegions::default();
+        dirty.mark_voxel(&IVec3::new(1, 2, 3));
+        dirty.mark_voxel(&IVec3::new(4, 0, 3));
+        dirty.mark_voxel(&IVec3::new(-1, 0, 0));
+
+        assert_eq!(dirty.chunks[&IVec3::ZERO].region, Region::new(IVec3::new(1, 0, 3), IVec3::new(5, 3, 4)));
+        assert_eq!(dirty.chunks[&IVec3::new(-1, 0, 0)].region, Region::from_voxel(IVec3::new(15, 0, 0)));
+        // only the marked voxels of the boxes are modified
+        assert!(dirty.chunks[&IVec3::ZERO].contains(Chunk::index(IVec3::new(4, 0, 3))));
+        assert!(dirty.chunks[&IVec3::new(-1, 0, 0)].contains(Chunk::index(IVec3::new(15, 0, 0))));
+        assert!(!dirty.chunks[&IVec3::ZERO].contains(Chunk::index(IVec3::new(2, 1, 3))));
+    }
+
+    #[test]
+    fn extracting_a_region_from_a_chunk() {
+        let mut chunk = Chunk::new();
+        chunk.set(IVec3::new(1, 0, 0), 1);
+        chunk.set(IVec3::new(0, 1, 0), 2);
+        chunk.set(IVec3::new(1, 1, 1), 3);
+
+        let data = chunk.extract(&Region::new(IVec3::ZERO, IVec3::splat(2)));
+        assert_eq!(data, vec![0, 1, 2, 0, 0, 0, 0, 3]);
+    }
+
+    #[test]
+    fn gpu_writes_read_back() {
+        let Some(mut renderer) = headless_renderer("gpu_writes_read_back") else {
+            return;
+        };
+
+        let mut world = VoxelWorld::new(&mut renderer);
+        world.set_voxel_at(5, &IVec3::new(1, 2, 3));
+        world.update_texture(&mut renderer);
+
+        // a compute shader writing in the atlas
+        let slot_origin = VoxelWorld::slot_origin(world.slots[&IVec3::ZERO]);
         renderer.write_texture(
-            self.texture, 
-            bytemuck::bytes_of(&self.data), 
+            world.get_texture(),
+            wgpu::Origin3d {
+                x: slot_origin.x + 4,
+                y: slot_origin.y,
+                z: slot_origin.z,
+            },
+            bytemuck::bytes_of(&9u32),
             wgpu::ImageDataLayout {
                 offset: 0,
-                bytes_per_row: Some(4 * self.size.x),
-                rows_per_image: Some(self.size.y),
-            }, 
+                bytes_per_row: Some(4),
+                rows_per_image: Some(1),
+            },
             Extent3d {
-                width: self.size.x,
-                height: self.size.y,
-                depth_or_array_layers: self.size.z,
+                width: 1,
+                height: 1,
+                depth_or_array_layers: 1,
             }
         );
+        // pending CPU modifications, not uploaded yet, whose box covers the GPU write
+        world.set_voxel_at(7, &IVec3::new(0, 0, 1));
+        world.set_voxel_at(6, &IVec3::new(6, 0, 0));
+        assert!(world.has_pending_edits());
+
+        world.read_back(&renderer).unwrap();
+        assert_eq!(world.get_voxel_at(&IVec3::new(4, 0, 0)), 9);
+        assert_eq!(world.get_voxel_at(&IVec3::new(1, 2, 3)), 5);
+        assert_eq!(world.get_voxel_at(&IVec3::new(0, 0, 1)), 7);
+
+        // uploading the box keeps the GPU write
+        world.update_texture(&mut renderer);
+        assert!(!world.has_pending_edits());
+        world.read_back(&renderer).unwrap();
+        assert_eq!(world.get_voxel_at(&IVec3::new(4, 0, 0)), 9);
+        assert_eq!(world.get_voxel_at(&IVec3::new(6, 0, 0)), 6);
+    }
+
+    #[test]
+    fn atlas_is_kept_with_structures() {
+        let Some(mut renderer) = headless_renderer("atlas_is_kept_with_structures") else {
+            return;
+        };
+
+        for representation in [WorldRepresentation::Octree, WorldRepresentation::Brickmap] {
+            let mut world = VoxelWorld::new(&mut renderer);
+            world.set_representation(representation);
+            world.set_voxel_at(5, &IVec3::new(1, 2, 3));
+            world.update_texture(&mut renderer);
+            assert!(world.slots.contains_key(&IVec3::ZERO));
+            assert!(world.get_structure_buffer().is_some());
+
+            // compute shaders find the chunk in the atlas
+            world.set_voxel_at(0, &IVec3::new(1, 2, 3));
+            world.dirty.take();
+            world.read_back(&renderer).unwrap();
+            assert_eq!(world.get_voxel_at(&IVec3::new(1, 2, 3)), 5);
+        }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn allocating_a_region() {
+        let mut chunks = ChunkMap::new();
+        chunks.allocate_region(IVec3::ZERO, IVec3::splat(32));
+        assert_eq!(chunks.len(), 8);
+        chunks.allocate_region(IVec3::splat(-1), IVec3::ZERO);
+        assert_eq!(chunks.len(), 9);
+    }
+
+    #[test]
+    fn raycasting_across_chunks() {
+        let mut chunks = ChunkMap::new();
+        chunks.set_voxel_at(3, &IVec3::new(-20, 5, 40));
+        let ray = Ray::new(Vec3::new(0.5, 5.5, 40.5), Vec3::NEG_X);
+        let hit = chunks.raycast(&ray, 64.0).unwrap();
+        assert_eq!(hit.voxel, IVec3::new(-20, 5, 40));
+        assert_eq!(hit.normal, IVec3::X);
+        assert_eq!(hit.value, 3);
+        assert_eq!(chunks.raycast(&ray, 16.0), None);
+    }
+
+    #[test]
+    fn slot_origins() {
+        assert_eq!(VoxelWorld::slot_origin(0), wgpu::Origin3d::ZERO);
+        assert_eq!(VoxelWorld::slot_origin(9), wgpu::Origin3d { x: 16, y: 16, z: 0 });
+        assert_eq!(VoxelWorld::slot_origin(64), wgpu::Origin3d { x: 0, y: 0, z: 16 });
+    }
+}
@@ -0,0 +1,345 @@
+//! Native world format.
+//!
+//! All values are little endian, each section ends with the CRC32 of its content:
+//! - header: magic, version, chunk size, chunk count, bounds min and max (exclusive), palette length
+//! - palette: material per voxel value, as the 12 floats of [`Material`], RGBA colors in version 1
+//! - chunk table: per chunk, its coordinates, payload offset and length, and the CRC32 of its voxels
+//! - payload: voxels of each chunk as an independent zlib stream, without trailing checksum
+
+use std::{io::{Read, Write}, path::Path};
+
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+use glam::IVec3;
+
+use crate::{palette::{Material, Palette}, voxel_world::{ChunkMap, CHUNK_SIZE}};
+
+const MAGIC: &[u8; 4] = b"OCTW";
+pub const VERSION: u32 = 2;
+/// Oldest version that can still be loaded
+const MIN_VERSION: u32 = 1;
+const MATERIAL_SIZE: usize = std::mem::size_of::<Material>();
+const CHUNK_VOLUME: usize = (CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE) as usize;
+/// Size of the header in bytes, without its checksum
+const HEADER_SIZE: usize = 4 + 4 + 4 + 4 + 2 * 12 + 4;
+/// Size of a chunk table entry in bytes
+const TABLE_ENTRY_SIZE: usize = 3 * 4 + 8 + 4 + 4;
+
+#[derive(Debug, thiserror::Error)]
+pub enum WorldFileError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("Not a world file")]
+    InvalidMagic,
+    #[error("Unsupported world file version {0}, expected {MIN_VERSION} to {VERSION}")]
+    UnsupportedVersion(u32),
+    #[error("Chunks of size {0} are not supported, expected {CHUNK_SIZE}")]
+    UnsupportedChunkSize(u32),
+    #[error("Unexpected end of file")]
+    UnexpectedEof,
+    #[error("Corrupted {0}: checksum mismatch")]
+    ChecksumMismatch(String),
+    #[error("Corrupted chunk {0}: {1}")]
+    CorruptedChunk(IVec3, String),
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8], WorldFileError> {
+        if self.data.len() < count {
+            return Err(WorldFileError::UnexpectedEof);
+        }
+        let (bytes, rest) = self.data.split_at(count);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, WorldFileError> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn u64(&mut self) -> Result<u64, WorldFileError> {
+        Ok(self.u32()? as u64 | (self.u32()? as u64) << 32)
+    }
+
+    fn ivec3(&mut self) -> Result<IVec3, WorldFileError> {
+        Ok(IVec3::new(self.u32()? as i32, self.u32()? as i32, self.u32()? as i32))
+    }
+
+    /// Section of `len` bytes, followed by its checksum
+    fn section(&mut self, len: usize, name: &str) -> Result<Reader<'a>, WorldFileError> {
+        let data = self.bytes(len)?;
+        if self.u32()? != crc32fast::hash(data) {
+            return Err(WorldFileError::ChecksumMismatch(name.to_string()));
+        }
+        Ok(Reader { data })
+    }
+}
+
+fn write_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn write_ivec3(out: &mut Vec<u8>, value: IVec3) {
+    for component in value.to_array() {
+        write_u32(out, component as u32);
+    }
+}
+
+fn write_section(out: &mut Vec<u8>, section: &[u8]) {
+    out.extend_from_slice(section);
+    write_u32(out, crc32fast::hash(section));
+}
+
+pub fn to_bytes(chunks: &ChunkMap, palette: &Palette) -> Result<Vec<u8>, WorldFileError> {
+    // sorted for deterministic files
+    let mut coords: Vec<IVec3> = chunks.iter().map(|(coord, _)| *coord).collect();
+    coords.sort_by_key(|coord| (coord.z, coord.y, coord.x));
+    let (min, max) = chunks.bounds().unwrap_or((IVec3::ZERO, IVec3::ZERO));
+
+    let mut header = MAGIC.to_vec();
+    write_u32(&mut header, VERSION);
+    write_u32(&mut header, CHUNK_SIZE as u32);
+    write_u32(&mut header, coords.len() as u32);
+    write_ivec3(&mut header, min);
+    write_ivec3(&mut header, max);
+    write_u32(&mut header, palette.materials().len() as u32);
+
+    let mut table = vec![];
+    let mut payload = vec![];
+    for coord in coords {
+        let voxels: &[u8] = bytemuck::cast_slice(chunks.chunk(coord).unwrap().voxels());
+        let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+        encoder.write_all(voxels)?;
+        let compressed = encoder.finish()?;
+
+        write_ivec3(&mut table, coord);
+        table.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+        write_u32(&mut table, compressed.len() as u32);
+        write_u32(&mut table, crc32fast::hash(voxels));
+        payload.extend_from_slice(&compressed);
+    }
+
+    let mut bytes = vec![];
+    write_section(&mut bytes, &header);
+    let palette_section: Vec<u8> = bytemuck::cast_slice::<_, f32>(palette.materials())
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .collect();
+    write_section(&mut bytes, &palette_section);
+    write_section(&mut bytes, &table);
+    bytes.extend_from_slice(&payload);
+    Ok(bytes)
+}
+
+pub fn from_bytes(data: &[u8]) -> Result<(ChunkMap, Palette), WorldFileError> {
+    let mut reader = Reader { data };
+
+    if data.get(..4) != Some(MAGIC.as_slice()) {
+        return Err(WorldFileError::InvalidMagic);
+    }
+    let version = u32::from_le_bytes(data.get(4..8).ok_or(WorldFileError::UnexpectedEof)?.try_into().unwrap());
+    if !(MIN_VERSION..=VERSION).contains(&version) {
+        return Err(WorldFileError::UnsupportedVersion(version));
+    }
+
+    let mut header = reader.section(HEADER_SIZE, "header")?;
+    header.bytes(8)?;
+    let chunk_size = header.u32()?;
+    if chunk_size != CHUNK_SIZE as u32 {
+        return Err(WorldFileError::UnsupportedChunkSize(chunk_size));
+    }
+    let chunk_count = header.u32()? as usize;
+    let _bounds = (header.ivec3()?, header.ivec3()?);
+    let palette_len = header.u32()? as usize;
+
+    let palette = if version == 1 {
+        let mut palette_section = reader.section(palette_len * 4, "palette")?;
+        let mut colors = Palette::new().colors();
+        for color in colors.iter_mut().take(palette_len) {
+            color.copy_from_slice(palette_section.bytes(4)?);
+        }
+        Palette::from_colors(&colors)
+    }
+    else {
+        let palette_section = reader.section(palette_len * MATERIAL_SIZE, "palette")?;
+        let values: Vec<f32> = palette_section.data
+            .chunks_exact(4)
+            .map(|value| f32::from_le_bytes([value[0], value[1], value[2], value[3]]))
+            .collect();
+        Palette::from_materials(bytemuck::cast_slice::<f32, Material>(&values).to_vec())
+    };
+
+    let mut table = reader.section(chunk_count * TABLE_ENTRY_SIZE, "chunk table")?;
+    let payload = reader.data;
+
+    let mut chunks = ChunkMap::new();
+    for _ in 0..chunk_count {
+        let coord = table.ivec3()?;
+        let offset = table.u64()? as usize;
+        let len = table.u32()? as usize;
+        let checksum = table.u32()?;
+
+        let compressed = offset
+            .checked_add(len)
+            .and_then(|end| payload.get(offset..end))
+            .ok_or(WorldFileError::UnexpectedEof)?;
+        let mut voxels = Vec::with_capacity(CHUNK_VOLUME * 4);
+        // one byte past a chunk is enough to reject streams that inflate to more
+        ZlibDecoder::new(compressed)
+            .take(CHUNK_VOLUME as u64 * 4 + 1)
+            .read_to_end(&mut voxels)
+            .map_err(|e| WorldFileError::CorruptedChunk(coord, e.to_string()))?;
+        if voxels.len() != CHUNK_VOLUME * 4 {
+            return Err(WorldFileError::CorruptedChunk(coord, format!("{} bytes of voxels", voxels.len())));
+        }
+        if crc32fast::hash(&voxels) != checksum {
+            return Err(WorldFileError::ChecksumMismatch(format!("chunk {}", coord)));
+        }
+
+        let chunk = chunks.allocate_chunk(coord);
+        for (index, value) in voxels.chunks_exact(4).enumerate() {
+            chunk.voxels_mut()[index] = u32::from_le_bytes([value[0], value[1], value[2], value[3]]);
+        }
+    }
+
+    Ok((chunks, palette))
+}
+
+pub fn save<P: AsRef<Path>>(path: P, chunks: &ChunkMap, palette: &Palette) -> Result<(), WorldFileError> {
+    let bytes = to_bytes(chunks, palette)?;
+    // written next to the destination then renamed, an interrupted save keeps the previous file
+    let path = path.as_ref();
+    let temporary_path = path.with_extension("tmp");
+    std::fs::write(&temporary_path, bytes)?;
+    std::fs::rename(&temporary_path, path)?;
+    Ok(())
+}
+
+pub fn load<P: AsRef<Path>>(path: P) -> Result<(ChunkMap, Palette), WorldFileError> {
+    from_bytes(&std::fs::read(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::palette::PALETTE_SIZE;
+
+    use super::*;
+
+    fn world() -> ChunkMap {
+        let mut chunks = ChunkMap::new();
+        chunks.set_voxel_at(1, &IVec3::new(0, 0, 0));
+        chunks.set_voxel_at(42, &IVec3::new(-20, 3, 17));
+        chunks.set_voxel_at(u32::MAX, &IVec3::new(5, -40, 2));
+        chunks.allocate_region(IVec3::splat(64), IVec3::splat(65));
+        chunks
+    }
+
+    fn assert_same_chunks(a: &ChunkMap, b: &ChunkMap) {
+        assert_eq!(a.len(), b.len());
+        for (coord, chunk) in a.iter() {
+            assert_eq!(chunk.voxels(), b.chunk(*coord).unwrap().voxels(), "chunk {}", coord);
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let chunks = world();
+        let mut palette = Palette::new();
+        let mut material = Material::new([0.1, 0.2, 0.3]);
+        material.emission = [2.0, 1.0, 0.5];
+        material.metallic = 0.8;
+        material.transparency = 0.25;
+        palette.set(42, material);
+
+        let (loaded, loaded_palette) = from_bytes(&to_bytes(&chunks, &palette).unwrap()).unwrap();
+        assert_same_chunks(&chunks, &loaded);
+        assert_eq!(loaded_palette, palette);
+    }
+
+    #[test]
+    fn version_1_palettes_are_colors() {
+        let chunks = world();
+        let mut colors = Palette::new().colors();
+        colors[7] = [200, 100, 50, 255];
+
+        // version 1 file written by hand: same layout with a palette of RGBA colors
+        let v2 = to_bytes(&chunks, &Palette::new()).unwrap();
+        let mut header = v2[..HEADER_SIZE].to_vec();
+        header[4] = 1;
+        let mut bytes = vec![];
+        write_section(&mut bytes, &header);
+        write_section(&mut bytes, colors.as_flattened());
+        bytes.extend_from_slice(&v2[HEADER_SIZE + 4 + PALETTE_SIZE * MATERIAL_SIZE + 4..]);
+
+        let (loaded, palette) = from_bytes(&bytes).unwrap();
+        assert_same_chunks(&chunks, &loaded);
+        assert_eq!(palette.colors(), colors);
+    }
+
+    #[test]
+    fn payload_is_compressed() {
+        let chunks = world();
+        let bytes = to_bytes(&chunks, &Palette::new()).unwrap();
+        assert!(bytes.len() < chunks.len() * CHUNK_VOLUME);
+    }
+
+    #[test]
+    fn file_round_trip() {
+        let path = std::env::temp_dir().join(format!("octo_world_{}.octw", std::process::id()));
+        let chunks = world();
+        save(&path, &chunks, &Palette::new()).unwrap();
+        let (loaded, _) = load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_same_chunks(&chunks, &loaded);
+    }
+
+    #[test]
+    fn corruption_is_detected() {
+        let bytes = to_bytes(&world(), &Palette::new()).unwrap();
+
+        // byte flips anywhere in the file are caught
+        for i in (0..bytes.len()).step_by(7) {
+            let mut corrupted = bytes.clone();
+            corrupted[i] ^= 0x10;
+            assert!(from_bytes(&corrupted).is_err(), "flip at byte {} not detected", i);
+        }
+
+        assert!(matches!(from_bytes(&bytes[..bytes.len() - 1]), Err(WorldFileError::UnexpectedEof | WorldFileError::CorruptedChunk(..))));
+    }
+
+    #[test]
+    fn oversized_chunks_are_rejected() {
+        let mut chunks = ChunkMap::new();
+        chunks.allocate_chunk(IVec3::ZERO);
+        let bytes = to_bytes(&chunks, &Palette::new()).unwrap();
+
+        // the only chunk is replaced by a stream inflating to twice its size
+        let table_start = HEADER_SIZE + 4 + PALETTE_SIZE * MATERIAL_SIZE + 4;
+        let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+        encoder.write_all(&vec![0; CHUNK_VOLUME * 8]).unwrap();
+        let compressed = encoder.finish().unwrap();
+        let mut table = bytes[table_start..table_start + TABLE_ENTRY_SIZE].to_vec();
+        table[20..24].copy_from_slice(&(compressed.len() as u32).to_le_bytes());
+        let mut corrupted = bytes[..table_start].to_vec();
+        write_section(&mut corrupted, &table);
+        corrupted.extend_from_slice(&compressed);
+
+        assert!(matches!(
+            from_bytes(&corrupted),
+            Err(WorldFileError::CorruptedChunk(_, message)) if message == format!("{} bytes of voxels", CHUNK_VOLUME * 4 + 1)
+        ));
+    }
+
+    #[test]
+    fn invalid_headers() {
+        assert!(matches!(from_bytes(b"VOX \x96\0\0\0"), Err(WorldFileError::InvalidMagic)));
+
+        let mut bytes = to_bytes(&ChunkMap::new(), &Palette::new()).unwrap();
+        bytes[4] = 3;
+        assert!(matches!(from_bytes(&bytes), Err(WorldFileError::UnsupportedVersion(3))));
+    }
+}